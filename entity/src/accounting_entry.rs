@@ -2,15 +2,38 @@ use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 use chrono::{Utc,DateTime};
 use sea_orm::Set;
+use utoipa::ToSchema;
+
+// why the pill count changed, only doses and missed doses are things the patient did
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Serialize, ToSchema)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    // the count the schedule was created with
+    #[sea_orm(string_value = "initial")]
+    Initial,
+    // pills that were taken
+    #[sea_orm(string_value = "dose")]
+    Dose,
+    // a dose that was skipped, the count doesn't change
+    #[sea_orm(string_value = "missed")]
+    Missed,
+    // the count was set by hand, like after a refill or a recount
+    #[sea_orm(string_value = "correction")]
+    Correction,
+}
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "accounting")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
-    id: Uuid,
-    schedule_id: Uuid,
-    amount: i32,
-    timestamp:DateTime<Utc>,
+    pub id: Uuid,
+    pub schedule_id: Uuid,
+    pub amount: i32,
+    pub kind: EntryKind,
+    pub timestamp: DateTime<Utc>,
+    // the account that made the change, the owner or one of their caregivers
+    pub actor_id: Option<Uuid>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::{Condition, ConnectionTrait, Set};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::schedule;
+
+// ordered so a manage grant also satisfies a read requirement
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    EnumIter,
+    DeriveActiveEnum,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    #[sea_orm(string_value = "read")]
+    Read,
+    #[sea_orm(string_value = "manage")]
+    Manage,
+}
+
+// access one user (the owner) gives another (the caregiver) to their schedules
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "caregiver_grant")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub caregiver_id: Uuid,
+    pub permission: Permission,
+    // a single schedule, or every schedule the owner has now and later when empty
+    pub schedule_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    // invitations have no effect until the caregiver accepts them
+    pub accepted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::OwnerId",
+        to = "super::user::Column::Id"
+    )]
+    Owner,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::CaregiverId",
+        to = "super::user::Column::Id"
+    )]
+    Caregiver,
+    #[sea_orm(
+        belongs_to = "super::schedule::Entity",
+        from = "Column::ScheduleId",
+        to = "super::schedule::Column::Id"
+    )]
+    Schedule,
+}
+
+impl Related<super::schedule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Schedule.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            created_at: Set(Utc::now()),
+            accepted_at: Set(None),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+// which of an owner's schedules a user can see
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleScope {
+    All,
+    Only(Vec<Uuid>),
+}
+
+impl ScheduleScope {
+    pub fn condition(&self) -> Condition {
+        match self {
+            ScheduleScope::All => Condition::all(),
+            ScheduleScope::Only(ids) => {
+                Condition::all().add(schedule::Column::Id.is_in(ids.iter().cloned()))
+            }
+        }
+    }
+}
+
+impl Model {
+    async fn accepted<C: ConnectionTrait>(
+        owner_id: Uuid,
+        caregiver_id: Uuid,
+        db: &C,
+    ) -> Result<Vec<Model>, DbErr> {
+        Entity::find()
+            .filter(Column::OwnerId.eq(owner_id))
+            .filter(Column::CaregiverId.eq(caregiver_id))
+            .filter(Column::AcceptedAt.is_not_null())
+            .all(db)
+            .await
+    }
+
+    // the owner has full access, anyone else only what their accepted grants allow
+    pub async fn permission_for<C: ConnectionTrait>(
+        user_id: Uuid,
+        schedule: &schedule::Model,
+        db: &C,
+    ) -> Result<Option<Permission>, DbErr> {
+        if schedule.user_id == user_id {
+            return Ok(Some(Permission::Manage));
+        }
+        Ok(Model::accepted(schedule.user_id, user_id, db)
+            .await?
+            .into_iter()
+            .filter(|grant| grant.schedule_id.is_none_or(|id| id == schedule.id))
+            .map(|grant| grant.permission)
+            .max())
+    }
+
+    // permission over every schedule of the owner, needed to add schedules for them
+    pub async fn permission_for_all<C: ConnectionTrait>(
+        user_id: Uuid,
+        owner_id: Uuid,
+        db: &C,
+    ) -> Result<Option<Permission>, DbErr> {
+        if owner_id == user_id {
+            return Ok(Some(Permission::Manage));
+        }
+        Ok(Model::accepted(owner_id, user_id, db)
+            .await?
+            .into_iter()
+            .filter(|grant| grant.schedule_id.is_none())
+            .map(|grant| grant.permission)
+            .max())
+    }
+
+    // everyone who can see the schedule, the owner first
+    pub async fn audience<C: ConnectionTrait>(
+        schedule: &schedule::Model,
+        db: &C,
+    ) -> Result<Vec<Uuid>, DbErr> {
+        let grants = Entity::find()
+            .filter(Column::OwnerId.eq(schedule.user_id))
+            .filter(Column::AcceptedAt.is_not_null())
+            .filter(
+                Condition::any()
+                    .add(Column::ScheduleId.is_null())
+                    .add(Column::ScheduleId.eq(schedule.id)),
+            )
+            .all(db)
+            .await?;
+        let mut audience = vec![schedule.user_id];
+        for grant in grants {
+            if !audience.contains(&grant.caregiver_id) {
+                audience.push(grant.caregiver_id);
+            }
+        }
+        Ok(audience)
+    }
+
+    // None when the user can't see any of the owner's schedules
+    pub async fn visible_schedules(
+        user_id: Uuid,
+        owner_id: Uuid,
+        db: &DatabaseConnection,
+    ) -> Result<Option<ScheduleScope>, DbErr> {
+        if owner_id == user_id {
+            return Ok(Some(ScheduleScope::All));
+        }
+        let grants = Model::accepted(owner_id, user_id, db).await?;
+        if grants.is_empty() {
+            return Ok(None);
+        }
+        if grants.iter().any(|grant| grant.schedule_id.is_none()) {
+            return Ok(Some(ScheduleScope::All));
+        }
+        Ok(Some(ScheduleScope::Only(
+            grants
+                .iter()
+                .filter_map(|grant| grant.schedule_id)
+                .collect(),
+        )))
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::Expr, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+
+use crate::{token, user};
+
+static VERIFICATION_TOKEN_TTL: i64 = 60 * 60 * 24; // in seconds
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "email_verification")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // only the sha256 of the token is stored
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            expires_at: Set(Utc::now() + Duration::seconds(VERIFICATION_TOKEN_TTL)),
+            used_at: Set(None),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    // returns the plain token to mail to the user
+    pub async fn create(user_id: Uuid, db: &DatabaseConnection) -> Result<String, DbErr> {
+        let token = token::generate();
+        let mut model = ActiveModel::new();
+        model.user_id = Set(user_id);
+        model.token_hash = Set(token::hash(&token));
+        model.insert(db).await?;
+        Ok(token)
+    }
+
+    // marks the user's address as verified, returns false if the token can't be used
+    pub async fn redeem(token: &str, db: &DatabaseConnection) -> Result<bool, DbErr> {
+        let now = Utc::now();
+        let txn = db.begin().await?;
+        let verification = Entity::find()
+            .filter(Column::TokenHash.eq(token::hash(token)))
+            .filter(Column::UsedAt.is_null())
+            .filter(Column::ExpiresAt.gt(now))
+            .one(&txn)
+            .await?;
+        let verification = match verification {
+            Some(verification) => verification,
+            None => return Ok(false),
+        };
+
+        let claimed = Entity::update_many()
+            .col_expr(Column::UsedAt, Expr::value(now))
+            .filter(Column::Id.eq(verification.id))
+            .filter(Column::UsedAt.is_null())
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        if claimed == 0 {
+            return Ok(false);
+        }
+        user::Entity::update_many()
+            .col_expr(user::Column::EmailVerifiedAt, Expr::value(now))
+            .filter(user::Column::Id.eq(verification.user_id))
+            .filter(user::Column::EmailVerifiedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{Condition, QuerySelect, Set};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// finished exports are deleted after this long, failed ones forgotten
+static EXPORT_RETENTION: i64 = 60 * 60; // in seconds
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Serialize, ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "ready")]
+    Ready,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
+
+// a background export. the archive is kept in postgres, so any instance can serve the
+// status and the download of a job another one ran
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "export_job")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: ExportStatus,
+    // the zip, once ready
+    #[serde(skip_serializing, skip_deserializing)]
+    pub archive: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            status: Set(ExportStatus::Pending),
+            archive: Set(None),
+            created_at: Set(Utc::now()),
+            finished_at: Set(None),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    pub async fn start(user_id: Uuid, db: &DatabaseConnection) -> Result<Model, DbErr> {
+        let mut job = ActiveModel::new();
+        job.user_id = Set(user_id);
+        job.insert(db).await
+    }
+
+    // saves the archive, or marks the job failed without one
+    pub async fn finish(
+        id: Uuid,
+        archive: Option<Vec<u8>>,
+        db: &DatabaseConnection,
+    ) -> Result<(), DbErr> {
+        let status = match archive {
+            Some(_) => ExportStatus::Ready,
+            None => ExportStatus::Failed,
+        };
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(status))
+            .col_expr(Column::Archive, Expr::value(archive))
+            .col_expr(Column::FinishedAt, Expr::value(Utc::now()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    // the status of a job, only for the user who started it. the archive isn't loaded
+    pub async fn status(
+        id: Uuid,
+        user_id: Uuid,
+        db: &DatabaseConnection,
+    ) -> Result<Option<ExportStatus>, DbErr> {
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+        enum QueryAs {
+            Status,
+        }
+        Entity::find()
+            .select_only()
+            .column_as(Column::Status, QueryAs::Status)
+            .filter(Column::Id.eq(id))
+            .filter(Column::UserId.eq(user_id))
+            .into_values::<_, QueryAs>()
+            .one(db)
+            .await
+    }
+
+    pub async fn archive(id: Uuid, db: &DatabaseConnection) -> Result<Option<Vec<u8>>, DbErr> {
+        Ok(Entity::find_by_id(id)
+            .filter(Column::Status.eq(ExportStatus::Ready))
+            .one(db)
+            .await?
+            .and_then(|job| job.archive))
+    }
+
+    // deletes jobs finished longer than the retention ago, and pending ones that old, whose
+    // instance must have died. returns how many were deleted
+    pub async fn purge_expired(db: &DatabaseConnection) -> Result<u64, DbErr> {
+        let before = Utc::now() - Duration::seconds(EXPORT_RETENTION);
+        Ok(Entity::delete_many()
+            .filter(
+                Condition::any().add(Column::FinishedAt.lt(before)).add(
+                    Condition::all()
+                        .add(Column::Status.eq(ExportStatus::Pending))
+                        .add(Column::CreatedAt.lt(before)),
+                ),
+            )
+            .exec(db)
+            .await?
+            .rows_affected)
+    }
+}
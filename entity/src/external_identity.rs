@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::{ConnectionTrait, Set};
+use serde::{Deserialize, Serialize};
+
+// an account at an OpenID Connect provider that can log in as the user
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "external_identity")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // issuer and subject together identify the account, the subject alone is only unique per issuer
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            created_at: Set(Utc::now()),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    pub async fn find(
+        issuer: &str,
+        subject: &str,
+        db: &DatabaseConnection,
+    ) -> Result<Option<Model>, DbErr> {
+        Entity::find()
+            .filter(Column::Issuer.eq(issuer))
+            .filter(Column::Subject.eq(subject))
+            .one(db)
+            .await
+    }
+
+    pub async fn link<C: ConnectionTrait>(
+        user_id: Uuid,
+        issuer: &str,
+        subject: &str,
+        email: Option<String>,
+        db: &C,
+    ) -> Result<Model, DbErr> {
+        let mut model = ActiveModel::new();
+        model.user_id = Set(user_id);
+        model.issuer = Set(issuer.to_string());
+        model.subject = Set(subject.to_string());
+        model.email = Set(email);
+        model.insert(db).await
+    }
+}
@@ -0,0 +1,147 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::{Expr, OnConflict, Query};
+use sea_orm::ConnectionTrait;
+use serde::{Deserialize, Serialize};
+
+static IDEMPOTENCY_KEY_TTL: i64 = 60 * 60 * 24; // in seconds
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "idempotency_key")]
+pub struct Model {
+    // keys are picked by clients, so they only have to be unique per user
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    // sha256 of the method, path and body the key was first used with
+    pub fingerprint: String,
+    // the response to replay, unset while the first request is still running
+    pub status: Option<i32>,
+    pub headers: Option<Json>,
+    pub body: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// what became of trying to use a key
+pub enum Claim {
+    // the key is new, the request should run and its response be saved
+    Claimed,
+    // the key was used before, with the saved response if the request finished
+    Existing(Model),
+}
+
+impl Model {
+    pub async fn claim<C: ConnectionTrait>(
+        user_id: Uuid,
+        key: &str,
+        fingerprint: &str,
+        db: &C,
+    ) -> Result<Claim, DbErr> {
+        let now = Utc::now();
+        // expired keys can be used again. a claim whose request is still running never is, no
+        // matter how slow it is, as running it again could apply the change twice. a request
+        // that died with its instance keeps its key in use until it expires
+        Entity::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Key.eq(key))
+            .filter(Column::ExpiresAt.lt(now))
+            .exec(db)
+            .await?;
+
+        // the insert decides between concurrent requests with the same key
+        let insert = Query::insert()
+            .into_table(Entity)
+            .columns([
+                Column::UserId,
+                Column::Key,
+                Column::Fingerprint,
+                Column::CreatedAt,
+                Column::ExpiresAt,
+            ])
+            .values_panic([
+                user_id.into(),
+                key.into(),
+                fingerprint.into(),
+                now.into(),
+                (now + Duration::seconds(IDEMPOTENCY_KEY_TTL)).into(),
+            ])
+            .on_conflict(
+                OnConflict::columns([Column::UserId, Column::Key])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .to_owned();
+        let backend = db.get_database_backend();
+        if db.execute(backend.build(&insert)).await?.rows_affected() == 1 {
+            return Ok(Claim::Claimed);
+        }
+
+        let existing = Entity::find_by_id((user_id, key.to_string()))
+            .one(db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound("Idempotency key was just deleted".to_string()))?;
+        Ok(Claim::Existing(existing))
+    }
+
+    pub async fn complete<C: ConnectionTrait>(
+        user_id: Uuid,
+        key: &str,
+        status: i32,
+        headers: Json,
+        body: Vec<u8>,
+        db: &C,
+    ) -> Result<(), DbErr> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(status))
+            .col_expr(Column::Headers, Expr::value(headers))
+            .col_expr(Column::Body, Expr::value(body))
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Key.eq(key))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    // forgets a key whose request failed, so a retry runs it again
+    pub async fn release<C: ConnectionTrait>(
+        user_id: Uuid,
+        key: &str,
+        db: &C,
+    ) -> Result<(), DbErr> {
+        Entity::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Key.eq(key))
+            .filter(Column::Status.is_null())
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn purge_expired(db: &DatabaseConnection) -> Result<u64, DbErr> {
+        Ok(Entity::delete_many()
+            .filter(Column::ExpiresAt.lt(Utc::now()))
+            .exec(db)
+            .await?
+            .rows_affected)
+    }
+}
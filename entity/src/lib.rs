@@ -2,4 +2,14 @@ pub mod product;
 pub mod user;
 pub mod session;
 pub mod schedule;
-pub mod accounting_entry;
\ No newline at end of file
+pub mod accounting_entry;
+pub mod audit_log;
+pub mod refresh_token;
+pub mod password_reset;
+pub mod recovery_code;
+pub mod caregiver_grant;
+pub mod external_identity;
+pub mod email_verification;
+pub mod idempotency_key;
+pub mod export_job;
+pub mod token;
\ No newline at end of file
@@ -0,0 +1,121 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::Expr, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+
+use crate::{session, token, user};
+
+static RESET_TOKEN_TTL: i64 = 60 * 60; // in seconds
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "password_reset")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // only the sha256 of the token is stored
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    // reset tokens can only be redeemed once
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            expires_at: Set(Utc::now() + Duration::seconds(RESET_TOKEN_TTL)),
+            used_at: Set(None),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    // returns the plain token to send to the user
+    pub async fn create(user_id: Uuid, db: &DatabaseConnection) -> Result<String, DbErr> {
+        let token = token::generate();
+        let mut model = ActiveModel::new();
+        model.user_id = Set(user_id);
+        model.token_hash = Set(token::hash(&token));
+        model.insert(db).await?;
+        Ok(token)
+    }
+
+    // the user an unused, unexpired token belongs to
+    pub async fn user(token: &str, db: &DatabaseConnection) -> Result<Option<user::Model>, DbErr> {
+        let reset = Entity::find()
+            .filter(Column::TokenHash.eq(token::hash(token)))
+            .filter(Column::UsedAt.is_null())
+            .filter(Column::ExpiresAt.gt(Utc::now()))
+            .find_also_related(user::Entity)
+            .one(db)
+            .await?;
+        Ok(reset.and_then(|(_, user)| user))
+    }
+
+    // sets the hash of a new password and logs out every session, returns false if the token
+    // can't be used
+    pub async fn redeem(
+        token: &str,
+        password_hash: String,
+        db: &DatabaseConnection,
+    ) -> Result<bool, DbErr> {
+        let now = Utc::now();
+        let txn = db.begin().await?;
+        let reset = Entity::find()
+            .filter(Column::TokenHash.eq(token::hash(token)))
+            .filter(Column::UsedAt.is_null())
+            .filter(Column::ExpiresAt.gt(now))
+            .one(&txn)
+            .await?;
+        let reset = match reset {
+            Some(reset) => reset,
+            None => return Ok(false),
+        };
+
+        // only one request can claim the token
+        let claimed = Entity::update_many()
+            .col_expr(Column::UsedAt, Expr::value(now))
+            .filter(Column::Id.eq(reset.id))
+            .filter(Column::UsedAt.is_null())
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        if claimed == 0 {
+            return Ok(false);
+        }
+
+        let user = match user::Entity::find_by_id(reset.user_id).one(&txn).await? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        let mut user: user::ActiveModel = user.into();
+        user.password = Set(password_hash);
+        user.has_password = Set(true);
+        user.update(&txn).await?;
+        session::Entity::delete_many()
+            .filter(session::Column::UserId.eq(reset.user_id))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+        Ok(true)
+    }
+}
@@ -6,22 +6,22 @@ use serde::{Deserialize, Serialize};
 pub struct Model {
     #[sea_orm(primary_key)]
     #[serde(alias = "ProductNo")]
-    product_no: String,
+    pub product_no: String,
     #[sea_orm(primary_key)]
     #[serde(alias = "ApplNo")]
-    appl_no: String,
+    pub appl_no: String,
     #[serde(alias = "Form")]
-    form: Option<String>,
+    pub form: Option<String>,
     #[serde(alias = "Strength")]
-    strength: Option<String>,
+    pub strength: Option<String>,
     #[serde(alias = "ReferenceDrug")]
-    reference_drug: Option<i32>,
+    pub reference_drug: Option<i32>,
     #[serde(alias = "DrugName")]
-    drug_name: Option<String>,
+    pub drug_name: Option<String>,
     #[serde(alias = "ActiveIngredient")]
-    active_ingredient: Option<String>,
+    pub active_ingredient: Option<String>,
     #[serde(alias = "ReferenceStandard")]
-    reference_standard: Option<i32>,
+    pub reference_standard: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::Expr, ConnectionTrait, Set};
+use serde::{Deserialize, Serialize};
+
+use crate::token;
+
+// number of codes handed out when two factor authentication is confirmed
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "recovery_code")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    // only the sha256 of the code is stored
+    pub code_hash: String,
+    // each code can only be used once
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            id: Set(Uuid::new_v4()),
+            used_at: Set(None),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+// codes are typed by hand, so dashes, spaces and case are ignored
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+impl Model {
+    // replaces every code the user has, returns the plain codes to show them once
+    pub async fn regenerate<C: ConnectionTrait>(
+        user_id: Uuid,
+        db: &C,
+    ) -> Result<Vec<String>, DbErr> {
+        Entity::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .exec(db)
+            .await?;
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate()).collect();
+        let models = codes.iter().map(|code| {
+            let mut model = ActiveModel::new();
+            model.user_id = Set(user_id);
+            model.code_hash = Set(token::hash(&normalize(code)));
+            model
+        });
+        Entity::insert_many(models).exec(db).await?;
+        Ok(codes)
+    }
+
+    // marks the code as used, returns false if it doesn't exist or was already used
+    pub async fn redeem(user_id: Uuid, code: &str, db: &DatabaseConnection) -> Result<bool, DbErr> {
+        let used = Entity::update_many()
+            .col_expr(Column::UsedAt, Expr::value(Utc::now()))
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::CodeHash.eq(token::hash(&normalize(code))))
+            .filter(Column::UsedAt.is_null())
+            .exec(db)
+            .await?
+            .rows_affected;
+        Ok(used > 0)
+    }
+}
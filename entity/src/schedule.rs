@@ -10,12 +10,21 @@ use chrono::{Utc,DateTime};
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
-    user_id: Uuid,
-    drug_name: String,
-    pill_count: i32,
-    pill_amount: i32,
-    cron: String,
-    added_at: DateTime<Utc>,
+    pub user_id: Uuid,
+    pub drug_name: String,
+    pub pill_count: i32,
+    pub pill_amount: i32,
+    pub cron: String,
+    // the human friendly definition the cron expression was compiled from, if any
+    pub definition: Option<Json>,
+    pub added_at: DateTime<Utc>,
+    // the account that last changed the schedule
+    pub updated_by: Option<Uuid>,
+    // bumped on every change, clients send it back in If-Match to not overwrite newer changes
+    pub version: i32,
+    // the FDA product taken, unset when the client didn't pick one or it left the FDA data
+    pub product_appl_no: Option<String>,
+    pub product_no: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -47,6 +56,7 @@ impl ActiveModelBehavior for ActiveModel {
         Self {
             id: Set(Uuid::new_v4()),
             added_at: Set(Utc::now()),
+            version: Set(1),
             ..ActiveModelTrait::default()
         }
     }
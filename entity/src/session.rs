@@ -1,13 +1,13 @@
-
-
 use chrono::Utc;
-use jsonwebtoken::{EncodingKey, Header};
-use sea_orm::{entity::prelude::*, Set};
+use sea_orm::{entity::prelude::*, sea_query::Expr, ConnectionTrait, Set, TransactionTrait};
 use serde::{Deserialize, Serialize};
-use std::fmt;
 
+use crate::{refresh_token, token, user};
 
 static ONE_WEEK: i64 = 60 * 60 * 24 * 7; // in seconds
+static ACCESS_TOKEN_TTL: i64 = 60 * 15; // in seconds
+// the typ claim of access tokens, other signed tokens carry their own
+pub const ACCESS_TOKEN_TYPE: &str = "access";
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "Session")]
@@ -18,6 +18,30 @@ pub struct Model {
     pub iat: i64,
     // expiration
     pub exp: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // a name the user gave the device
+    pub device_label: Option<String>,
+    pub last_seen: Option<i64>,
+}
+
+// the claims of an access token, kept apart from the session row so device details stay out of tokens
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Claims {
+    pub typ: String,
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    #[serde(default)]
+    pub role: user::Role,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+// where a session is being used from
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -28,55 +52,115 @@ pub enum Relation {
         to = "super::user::Column::Id"
     )]
     User,
+    #[sea_orm(has_many = "super::refresh_token::Entity")]
+    RefreshToken,
 }
 
-impl fmt::Display for Model {
-    fn fmt (&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let string = std::env::var("SECRET_KEY").unwrap();
-        let key = string.as_bytes();
-      let token =  jsonwebtoken::encode(
-            &Header::default(),
-            &self,
-            &EncodingKey::from_secret(&key),
-        ).unwrap();
-
-        fmt.write_str(&token)?;
-        Ok(())
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
     }
 }
 
-impl Related<super::user::Entity> for Entity {
+impl Related<super::refresh_token::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::User.def()
+        Relation::RefreshToken.def()
     }
 }
 
 impl Model {
-    pub fn generate(user_id: Uuid, session_id: Uuid) -> String {
-        let string = std::env::var("SECRET_KEY").unwrap();
-        let key = string.as_bytes();
-        let now = Utc::now().timestamp_nanos() / 1_000_000_000; // nanosecond -> second
-        let payload = Model {
+    // claims for a short lived access token, verified from its signature alone,
+    // so a role change takes effect when the token is next refreshed
+    pub fn access_claims(&self, role: user::Role) -> Claims {
+        let now = Utc::now().timestamp();
+        Claims {
+            typ: ACCESS_TOKEN_TYPE.to_string(),
+            session_id: self.session_id,
+            user_id: self.user_id,
+            role,
             iat: now,
-            exp: now + ONE_WEEK,
-            user_id: user_id,
-            session_id: session_id,
+            exp: now + ACCESS_TOKEN_TTL,
+        }
+    }
+
+    pub async fn issue_refresh_token<C: ConnectionTrait>(&self, db: &C) -> Result<String, DbErr> {
+        let token = token::generate();
+        let mut model = refresh_token::ActiveModel::new();
+        model.session_id = Set(self.session_id);
+        model.token_hash = Set(token::hash(&token));
+        model.insert(db).await?;
+        Ok(token)
+    }
+
+    // swaps a refresh token for new access claims and a new refresh token,
+    // returns None if the token is unknown, expired or reused, or the account is disabled
+    pub async fn refresh(
+        token: &str,
+        device: Device,
+        db: &DatabaseConnection,
+    ) -> Result<Option<(Claims, String)>, DbErr> {
+        let found = refresh_token::Entity::find()
+            .filter(refresh_token::Column::TokenHash.eq(token::hash(token)))
+            .find_also_related(Entity)
+            .one(db)
+            .await?;
+        let (refresh, session) = match found {
+            Some((refresh, Some(session))) => (refresh, session),
+            _ => return Ok(None),
         };
-        jsonwebtoken::encode(
-            &Header::default(),
-            &payload,
-            &EncodingKey::from_secret(&key),
-        )
-        .unwrap()
+        let now = Utc::now();
+        if session.exp < now.timestamp() {
+            return Ok(None);
+        }
+        let role = match user::Entity::find_by_id(session.user_id).one(db).await? {
+            Some(user) if !user.disabled => user.role,
+            _ => return Ok(None),
+        };
+
+        let txn = db.begin().await?;
+        // only one request can rotate a token, so a token that was already rotated is being replayed
+        let rotated = refresh_token::Entity::update_many()
+            .col_expr(refresh_token::Column::RotatedAt, Expr::value(now))
+            .filter(refresh_token::Column::Id.eq(refresh.id))
+            .filter(refresh_token::Column::RotatedAt.is_null())
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        if rotated == 0 {
+            txn.rollback().await?;
+            // the token may have been stolen, revoke the whole family
+            Entity::delete_by_id(session.session_id).exec(db).await?;
+            return Ok(None);
+        }
+
+        let mut active: ActiveModel = session.into();
+        active.exp = Set(now.timestamp() + ONE_WEEK);
+        active.last_seen = Set(Some(now.timestamp()));
+        active.user_agent = Set(device.user_agent);
+        active.ip_address = Set(device.ip_address);
+        let session = active.update(&txn).await?;
+        let token = session.issue_refresh_token(&txn).await?;
+        txn.commit().await?;
+        Ok(Some((session.access_claims(role), token)))
+    }
+
+    // sessions can't be refreshed past their expiry, so they are safe to delete
+    pub async fn purge_expired(db: &DatabaseConnection) -> Result<u64, DbErr> {
+        Ok(Entity::delete_many()
+            .filter(Column::Exp.lt(Utc::now().timestamp()))
+            .exec(db)
+            .await?
+            .rows_affected)
     }
 }
 
 impl ActiveModelBehavior for ActiveModel {
     fn new() -> Self {
-        let now = Utc::now().timestamp_nanos() / 1_000_000_000; // nanosecond -> second
+        let now = Utc::now().timestamp();
         Self {
             iat: Set(now),
             exp: Set(now + ONE_WEEK),
+            last_seen: Set(Some(now)),
             session_id: Set(Uuid::new_v4()),
             ..ActiveModelTrait::default()
         }
@@ -0,0 +1,12 @@
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+// random bearer secret handed to a client, only its hash is stored
+pub fn generate() -> String {
+    let bytes = rand::thread_rng().gen::<[u8; 32]>();
+    hex::encode(bytes)
+}
+
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
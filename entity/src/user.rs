@@ -0,0 +1,328 @@
+use argon2;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sea_orm::{
+    entity::prelude::*, sea_query::Expr, ActiveValue, ActiveValue::NotSet, Condition,
+    ConnectionTrait, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
+
+use crate::{accounting_entry, audit_log, recovery_code, schedule, session};
+
+// usernames are stored NFKC case folded, so lookalike spellings of a name are the same account
+pub fn normalize_username(username: &str) -> String {
+    let folded = caseless::default_case_fold_str(&username.trim().nfkc().collect::<String>());
+    folded.nfkc().collect()
+}
+
+// argon2id with 64MiB of memory, slow on purpose so run it off the async workers. the password
+// column only ever holds these hashes
+pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+    let salt = rand::thread_rng().gen::<[u8; 32]>();
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: 65536,
+        time_cost: 10,
+        lanes: 4,
+        thread_mode: argon2::ThreadMode::Parallel,
+        secret: &[],
+        ad: &[],
+        hash_length: 32,
+    };
+    argon2::hash_encoded(password.as_bytes(), &salt, &config)
+}
+
+// usernames that look like an email address get a verification mail
+pub fn is_email(username: &str) -> bool {
+    match username.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.contains('@'),
+        None => false,
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    EnumIter,
+    DeriveActiveEnum,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // tokens issued before roles existed belong to regular users
+    #[default]
+    #[sea_orm(string_value = "user")]
+    User,
+    #[sea_orm(string_value = "admin")]
+    Admin,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "Users")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub username: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    password: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    // encrypted by the application, set while enrolling and once enrolled
+    #[serde(skip_serializing, skip_deserializing)]
+    pub totp_secret: Option<String>,
+    // true once the user has confirmed a code from their authenticator
+    pub totp_enabled: bool,
+    // the last time step a code was accepted for, so each code works once
+    #[serde(skip_serializing, skip_deserializing)]
+    pub totp_last_step: Option<i64>,
+    pub role: Role,
+    // disabled accounts can't log in or refresh their sessions
+    pub disabled: bool,
+    // set once the user followed the link mailed to an email address username
+    pub email_verified_at: Option<DateTime<Utc>>,
+    // false for accounts made by a single sign-on login until the user sets a password, they
+    // have a random one nobody knows
+    pub has_password: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::session::Entity")]
+    Session,
+    #[sea_orm(has_many = "super::schedule::Entity")]
+    Schedule,
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl Related<super::schedule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Schedule.def()
+    }
+}
+
+impl Model {
+    pub async fn find_by_username<C: ConnectionTrait>(
+        username: &str,
+        db: &C,
+    ) -> Result<Option<Model>, DbErr> {
+        Entity::find()
+            .filter(Column::Username.eq(normalize_username(username)))
+            .one(db)
+            .await
+    }
+
+    pub fn verify_password(&self, password: String) -> Result<bool, argon2::Error> {
+        argon2::verify_encoded(&self.password, password.as_bytes())
+    }
+
+    pub async fn validate_login_session(
+        token: &session::Claims,
+        db: &DatabaseConnection,
+    ) -> Result<session::Model, DbErr> {
+        let user = session::Entity::find()
+            .filter(session::Column::SessionId.eq(token.session_id))
+            .filter(session::Column::UserId.eq(token.user_id))
+            .one(db)
+            .await?;
+        match user {
+            Some(v) => Ok(v),
+            None => Err(DbErr::RecordNotFound("missing".to_string())),
+        }
+    }
+
+    pub async fn new_login_session(
+        &self,
+        device: session::Device,
+        db: &DatabaseConnection,
+    ) -> Result<session::Model, DbErr> {
+        let mut ses = super::session::ActiveModel::new();
+        ses.user_id = Set(self.id);
+        ses.user_agent = Set(device.user_agent);
+        ses.ip_address = Set(device.ip_address);
+        super::session::Entity::insert(ses)
+            .exec_with_returning(db)
+            .await
+    }
+
+    // saves the new password's hash, every other session is logged out. accounts without a
+    // password get one this way
+    pub async fn change_password(
+        self,
+        password_hash: String,
+        current_session: Uuid,
+        db: &DatabaseConnection,
+    ) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+        let id = self.id;
+        let mut model: ActiveModel = self.into();
+        model.password = Set(password_hash);
+        model.has_password = Set(true);
+        model.update(&txn).await?;
+        session::Entity::delete_many()
+            .filter(session::Column::UserId.eq(id))
+            .filter(session::Column::SessionId.ne(current_session))
+            .exec(&txn)
+            .await?;
+        txn.commit().await
+    }
+
+    // stores a new unconfirmed secret, replacing any unfinished enrolment
+    pub async fn start_totp_enrolment(
+        self,
+        encrypted_secret: String,
+        db: &DatabaseConnection,
+    ) -> Result<(), DbErr> {
+        let mut model: ActiveModel = self.into();
+        model.totp_secret = Set(Some(encrypted_secret));
+        model.totp_enabled = Set(false);
+        model.totp_last_step = Set(None);
+        model.update(db).await?;
+        Ok(())
+    }
+
+    // turns two factor authentication on, returns the user's recovery codes
+    pub async fn enable_totp(self, db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+        let txn = db.begin().await?;
+        let id = self.id;
+        let mut model: ActiveModel = self.into();
+        model.totp_enabled = Set(true);
+        model.update(&txn).await?;
+        let codes = recovery_code::Model::regenerate(id, &txn).await?;
+        txn.commit().await?;
+        Ok(codes)
+    }
+
+    pub async fn disable_totp(self, db: &DatabaseConnection) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+        let id = self.id;
+        let mut model: ActiveModel = self.into();
+        model.totp_secret = Set(None);
+        model.totp_enabled = Set(false);
+        model.totp_last_step = Set(None);
+        model.update(&txn).await?;
+        recovery_code::Entity::delete_many()
+            .filter(recovery_code::Column::UserId.eq(id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await
+    }
+
+    // records the step a code was accepted for, false if it or a later step was already used
+    pub async fn claim_totp_step(&self, step: i64, db: &DatabaseConnection) -> Result<bool, DbErr> {
+        let claimed = Entity::update_many()
+            .col_expr(Column::TotpLastStep, Expr::value(step))
+            .filter(Column::Id.eq(self.id))
+            .filter(
+                Condition::any()
+                    .add(Column::TotpLastStep.is_null())
+                    .add(Column::TotpLastStep.lt(step)),
+            )
+            .exec(db)
+            .await?
+            .rows_affected;
+        Ok(claimed > 0)
+    }
+
+    // disables or re-enables the account, disabling also logs out every session
+    pub async fn set_disabled(self, disabled: bool, db: &DatabaseConnection) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+        let id = self.id;
+        let mut model: ActiveModel = self.into();
+        model.disabled = Set(disabled);
+        model.update(&txn).await?;
+        if disabled {
+            session::Entity::delete_many()
+                .filter(session::Column::UserId.eq(id))
+                .exec(&txn)
+                .await?;
+        }
+        txn.commit().await
+    }
+
+    // removes the user and everything they own in one transaction, leaving an anonymous audit entry
+    pub async fn delete_account(self, db: &DatabaseConnection) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+
+        let schedule_ids: Vec<Uuid> = schedule::Entity::find()
+            .filter(schedule::Column::UserId.eq(self.id))
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        let accounting_entries = accounting_entry::Entity::delete_many()
+            .filter(accounting_entry::Column::ScheduleId.is_in(schedule_ids))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        let schedules = schedule::Entity::delete_many()
+            .filter(schedule::Column::UserId.eq(self.id))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        let sessions = session::Entity::delete_many()
+            .filter(session::Column::UserId.eq(self.id))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        Entity::delete_by_id(self.id).exec(&txn).await?;
+
+        let mut audit = audit_log::ActiveModel::new();
+        audit.action = Set("account_deleted".to_string());
+        audit.detail = Set(Some(serde_json::json!({
+            "schedules": schedules,
+            "accounting_entries": accounting_entries,
+            "sessions": sessions,
+        })));
+        audit.insert(&txn).await?;
+
+        txn.commit().await
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        Self {
+            created: Set(Utc::now()),
+            updated: Set(Utc::now()),
+            password: NotSet,
+            totp_secret: Set(None),
+            totp_enabled: Set(false),
+            totp_last_step: Set(None),
+            role: Set(Role::User),
+            disabled: Set(false),
+            has_password: Set(true),
+            ..ActiveModelTrait::default()
+        }
+    }
+    fn before_save(mut self, _insert: bool) -> Result<Self, DbErr> {
+        let timestamp = Utc::now();
+        if self.id.is_not_set() {
+            self.id = Set(Uuid::new_v4());
+        }
+        if let ActiveValue::Set(username) = &self.username {
+            self.username = Set(normalize_username(username));
+        }
+        if self.created.is_not_set() {
+            self.created = Set(timestamp)
+        }
+
+        self.updated = Set(timestamp);
+        Ok(self)
+    }
+}
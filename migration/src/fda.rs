@@ -0,0 +1,181 @@
+use entity::product::*;
+
+use log::info;
+
+use std::io::{copy, Cursor};
+
+use std::collections::HashSet;
+use std::{fs, path::PathBuf};
+use tempfile::{Builder, TempDir};
+
+use sea_orm_migration::sea_orm::sea_query::{Cond, Expr, OnConflict, Query};
+use sea_orm_migration::sea_orm::{
+    ConnectionTrait, DbConn, EntityTrait, QueryFilter, TransactionTrait,
+};
+
+const FDA_URL: &str = "https://www.fda.gov/media/89850/download";
+// rows per insert statement, keeps each statement well under postgres' bind parameter limit
+const INSERT_BATCH: usize = 1000;
+
+// downloads the Drugs@FDA data files and brings the products table in line with them,
+// returns the number of products loaded
+pub async fn import(db: &DbConn) -> anyhow::Result<usize> {
+    let tmp_dir = Builder::new().prefix("fda").tempdir()?;
+    let zip = download_zip(&tmp_dir).await?;
+    let files = extract_zip(zip, &tmp_dir).await?;
+    for file in files {
+        match file.file_name().and_then(|name| name.to_str()) {
+            Some("Products.txt") => return load_data(file, db).await,
+            _ => continue,
+        }
+    }
+    anyhow::bail!("Products.txt was not found in the download")
+}
+
+async fn download_zip(dir: &TempDir) -> anyhow::Result<PathBuf> {
+    info!("Downloading file from {}", FDA_URL);
+    let response = reqwest::get(FDA_URL).await?;
+    // get content disposition header
+    let cd = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .unwrap();
+
+    // get filename from content disposition response header
+    let file_name = actix_web::http::header::ContentDisposition::from_raw(cd)?
+        .get_filename()
+        .unwrap()
+        .to_string();
+    info!("Got filename {} from Content Disposition", file_name);
+
+    //merge filename with process temp directory
+    let file_path = dir.path().join(&file_name);
+    //write binary from HTTP response to file
+    let mut file = fs::File::create(&file_path)?;
+    let mut content = Cursor::new(response.bytes().await?);
+    info!("Saving file to {:?}", &file_path);
+    copy(&mut content, &mut file)?;
+    info!("File saved sucessfully");
+    anyhow::Ok(file_path)
+}
+
+async fn extract_zip(file: PathBuf, dir: &TempDir) -> anyhow::Result<Vec<PathBuf>> {
+    // array of extracted filepaths
+    let mut files: Vec<PathBuf> = vec![];
+
+    // open and parse zip file
+    info!("Attempting to extract {:?}", &file);
+    let zip = fs::File::open(file)?;
+    let mut archive = zip::ZipArchive::new(zip)?;
+
+    //iterate over files in zip
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        // skip if current index has no file name
+        let file_path = match file.enclosed_name() {
+            Some(path) => path.to_owned(),
+            None => continue,
+        };
+        // join zip filename with temp directory
+        let outpath = dir.path().join(file_path);
+        if (*file.name()).ends_with('/') {
+            info!("File {} extracted to \"{}\"", i, outpath.display());
+            //create parent directory path if item is a folder
+            fs::create_dir_all(&outpath).unwrap();
+        } else {
+            info!(
+                "File {} extracted to \"{}\" ({} bytes)",
+                i,
+                outpath.display(),
+                file.size()
+            );
+            //write zip file to filesystem in temp directory
+            let mut outfile = fs::File::create(&outpath)?;
+            copy(&mut file, &mut outfile)?;
+            files.push(outpath.clone());
+        }
+    }
+    //return array of filepaths
+    anyhow::Ok(files)
+}
+
+async fn load_data(path: PathBuf, db: &DbConn) -> anyhow::Result<usize> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .flexible(true)
+        .from_path(&path)?;
+    info!("Starting import of file {:?}", &path);
+    let records = rdr.deserialize().collect::<Result<Vec<Model>, _>>()?;
+
+    // updated in place rather than replaced, so schedules linked to a product keep the link.
+    // one transaction, so readers never see a half loaded table
+    let txn = db.begin().await?;
+    let backend = txn.get_database_backend();
+    for batch in records.chunks(INSERT_BATCH) {
+        let mut insert = Query::insert();
+        insert.into_table(Entity).columns([
+            Column::ApplNo,
+            Column::ProductNo,
+            Column::Form,
+            Column::Strength,
+            Column::ReferenceDrug,
+            Column::DrugName,
+            Column::ActiveIngredient,
+            Column::ReferenceStandard,
+        ]);
+        for record in batch {
+            insert.values_panic([
+                record.appl_no.clone().into(),
+                record.product_no.clone().into(),
+                record.form.clone().into(),
+                record.strength.clone().into(),
+                record.reference_drug.into(),
+                record.drug_name.clone().into(),
+                record.active_ingredient.clone().into(),
+                record.reference_standard.into(),
+            ]);
+        }
+        insert.on_conflict(
+            OnConflict::columns([Column::ApplNo, Column::ProductNo])
+                .update_columns([
+                    Column::Form,
+                    Column::Strength,
+                    Column::ReferenceDrug,
+                    Column::DrugName,
+                    Column::ActiveIngredient,
+                    Column::ReferenceStandard,
+                ])
+                .to_owned(),
+        );
+        txn.execute(backend.build(&insert)).await?;
+    }
+
+    // products no longer in the data files, their schedules are unlinked by the foreign key
+    let loaded: HashSet<(&str, &str)> = records
+        .iter()
+        .map(|record| (record.appl_no.as_str(), record.product_no.as_str()))
+        .collect();
+    let existing = Entity::find().all(&txn).await?;
+    let removed: Vec<&Model> = existing
+        .iter()
+        .filter(|product| {
+            !loaded.contains(&(product.appl_no.as_str(), product.product_no.as_str()))
+        })
+        .collect();
+    for batch in removed.chunks(INSERT_BATCH) {
+        let mut condition = Cond::any();
+        for product in batch {
+            condition = condition.add(
+                Cond::all()
+                    .add(Expr::col(Column::ApplNo).eq(product.appl_no.as_str()))
+                    .add(Expr::col(Column::ProductNo).eq(product.product_no.as_str())),
+            );
+        }
+        Entity::delete_many().filter(condition).exec(&txn).await?;
+    }
+    txn.commit().await?;
+    info!("Removed {} products", removed.len());
+    info!("Imported {} products", records.len());
+
+    anyhow::Ok(records.len())
+}
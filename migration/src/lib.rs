@@ -1,10 +1,31 @@
 pub use sea_orm_migration::prelude::*;
 
+pub mod fda;
+
 mod m20220101_000001_create_user_table;
 mod m20220618_162459_create_product_table;
 mod m20220619_174222_create_session_table;
 mod m20220619_230031_create_schedule_table;
 mod m20220619_234623_create_accounting_table;
+mod m20261019_120000_add_schedule_definition;
+mod m20261019_130000_create_audit_log_table;
+mod m20261019_130100_add_cascade_deletes;
+mod m20261019_140000_create_refresh_token_table;
+mod m20261019_150000_add_session_device;
+mod m20261019_160000_create_password_reset_table;
+mod m20261019_170000_add_totp;
+mod m20261019_180000_add_user_role;
+mod m20261019_190000_create_caregiver_grant_table;
+mod m20261019_200000_create_external_identity_table;
+mod m20261019_210000_normalize_usernames;
+mod m20261019_210100_create_email_verification_table;
+mod m20261019_210200_add_schedule_version;
+mod m20261019_210300_create_idempotency_key_table;
+mod m20261019_220000_add_schedule_product;
+mod m20261019_220100_add_accounting_entry_kind;
+mod m20261019_220200_add_caregiver_grant_unique;
+mod m20261019_220300_add_user_has_password;
+mod m20261019_220400_create_export_job_table;
 
 
 
@@ -20,6 +41,25 @@ impl MigratorTrait for Migrator {
             Box::new(m20220619_174222_create_session_table::Migration),
             Box::new(m20220619_230031_create_schedule_table::Migration),
             Box::new(m20220619_234623_create_accounting_table::Migration),
+            Box::new(m20261019_120000_add_schedule_definition::Migration),
+            Box::new(m20261019_130000_create_audit_log_table::Migration),
+            Box::new(m20261019_130100_add_cascade_deletes::Migration),
+            Box::new(m20261019_140000_create_refresh_token_table::Migration),
+            Box::new(m20261019_150000_add_session_device::Migration),
+            Box::new(m20261019_160000_create_password_reset_table::Migration),
+            Box::new(m20261019_170000_add_totp::Migration),
+            Box::new(m20261019_180000_add_user_role::Migration),
+            Box::new(m20261019_190000_create_caregiver_grant_table::Migration),
+            Box::new(m20261019_200000_create_external_identity_table::Migration),
+            Box::new(m20261019_210000_normalize_usernames::Migration),
+            Box::new(m20261019_210100_create_email_verification_table::Migration),
+            Box::new(m20261019_210200_add_schedule_version::Migration),
+            Box::new(m20261019_210300_create_idempotency_key_table::Migration),
+            Box::new(m20261019_220000_add_schedule_product::Migration),
+            Box::new(m20261019_220100_add_accounting_entry_kind::Migration),
+            Box::new(m20261019_220200_add_caregiver_grant_unique::Migration),
+            Box::new(m20261019_220300_add_user_has_password::Migration),
+            Box::new(m20261019_220400_create_export_job_table::Migration),
         ]
     }
 }
@@ -2,7 +2,6 @@ use entity::product::*;
 use sea_orm_migration::prelude::*;
 pub struct Migration;
 
-use anyhow;
 
 use log::info;
 
@@ -0,0 +1,85 @@
+use entity::{accounting_entry, schedule, session, user};
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_130100_add_cascade_deletes"
+    }
+}
+
+// the original tables were created with unnamed foreign keys, so they carry postgres' default names
+const SESSION_USER_FK: &str = "Session_user_id_fkey";
+const SCHEDULE_USER_FK: &str = "schedule_user_id_fkey";
+const ACCOUNTING_SCHEDULE_FK: &str = "accounting_schedule_id_fkey";
+
+fn foreign_keys(on_delete: ForeignKeyAction) -> Vec<ForeignKeyCreateStatement> {
+    vec![
+        ForeignKey::create()
+            .name(SESSION_USER_FK)
+            .from_tbl(session::Entity)
+            .from_col(session::Column::UserId)
+            .to_tbl(user::Entity)
+            .to_col(user::Column::Id)
+            .on_delete(on_delete)
+            .to_owned(),
+        ForeignKey::create()
+            .name(SCHEDULE_USER_FK)
+            .from_tbl(schedule::Entity)
+            .from_col(schedule::Column::UserId)
+            .to_tbl(user::Entity)
+            .to_col(user::Column::Id)
+            .on_delete(on_delete)
+            .to_owned(),
+        ForeignKey::create()
+            .name(ACCOUNTING_SCHEDULE_FK)
+            .from_tbl(accounting_entry::Entity)
+            .from_col(accounting_entry::Column::ScheduleId)
+            .to_tbl(schedule::Entity)
+            .to_col(schedule::Column::Id)
+            .on_delete(on_delete)
+            .to_owned(),
+    ]
+}
+
+fn drop_foreign_keys() -> Vec<ForeignKeyDropStatement> {
+    vec![
+        ForeignKey::drop()
+            .name(SESSION_USER_FK)
+            .table(session::Entity)
+            .to_owned(),
+        ForeignKey::drop()
+            .name(SCHEDULE_USER_FK)
+            .table(schedule::Entity)
+            .to_owned(),
+        ForeignKey::drop()
+            .name(ACCOUNTING_SCHEDULE_FK)
+            .table(accounting_entry::Entity)
+            .to_owned(),
+    ]
+}
+
+// postgres can't alter the delete rule of a foreign key, so each one is recreated
+async fn replace_foreign_keys(
+    manager: &SchemaManager<'_>,
+    on_delete: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    for fk in drop_foreign_keys() {
+        manager.drop_foreign_key(fk).await?;
+    }
+    for fk in foreign_keys(on_delete) {
+        manager.create_foreign_key(fk).await?;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_foreign_keys(manager, ForeignKeyAction::Cascade).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_foreign_keys(manager, ForeignKeyAction::NoAction).await
+    }
+}
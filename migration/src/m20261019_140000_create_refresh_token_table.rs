@@ -0,0 +1,52 @@
+use entity::refresh_token::*;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_140000_create_refresh_token_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Entity)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Column::Id).uuid().not_null())
+                    .col(ColumnDef::new(Column::SessionId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(Column::TokenHash)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Column::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Column::RotatedAt).timestamp_with_time_zone())
+                    .primary_key(Index::create().col(Column::Id))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(Entity)
+                            .from_col(Column::SessionId)
+                            .to_tbl(entity::session::Entity)
+                            .to_col(entity::session::Column::SessionId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,40 @@
+use entity::session::*;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_150000_add_session_device"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .add_column(ColumnDef::new(Column::UserAgent).string())
+                    .add_column(ColumnDef::new(Column::IpAddress).string())
+                    .add_column(ColumnDef::new(Column::DeviceLabel).string())
+                    .add_column(ColumnDef::new(Column::LastSeen).big_integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .drop_column(Column::UserAgent)
+                    .drop_column(Column::IpAddress)
+                    .drop_column(Column::DeviceLabel)
+                    .drop_column(Column::LastSeen)
+                    .to_owned(),
+            )
+            .await
+    }
+}
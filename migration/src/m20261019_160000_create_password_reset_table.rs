@@ -0,0 +1,52 @@
+use entity::password_reset::*;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_160000_create_password_reset_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Entity)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Column::Id).uuid().not_null())
+                    .col(ColumnDef::new(Column::UserId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(Column::TokenHash)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Column::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Column::UsedAt).timestamp_with_time_zone())
+                    .primary_key(Index::create().col(Column::Id))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(Entity)
+                            .from_col(Column::UserId)
+                            .to_tbl(entity::user::Entity)
+                            .to_col(entity::user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Entity).to_owned())
+            .await
+    }
+}
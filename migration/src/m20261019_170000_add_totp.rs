@@ -0,0 +1,76 @@
+use entity::recovery_code;
+use entity::user;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_170000_add_totp"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .add_column(ColumnDef::new(user::Column::TotpSecret).string())
+                    .add_column(
+                        ColumnDef::new(user::Column::TotpEnabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(user::Column::TotpLastStep).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(recovery_code::Entity)
+                    .if_not_exists()
+                    .col(ColumnDef::new(recovery_code::Column::Id).uuid().not_null())
+                    .col(
+                        ColumnDef::new(recovery_code::Column::UserId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(recovery_code::Column::CodeHash)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(recovery_code::Column::UsedAt).timestamp_with_time_zone())
+                    .primary_key(Index::create().col(recovery_code::Column::Id))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(recovery_code::Entity)
+                            .from_col(recovery_code::Column::UserId)
+                            .to_tbl(user::Entity)
+                            .to_col(user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(recovery_code::Entity).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .drop_column(user::Column::TotpSecret)
+                    .drop_column(user::Column::TotpEnabled)
+                    .drop_column(user::Column::TotpLastStep)
+                    .to_owned(),
+            )
+            .await
+    }
+}
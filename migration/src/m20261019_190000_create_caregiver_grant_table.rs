@@ -0,0 +1,145 @@
+use entity::{accounting_entry, caregiver_grant, schedule, user};
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_190000_create_caregiver_grant_table"
+    }
+}
+
+const ACCOUNTING_ACTOR_FK: &str = "accounting_actor_id_fkey";
+const SCHEDULE_UPDATED_BY_FK: &str = "schedule_updated_by_fkey";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(caregiver_grant::Entity)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::Id)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::OwnerId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::CaregiverId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::Permission)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(caregiver_grant::Column::ScheduleId).uuid())
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(caregiver_grant::Column::AcceptedAt)
+                            .timestamp_with_time_zone(),
+                    )
+                    .primary_key(Index::create().col(caregiver_grant::Column::Id))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(caregiver_grant::Entity)
+                            .from_col(caregiver_grant::Column::OwnerId)
+                            .to_tbl(user::Entity)
+                            .to_col(user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(caregiver_grant::Entity)
+                            .from_col(caregiver_grant::Column::CaregiverId)
+                            .to_tbl(user::Entity)
+                            .to_col(user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(caregiver_grant::Entity)
+                            .from_col(caregiver_grant::Column::ScheduleId)
+                            .to_tbl(schedule::Entity)
+                            .to_col(schedule::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // who made each change, kept when that account is deleted
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(accounting_entry::Entity)
+                    .add_column(ColumnDef::new(accounting_entry::Column::ActorId).uuid())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(schedule::Entity)
+                    .add_column(ColumnDef::new(schedule::Column::UpdatedBy).uuid())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(ACCOUNTING_ACTOR_FK)
+                    .from_tbl(accounting_entry::Entity)
+                    .from_col(accounting_entry::Column::ActorId)
+                    .to_tbl(user::Entity)
+                    .to_col(user::Column::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(SCHEDULE_UPDATED_BY_FK)
+                    .from_tbl(schedule::Entity)
+                    .from_col(schedule::Column::UpdatedBy)
+                    .to_tbl(user::Entity)
+                    .to_col(user::Column::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(schedule::Entity)
+                    .drop_column(schedule::Column::UpdatedBy)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(accounting_entry::Entity)
+                    .drop_column(accounting_entry::Column::ActorId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(caregiver_grant::Entity).to_owned())
+            .await
+    }
+}
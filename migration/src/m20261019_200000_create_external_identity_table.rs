@@ -0,0 +1,62 @@
+use entity::external_identity::*;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_200000_create_external_identity_table"
+    }
+}
+
+const ISSUER_SUBJECT_INDEX: &str = "external_identity_issuer_subject";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Entity)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Column::Id).uuid().not_null())
+                    .col(ColumnDef::new(Column::UserId).uuid().not_null())
+                    .col(ColumnDef::new(Column::Issuer).string().not_null())
+                    .col(ColumnDef::new(Column::Subject).string().not_null())
+                    .col(ColumnDef::new(Column::Email).string())
+                    .col(
+                        ColumnDef::new(Column::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .primary_key(Index::create().col(Column::Id))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(Entity)
+                            .from_col(Column::UserId)
+                            .to_tbl(entity::user::Entity)
+                            .to_col(entity::user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // one external account can only ever be linked to one user
+        manager
+            .create_index(
+                Index::create()
+                    .name(ISSUER_SUBJECT_INDEX)
+                    .table(Entity)
+                    .col(Column::Issuer)
+                    .col(Column::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Entity).to_owned())
+            .await
+    }
+}
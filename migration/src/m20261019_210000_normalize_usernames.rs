@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+
+use entity::user::*;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::{
+    ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Statement, TransactionTrait,
+};
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_210000_normalize_usernames"
+    }
+}
+
+// the original unique key is case sensitive, this one also catches names differing in case
+const USERNAME_LOWER_INDEX: &str = "Users_username_lower_key";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+        // only the columns that exist at this point are read, the model may have grown since
+        let rows = db
+            .query_all(Statement::from_string(
+                backend,
+                r#"SELECT id, username FROM "Users" ORDER BY created, id"#.to_string(),
+            ))
+            .await?;
+
+        let mut accounts: BTreeMap<String, Vec<(Uuid, String)>> = BTreeMap::new();
+        for row in rows {
+            let id: Uuid = row.try_get("", "id")?;
+            let username: String = row.try_get("", "username")?;
+            accounts
+                .entry(normalize_username(&username))
+                .or_default()
+                .push((id, username));
+        }
+
+        // accounts whose names collapse into one can't be told apart any more, and renaming
+        // them would lock their owners out. nothing is changed until an operator renames or
+        // merges them
+        let collisions: Vec<String> = accounts
+            .iter()
+            .filter(|(_, owners)| owners.len() > 1)
+            .map(|(normalized, owners)| {
+                let owners: Vec<String> = owners
+                    .iter()
+                    .map(|(id, username)| format!("{:?} ({})", username, id))
+                    .collect();
+                format!("{:?}: {}", normalized, owners.join(", "))
+            })
+            .collect();
+        if !collisions.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "{} usernames are the same once normalized, rename all but one account of each \
+                 and run the migration again:\n{}",
+                collisions.len(),
+                collisions.join("\n")
+            )));
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .add_column(ColumnDef::new(Column::EmailVerifiedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        let txn = db.begin().await?;
+        for (normalized, owners) in accounts {
+            let (id, username) = &owners[0];
+            if username == &normalized {
+                continue;
+            }
+            Entity::update_many()
+                .col_expr(Column::Username, Expr::value(normalized))
+                .filter(Column::Id.eq(*id))
+                .exec(&txn)
+                .await?;
+        }
+        txn.execute(Statement::from_string(
+            backend,
+            format!(
+                r#"CREATE UNIQUE INDEX "{}" ON "Users" (lower(username))"#,
+                USERNAME_LOWER_INDEX
+            ),
+        ))
+        .await?;
+        txn.commit().await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // normalized usernames are kept, the original spelling isn't stored anywhere
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(USERNAME_LOWER_INDEX)
+                    .table(Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .drop_column(Column::EmailVerifiedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm_migration::sea_orm::{Database, DatabaseConnection};
+
+    use super::*;
+    use crate::m20220101_000001_create_user_table;
+
+    // a database of its own, the shared test database is long past this migration
+    async fn scratch_database() -> (DatabaseConnection, DatabaseConnection, String) {
+        let url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must be set to run database tests");
+        let admin = Database::connect(&url).await.unwrap();
+        let name = format!("normalize_usernames_{}", Uuid::new_v4().to_simple());
+        admin
+            .execute(Statement::from_string(
+                admin.get_database_backend(),
+                format!(r#"CREATE DATABASE "{}""#, name),
+            ))
+            .await
+            .unwrap();
+        let base = &url[..url.rfind('/').unwrap()];
+        let db = Database::connect(&format!("{}/{}", base, name))
+            .await
+            .unwrap();
+        (admin, db, name)
+    }
+
+    async fn insert(db: &DatabaseConnection, username: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            r#"INSERT INTO "Users" (id, username, password, created, updated) VALUES ($1, $2, '', now(), now())"#,
+            vec![id.into(), username.into()],
+        ))
+        .await
+        .unwrap();
+        id
+    }
+
+    async fn usernames(db: &DatabaseConnection) -> Vec<String> {
+        db.query_all(Statement::from_string(
+            db.get_database_backend(),
+            r#"SELECT username FROM "Users" ORDER BY username"#.to_string(),
+        ))
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.try_get("", "username").unwrap())
+        .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn colliding_usernames_fail_the_migration_without_changes() {
+        let (admin, db, name) = scratch_database().await;
+        let manager = SchemaManager::new(&db);
+        m20220101_000001_create_user_table::Migration
+            .up(&manager)
+            .await
+            .unwrap();
+        let bob = insert(&db, "Bob").await;
+        let other_bob = insert(&db, "bob").await;
+        insert(&db, "ALICE").await;
+
+        let err = Migration.up(&manager).await.unwrap_err().to_string();
+        assert!(
+            err.contains("1 usernames are the same once normalized"),
+            "{}",
+            err
+        );
+        assert!(err.contains(&bob.to_string()) && err.contains(&other_bob.to_string()));
+        assert!(!err.contains("ALICE"));
+        assert_eq!(usernames(&db).await, ["ALICE", "Bob", "bob"]);
+        assert!(!manager
+            .has_column("Users", "email_verified_at")
+            .await
+            .unwrap());
+
+        // once an operator renamed one of them it goes through
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            format!(
+                r#"UPDATE "Users" SET username = 'Robert' WHERE id = '{}'"#,
+                bob
+            ),
+        ))
+        .await
+        .unwrap();
+        Migration.up(&manager).await.unwrap();
+        assert_eq!(usernames(&db).await, ["alice", "bob", "robert"]);
+
+        drop(db);
+        admin
+            .execute(Statement::from_string(
+                admin.get_database_backend(),
+                format!(r#"DROP DATABASE "{}" WITH (FORCE)"#, name),
+            ))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+use entity::idempotency_key::*;
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_210300_create_idempotency_key_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Entity)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Column::UserId).uuid().not_null())
+                    .col(ColumnDef::new(Column::Key).string().not_null())
+                    .col(ColumnDef::new(Column::Fingerprint).string().not_null())
+                    .col(ColumnDef::new(Column::Status).integer())
+                    .col(ColumnDef::new(Column::Headers).json())
+                    .col(ColumnDef::new(Column::Body).binary())
+                    .col(
+                        ColumnDef::new(Column::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Column::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .primary_key(Index::create().col(Column::UserId).col(Column::Key))
+                    .foreign_key(
+                        ForeignKeyCreateStatement::new()
+                            .from_tbl(Entity)
+                            .from_col(Column::UserId)
+                            .to_tbl(entity::user::Entity)
+                            .to_col(entity::user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Entity).to_owned())
+            .await
+    }
+}
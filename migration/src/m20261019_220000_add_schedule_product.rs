@@ -0,0 +1,66 @@
+use entity::{product, schedule};
+use sea_orm_migration::prelude::*;
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_220000_add_schedule_product"
+    }
+}
+
+const SCHEDULE_PRODUCT_FK: &str = "schedule_product_fkey";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // existing schedules are left unlinked, a drug name can match several products
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(schedule::Entity)
+                    .add_column(ColumnDef::new(schedule::Column::ProductApplNo).string_len(6))
+                    .add_column(ColumnDef::new(schedule::Column::ProductNo).string_len(6))
+                    .to_owned(),
+            )
+            .await?;
+
+        // the link is dropped when a product disappears from the FDA data
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(SCHEDULE_PRODUCT_FK)
+                    .from(
+                        schedule::Entity,
+                        (schedule::Column::ProductApplNo, schedule::Column::ProductNo),
+                    )
+                    .to(
+                        product::Entity,
+                        (product::Column::ApplNo, product::Column::ProductNo),
+                    )
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .on_update(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name(SCHEDULE_PRODUCT_FK)
+                    .table(schedule::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(schedule::Entity)
+                    .drop_column(schedule::Column::ProductApplNo)
+                    .drop_column(schedule::Column::ProductNo)
+                    .to_owned(),
+            )
+            .await
+    }
+}
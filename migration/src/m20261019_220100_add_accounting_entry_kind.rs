@@ -0,0 +1,54 @@
+use entity::accounting_entry::*;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_220100_add_accounting_entry_kind"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // earlier entries can't tell a dose from a recount, so only each schedule's first entry
+    // is known for what it is and the rest are kept as corrections, which aren't exported
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .add_column(
+                        ColumnDef::new(Column::Kind)
+                            .string_len(16)
+                            .not_null()
+                            .default("correction"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            manager.get_database_backend(),
+            r#"UPDATE accounting SET kind = 'initial' WHERE id IN (
+                SELECT DISTINCT ON (schedule_id) id FROM accounting
+                ORDER BY schedule_id, timestamp, id
+            )"#
+            .to_string(),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .drop_column(Column::Kind)
+                    .to_owned(),
+            )
+            .await
+    }
+}
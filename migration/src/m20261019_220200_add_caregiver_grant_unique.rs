@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_220200_add_caregiver_grant_unique"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // one grant per caregiver for all of an owner's schedules, and one per single schedule.
+    // of duplicates the accepted one is kept, so nobody loses access they have now, then the
+    // one with more access, then the oldest
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+        db.execute(Statement::from_string(
+            backend,
+            r#"DELETE FROM caregiver_grant WHERE id NOT IN (
+                SELECT DISTINCT ON (owner_id, caregiver_id, schedule_id) id FROM caregiver_grant
+                ORDER BY owner_id, caregiver_id, schedule_id,
+                    accepted_at IS NULL, permission = 'manage' DESC, created_at, id
+            )"#
+            .to_string(),
+        ))
+        .await?;
+        // a null schedule is the grant for every schedule, coalesced so those collide too
+        db.execute(Statement::from_string(
+            backend,
+            r#"CREATE UNIQUE INDEX caregiver_grant_unique ON caregiver_grant (
+                owner_id, caregiver_id,
+                COALESCE(schedule_id, '00000000-0000-0000-0000-000000000000'::uuid)
+            )"#
+            .to_string(),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            manager.get_database_backend(),
+            "DROP INDEX caregiver_grant_unique".to_string(),
+        ))
+        .await?;
+        Ok(())
+    }
+}
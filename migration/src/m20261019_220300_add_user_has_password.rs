@@ -0,0 +1,56 @@
+use entity::user::*;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261019_220300_add_user_has_password"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // accounts made by a single sign-on login were linked to their identity in the same
+    // transaction. they are taken to have no password, even if one was set through a reset
+    // since, which only means confirming actions at the provider instead
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .add_column(
+                        ColumnDef::new(Column::HasPassword)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            manager.get_database_backend(),
+            r#"UPDATE "Users" SET has_password = false WHERE EXISTS (
+                SELECT 1 FROM external_identity
+                WHERE external_identity.user_id = "Users".id
+                AND external_identity.created_at - "Users".created < interval '1 minute'
+            )"#
+            .to_string(),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Entity)
+                    .drop_column(Column::HasPassword)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,280 @@
+use crate::models::auth::{Admin, RequireRole};
+use crate::models::error::{ApiError, ErrorBody};
+use crate::models::response::{ApiVersion, Envelope, ResponseBody};
+use crate::utils::fda_sync::{FdaSync, SyncStatus};
+use crate::utils::query_utils::{self, ListParams, Sortable};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use entity::user::Role;
+use entity::{session, user};
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub fn admin_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/users").route(web::get().to(list_users)))
+        .service(web::resource("/users/{id}/disable").route(web::post().to(disable_user)))
+        .service(web::resource("/users/{id}/enable").route(web::post().to(enable_user)))
+        .service(web::resource("/users/{id}/sessions").route(web::delete().to(revoke_sessions)))
+        .service(
+            web::resource("/sync")
+                .route(web::get().to(get_sync))
+                .route(web::post().to(start_sync)),
+        );
+}
+
+#[derive(Serialize, ToSchema)]
+struct AdminUserResponse {
+    id: Uuid,
+    username: String,
+    role: Role,
+    disabled: bool,
+    totp_enabled: bool,
+    sessions: usize,
+}
+
+const USER_SORTABLE: &[Sortable<user::Column>] = &[
+    ("username", user::Column::Username),
+    ("created", user::Column::Created),
+];
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users",
+    tag = "admin",
+    description = "Sortable by username and created, by username by default",
+    params(ListParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "One page of users", body = ResponseBody<Vec<AdminUserResponse>>),
+        (status = 400, description = "Invalid sort, cursor or page", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+    )
+)]
+async fn list_users(
+    _admin: RequireRole<Admin>,
+    db: web::Data<DatabaseConnection>,
+    params: web::Query<ListParams>,
+    version: ApiVersion,
+) -> Result<Envelope<Vec<AdminUserResponse>>, ApiError> {
+    // the legacy route was paged by offset from the start, so it still is, from the first
+    // page by default, rather than returning every user like the other legacy lists. list
+    // is told it's v1 so it pages either way
+    let params = match version {
+        ApiVersion::Legacy => ListParams {
+            page_num: Some(params.page_num.unwrap_or(0)),
+            ..params.into_inner()
+        },
+        ApiVersion::V1 => params.into_inner(),
+    };
+    let (users, pagination) = query_utils::list(
+        user::Entity::find(),
+        &params,
+        USER_SORTABLE,
+        "username",
+        ApiVersion::V1,
+        db.get_ref(),
+    )
+    .await?;
+
+    let sessions = session::Entity::find()
+        .filter(session::Column::UserId.is_in(users.iter().map(|u| u.id)))
+        .all(db.get_ref())
+        .await?;
+    let data = users
+        .into_iter()
+        .map(|u| AdminUserResponse {
+            sessions: sessions.iter().filter(|s| s.user_id == u.id).count(),
+            id: u.id,
+            username: u.username,
+            role: u.role,
+            disabled: u.disabled,
+            totp_enabled: u.totp_enabled,
+        })
+        .collect();
+
+    Ok(Envelope::page(data, pagination))
+}
+
+async fn find_user(id: Uuid, db: &DatabaseConnection) -> Result<user::Model, ApiError> {
+    user::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{id}/disable",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "The user id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user can no longer log in"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The admin tried to disable themselves", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+        (status = 404, description = "No such user", body = ErrorBody),
+    )
+)]
+async fn disable_user(
+    admin: RequireRole<Admin>,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    // an admin disabling themselves could leave nobody able to undo it
+    if *id == admin.user_id {
+        return Err(ApiError::invalid(
+            "id",
+            "own_account",
+            "You can't disable your own account",
+        ));
+    }
+    let model = find_user(*id, db.get_ref()).await?;
+    model.set_disabled(true, db.get_ref()).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{id}/enable",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "The user id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user can log in again"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+        (status = 404, description = "No such user", body = ErrorBody),
+    )
+)]
+async fn enable_user(
+    _admin: RequireRole<Admin>,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(*id, db.get_ref()).await?;
+    model.set_disabled(false, db.get_ref()).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+// logs the user out everywhere, access tokens already issued stay valid until they expire
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/users/{id}/sessions",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "The user id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every session of the user was ended"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+        (status = 404, description = "No such user", body = ErrorBody),
+    )
+)]
+async fn revoke_sessions(
+    _admin: RequireRole<Admin>,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(*id, db.get_ref()).await?;
+    session::Entity::delete_many()
+        .filter(session::Column::UserId.eq(model.id))
+        .exec(db.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/sync",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The state of the last products import", body = ResponseBody<SyncStatus>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+    )
+)]
+async fn get_sync(
+    _admin: RequireRole<Admin>,
+    sync: web::Data<FdaSync>,
+) -> Result<Envelope<SyncStatus>, ApiError> {
+    Ok(Envelope::ok(sync.status()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/sync",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "The import was started", body = ResponseBody<SyncStatus>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The caller isn't an admin", body = ErrorBody),
+        (status = 409, description = "An import is already running", body = ResponseBody<SyncStatus>),
+    )
+)]
+async fn start_sync(
+    _admin: RequireRole<Admin>,
+    db: web::Data<DatabaseConnection>,
+    sync: web::Data<FdaSync>,
+) -> Result<Envelope<SyncStatus>, ApiError> {
+    let started = FdaSync::start(sync.clone(), db.get_ref().clone());
+    let status = match started {
+        true => StatusCode::ACCEPTED,
+        false => StatusCode::CONFLICT,
+    };
+    Ok(Envelope::ok(sync.status()).status(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use entity::user::{self, Role};
+    use sea_orm::{ActiveModelTrait, Set};
+    use serde_json::Value;
+
+    use crate::test_utils::{self, PASSWORD};
+    use crate::utils::oidc::OidcClient;
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn users_are_paged_on_both_versions_and_huge_pages_are_refused() {
+        let db = test_utils::database().await;
+        let admin = test_utils::create_user(&db, PASSWORD).await;
+        let mut active: user::ActiveModel = admin.into();
+        active.role = Set(Role::Admin);
+        let admin = active.update(&db).await.unwrap();
+        let bearer = test_utils::bearer(&db, &admin).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let get = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(bearer.clone())
+                .to_request()
+        };
+
+        let body: Value =
+            test::call_and_read_body_json(&app, get("/api/v1/admin/users?page_size=1")).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert!(body["pagination"]["next_cursor"].is_string());
+        // the legacy route keeps its own page shape
+        let body: Value =
+            test::call_and_read_body_json(&app, get("/api/admin/users?page_size=1")).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["page_num"], 0);
+        assert_eq!(body["page_size"], 1);
+
+        for prefix in ["/api/v1", "/api"] {
+            let uri = format!("{}/admin/users?page_num={}&page_size=200", prefix, u64::MAX);
+            let res = test::call_service(&app, get(&uri)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", prefix);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["details"][0]["code"], "page_too_large");
+        }
+    }
+}
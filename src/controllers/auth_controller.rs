@@ -1,99 +1,1191 @@
+use crate::utils::client_ip::client_ip;
+use crate::utils::hashing::{self, verify_password};
+use crate::utils::login_throttle::{password_keys, LoginThrottle, ThrottleKey};
+use crate::utils::mail_utils::{send_email_verification, send_mail};
+use crate::utils::oidc::{IdTokenClaims, OidcClient, Purpose, STATE_COOKIE};
+use crate::utils::password_policy::check_password;
+use crate::utils::token_utils::{
+    decode_mfa_challenge, encode_mfa_challenge, encode_reauth_token, encode_token,
+};
+use crate::utils::totp;
+
+use actix_web::http::header::{LOCATION, USER_AGENT};
 use actix_web::web;
-use actix_web::{Error, HttpResponse};
-use entity::{session, user};
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
-use crate::utils::is_password_valid;
+use actix_web::{rt, HttpRequest, HttpResponse};
+use chrono::Utc;
+use entity::recovery_code;
+use entity::{email_verification, external_identity, password_reset, session, token, user};
+use log::{error, info};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set, TransactionTrait};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::models::auth::Authenticated;
+use crate::models::dto;
+use crate::models::error::{ApiError, ErrorBody};
 pub fn auth_service(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/signup").route(web::post().to(signup)))
         .service(web::resource("/login").route(web::post().to(login)))
+        .service(web::resource("/mfa").route(web::post().to(verify_mfa)))
+        .service(web::resource("/refresh").route(web::post().to(refresh)))
+        .service(web::resource("/forgot").route(web::post().to(forgot_password)))
+        .service(web::resource("/reset").route(web::post().to(reset_password)))
+        .service(web::resource("/verify_email").route(web::post().to(verify_email)))
+        .service(web::resource("/oidc/login").route(web::get().to(oidc_login)))
+        .service(web::resource("/oidc/callback").route(web::get().to(oidc_callback)))
         .service(web::resource("/logout").route(web::get().to(logout)));
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct SignupRequest {
     username: String,
     password: String,
 }
 
+// long enough for any email address
+const MAX_USERNAME_LENGTH: usize = 254;
+
+fn check_username(username: &str) -> Result<(), ApiError> {
+    let normalized = user::normalize_username(username);
+    if normalized.is_empty() {
+        return Err(ApiError::invalid(
+            "username",
+            "required",
+            "A username is required",
+        ));
+    }
+    if normalized.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(ApiError::invalid(
+            "username",
+            "too_long",
+            format!(
+                "Username must be at most {} characters",
+                MAX_USERNAME_LENGTH
+            ),
+        ));
+    }
+    if normalized
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(ApiError::invalid(
+            "username",
+            "invalid_character",
+            "Username can't contain spaces or control characters",
+        ));
+    }
+    Ok(())
+}
 
+#[utoipa::path(
+    post,
+    path = "/auth/signup",
+    tag = "auth",
+    request_body = SignupRequest,
+    responses(
+        (status = 201, description = "The new account", body = dto::User),
+        (status = 400, description = "Invalid username or password", body = ErrorBody),
+        (status = 409, description = "The username is taken", body = ErrorBody),
+    )
+)]
 async fn signup(
     db: web::Data<DatabaseConnection>,
     body: web::Json<SignupRequest>,
-) -> Result<HttpResponse, Error> {
-    if !is_password_valid(&body.password) {
-        return Ok(HttpResponse::Ok().json("Password does not meet minimum requirements"));
-    }
+) -> Result<HttpResponse, ApiError> {
+    check_username(&body.username)?;
+    check_password("password", &body.password, &body.username)?;
     let conn = db.as_ref();
-    let model = user::ActiveModel {
+    if user::Model::find_by_username(&body.username, conn)
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::UsernameTaken);
+    }
+
+    let password_hash = hashing::hash_password(body.password.to_owned()).await?;
+    // the username is normalized by before_save
+    let user = match (user::ActiveModel {
         username: Set(body.username.to_owned()),
-        password: Set(body.password.to_owned()),
+        password: Set(password_hash),
         ..Default::default()
-    };
-
-    let result = model.save(conn).await;
-
-    match result {
-        Ok(r) => {
-            let user = user::Entity::find_by_id(*r.id.as_ref())
-                .one(conn)
-                .await
-                .unwrap();
-            Ok(HttpResponse::Created().json(user))
+    })
+    .insert(conn)
+    .await
+    {
+        Ok(user) => user,
+        // a signup racing this one for the same name got there first
+        Err(err) => {
+            return match user::Model::find_by_username(&body.username, conn).await? {
+                Some(_) => Err(ApiError::UsernameTaken),
+                None => Err(err.into()),
+            }
         }
-        Err(e) => Ok(HttpResponse::ExpectationFailed().json(e.to_string())),
+    };
+    if user::is_email(&user.username) {
+        send_email_verification(user.clone(), db.clone());
     }
+    Ok(HttpResponse::Created().json(dto::User::from(&user)))
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct TokenResponse {
     token: String,
+    refresh_token: String,
+}
+
+// returned by login instead of a session when the account has two factor authentication
+#[derive(Serialize, Deserialize, ToSchema)]
+struct MfaChallengeResponse {
+    mfa_required: bool,
+    challenge_token: String,
+}
+
+// a session, or a challenge to answer first when the account has two factor authentication
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum LoginResponse {
+    Session(TokenResponse),
+    MfaChallenge(MfaChallengeResponse),
+}
+
+// user agents are capped so a client can't store arbitrary amounts of text
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+fn device(req: &HttpRequest) -> session::Device {
+    session::Device {
+        user_agent: req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+        ip_address: client_ip(req),
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = SignupRequest,
+    responses(
+        (status = 200, description = "Logged in", body = LoginResponse),
+        (status = 403, description = "Wrong credentials or a disabled account", body = ErrorBody),
+        (status = 429, description = "Too many failed attempts", body = ErrorBody),
+    )
+)]
 async fn login(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
     body: web::Json<SignupRequest>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let username = body.username.to_owned();
-    let db_response = user::Entity::find()
-        .filter(user::Column::Username.eq(username.clone()))
+    let keys = password_keys(&username, device(&req).ip_address);
+    // checked before the database or argon2 are touched
+    if let Some(retry_after) = throttle.retry_after(&keys) {
+        return Err(ApiError::TooManyAttempts(retry_after));
+    }
+
+    let user = user::Model::find_by_username(&username, db.as_ref()).await?;
+
+    let verified = match &user {
+        Some(user) => verify_password(user, body.password.to_owned()).await?,
+        None => false,
+    };
+    match user {
+        Some(user) if verified => {
+            throttle.clear(&keys[0]);
+            let response = finish_login(&req, user, db.as_ref()).await?;
+            Ok(HttpResponse::Ok().json(response))
+        }
+        // unknown users and wrong passwords get the same answer
+        _ => {
+            throttle.record_failure(&keys);
+            Err(ApiError::InvalidCredentials)
+        }
+    }
+}
+
+// the steps after the user has proven who they are, shared by every way of logging in
+async fn finish_login(
+    req: &HttpRequest,
+    user: user::Model,
+    db: &DatabaseConnection,
+) -> Result<LoginResponse, ApiError> {
+    if user.disabled {
+        return Err(ApiError::AccountDisabled);
+    }
+    if user.totp_enabled {
+        return Ok(LoginResponse::MfaChallenge(MfaChallengeResponse {
+            mfa_required: true,
+            challenge_token: encode_mfa_challenge(user.id).map_err(ApiError::internal)?,
+        }));
+    }
+    Ok(LoginResponse::Session(start_session(req, user, db).await?))
+}
+
+async fn start_session(
+    req: &HttpRequest,
+    user: user::Model,
+    db: &DatabaseConnection,
+) -> Result<TokenResponse, ApiError> {
+    let session = user.new_login_session(device(req), db).await?;
+    let refresh_token = session.issue_refresh_token(db).await?;
+    Ok(TokenResponse {
+        token: encode_token(&session.access_claims(user.role)).map_err(ApiError::internal)?,
+        refresh_token,
+    })
+}
+
+// second login step, takes either a code from the authenticator or an unused recovery code
+#[derive(Serialize, Deserialize, ToSchema)]
+struct MfaRequest {
+    challenge_token: String,
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/mfa",
+    tag = "auth",
+    request_body = MfaRequest,
+    responses(
+        (status = 200, description = "Logged in", body = TokenResponse),
+        (status = 400, description = "No code was given", body = ErrorBody),
+        (status = 401, description = "The challenge token is invalid or expired", body = ErrorBody),
+        (status = 403, description = "The code is wrong", body = ErrorBody),
+        (status = 429, description = "Too many failed attempts", body = ErrorBody),
+    )
+)]
+async fn verify_mfa(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<MfaRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let claims = decode_mfa_challenge(&body.challenge_token).map_err(|_| ApiError::InvalidToken)?;
+    // a challenge token lasts long enough to guess a lot of six digit codes
+    let keys = [ThrottleKey::Mfa(claims.mfa_user_id)];
+    if let Some(retry_after) = throttle.retry_after(&keys) {
+        return Err(ApiError::TooManyAttempts(retry_after));
+    }
+    let user = match user::Entity::find_by_id(claims.mfa_user_id)
         .one(db.as_ref())
-        .await;
+        .await?
+    {
+        Some(user) if user.totp_enabled && !user.disabled => user,
+        _ => return Err(ApiError::InvalidToken),
+    };
+
+    let verified = match (&body.code, &body.recovery_code) {
+        (Some(code), _) => {
+            let secret = user
+                .totp_secret
+                .as_deref()
+                .map(totp::decrypt_secret)
+                .ok_or_else(|| ApiError::internal(anyhow::anyhow!("TOTP secret is missing")))?
+                .map_err(ApiError::internal)?;
+            match totp::verify(&secret, code, Utc::now().timestamp()) {
+                Some(step) => user.claim_totp_step(step, db.as_ref()).await?,
+                None => false,
+            }
+        }
+        (None, Some(code)) => recovery_code::Model::redeem(user.id, code, db.as_ref()).await?,
+        (None, None) => return Err(ApiError::invalid("code", "required", "A code is required")),
+    };
+    if !verified {
+        throttle.record_failure(&keys);
+        return Err(ApiError::InvalidCredentials);
+    }
+    throttle.clear(&keys[0]);
+    let response = start_session(&req, user, db.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
 
-    match db_response {
-        //no error returned from db
-        Ok(result) => match result {
-            //user returned from db
-            Some(user) => {
-                //if password matches
-                if user.verify_password(body.password.to_owned()).unwrap() {
-                    let token = user.new_login_session(db.as_ref()).await.unwrap();
-                    return Ok(HttpResponse::Ok().json(TokenResponse {
-                        token: token.to_string(),
-                    }));
-                //incorrect password
-                } else {
-                    return Ok(HttpResponse::Forbidden().body("Forbidden"));
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "A new access and refresh token", body = TokenResponse),
+        (status = 401, description = "The refresh token is invalid, expired or reused", body = ErrorBody),
+    )
+)]
+async fn refresh(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ApiError> {
+    match session::Model::refresh(&body.refresh_token, device(&req), db.as_ref()).await? {
+        Some((claims, refresh_token)) => Ok(HttpResponse::Ok().json(TokenResponse {
+            token: encode_token(&claims).map_err(ApiError::internal)?,
+            refresh_token,
+        })),
+        None => Err(ApiError::InvalidToken),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ForgotPasswordRequest {
+    username: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/forgot",
+    tag = "auth",
+    request_body = ForgotPasswordRequest,
+    responses(
+        (status = 200, description = "A reset link is mailed if the account exists"),
+    )
+)]
+async fn forgot_password(
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<ForgotPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user = user::Model::find_by_username(&body.username, db.as_ref()).await?;
+
+    // the reply is the same whether or not the account exists, so usernames can't be probed.
+    // resets are only mailed to addresses the owner has verified, anyone could have signed up
+    // with an address that isn't theirs
+    if let Some(user) = user {
+        if user::is_email(&user.username) && user.email_verified_at.is_some() {
+            rt::spawn(async move {
+                let token = match password_reset::Model::create(user.id, db.as_ref()).await {
+                    Ok(token) => token,
+                    Err(err) => return error!("Failed to create password reset: {:?}", err),
+                };
+                let link = match std::env::var("PASSWORD_RESET_URL") {
+                    Ok(url) => format!("{}{}", url, token),
+                    Err(_) => token,
+                };
+                let body = format!(
+                    "Use this link to reset your password, it expires in an hour:\n\n{}",
+                    link
+                );
+                if let Err(err) = send_mail(&user.username, "Reset your password", body).await {
+                    error!("Failed to send password reset: {:?}", err);
                 }
+            });
+        }
+    }
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+fn invalid_reset_token() -> ApiError {
+    ApiError::invalid(
+        "token",
+        "invalid_token",
+        "Reset token is invalid or expired",
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/reset",
+    tag = "auth",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "The password was changed"),
+        (status = 400, description = "Invalid token or password", body = ErrorBody),
+    )
+)]
+async fn reset_password(
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<ResetPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user = password_reset::Model::user(&body.token, db.as_ref())
+        .await?
+        .ok_or_else(invalid_reset_token)?;
+    check_password("password", &body.password, &user.username)?;
+    let password_hash = hashing::hash_password(body.password.to_owned()).await?;
+    match password_reset::Model::redeem(&body.token, password_hash, db.as_ref()).await? {
+        true => Ok(HttpResponse::Ok().body("")),
+        false => Err(invalid_reset_token()),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/verify_email",
+    tag = "auth",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "The email address is verified"),
+        (status = 400, description = "The token is invalid or expired", body = ErrorBody),
+    )
+)]
+async fn verify_email(
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<VerifyEmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    match email_verification::Model::redeem(&body.token, db.as_ref()).await? {
+        true => Ok(HttpResponse::Ok().body("")),
+        false => Err(ApiError::invalid(
+            "token",
+            "invalid_token",
+            "Verification token is invalid or expired",
+        )),
+    }
+}
+
+// sends the browser to the identity provider
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/login",
+    tag = "auth",
+    responses(
+        (status = 302, description = "Redirect to the identity provider"),
+        (status = 404, description = "Single sign-on isn't configured", body = ErrorBody),
+        (status = 502, description = "The identity provider is unavailable", body = ErrorBody),
+    )
+)]
+async fn oidc_login(oidc: web::Data<OidcClient>) -> Result<HttpResponse, ApiError> {
+    if oidc.config.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    match oidc.authorization_url(Purpose::Login).await {
+        Ok((url, cookie)) => Ok(HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .cookie(cookie)
+            .finish()),
+        Err(err) => {
+            error!("Failed to start single sign-on: {:?}", err);
+            Err(ApiError::Upstream)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    // set by the provider when the user refused or the request was invalid
+    error: Option<String>,
+}
+
+// proof of logging in at the provider again, sent along with a destructive action
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ReauthResponse {
+    reauth_token: String,
+}
+
+// what the callback returns for each purpose of the login
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum CallbackResponse {
+    Login(LoginResponse),
+    Identity(dto::Identity),
+    Reauth(ReauthResponse),
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/callback",
+    tag = "auth",
+    params(OidcCallback),
+    responses(
+        (status = 200, description = "Logged in, the linked identity when linking, or a reauth token when confirming an action", body = CallbackResponse),
+        (status = 401, description = "Single sign-on failed", body = ErrorBody),
+        (status = 403, description = "The identity isn't linked to the account confirming an action", body = ErrorBody),
+        (status = 404, description = "Single sign-on isn't configured", body = ErrorBody),
+        (status = 409, description = "The identity or username belongs to another account", body = ErrorBody),
+    )
+)]
+async fn oidc_callback(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    oidc: web::Data<OidcClient>,
+    query: web::Query<OidcCallback>,
+) -> Result<HttpResponse, ApiError> {
+    if oidc.config.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    if let Some(err) = &query.error {
+        info!("Identity provider refused the login: {}", err);
+        return Err(ApiError::SsoFailed);
+    }
+    let (code, state) = match (&query.code, &query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            return Err(ApiError::invalid(
+                "code",
+                "required",
+                "code and state are required",
+            ))
+        }
+    };
+    // the callback has to come back to the browser that started the login, otherwise an
+    // attacker could have a victim finish a login the attacker started
+    match req.cookie(STATE_COOKIE) {
+        Some(cookie) if cookie.value() == state.as_str() => (),
+        _ => {
+            info!("Single sign-on callback without the state cookie of its login");
+            return Err(ApiError::SsoFailed);
+        }
+    }
+    let completion = match oidc.complete(code, state).await {
+        Ok(completion) => completion,
+        Err(err) => {
+            info!("Single sign-on failed: {:?}", err);
+            return Err(ApiError::SsoFailed);
+        }
+    };
+    let claims = completion.claims;
+    let identity = external_identity::Model::find(&claims.iss, &claims.sub, db.as_ref()).await?;
+    let mut response = HttpResponse::Ok();
+    response.cookie(oidc.removal_cookie());
+
+    match completion.purpose {
+        Purpose::Login => {
+            let user = match identity {
+                Some(identity) => user::Entity::find_by_id(identity.user_id)
+                    .one(db.as_ref())
+                    .await?
+                    .ok_or_else(|| ApiError::internal(anyhow::anyhow!("Identity has no user")))?,
+                None => create_sso_user(&claims, db.as_ref()).await?,
+            };
+            let login = finish_login(&req, user, db.as_ref()).await?;
+            Ok(response.json(CallbackResponse::Login(login)))
+        }
+        // a logged in user linking the identity to their account
+        Purpose::Link(user_id) => match identity {
+            Some(identity) if identity.user_id == user_id => {
+                Ok(response.json(CallbackResponse::Identity(dto::Identity::from(&identity))))
             }
-            //no user found in db
-            None => return Ok(HttpResponse::Forbidden().body("Forbidden")),
+            Some(_) => Err(ApiError::IdentityLinked),
+            None => {
+                let identity = external_identity::Model::link(
+                    user_id,
+                    &claims.iss,
+                    &claims.sub,
+                    claims.email.clone(),
+                    db.as_ref(),
+                )
+                .await?;
+                Ok(response.json(CallbackResponse::Identity(dto::Identity::from(&identity))))
+            }
+        },
+        // only an identity of the account itself confirms its actions
+        Purpose::Reauth {
+            user_id,
+            session_id,
+        } => match identity {
+            Some(identity) if identity.user_id == user_id => {
+                let reauth_token =
+                    encode_reauth_token(user_id, session_id).map_err(ApiError::internal)?;
+                Ok(response.json(CallbackResponse::Reauth(ReauthResponse { reauth_token })))
+            }
+            _ => Err(ApiError::Forbidden),
         },
-        // database error
-        Err(err) => return Ok(HttpResponse::InternalServerError().body(err.to_string())),
+    }
+}
+
+// first login of an unlinked identity. an existing account with the same username is never
+// taken over, its owner has to log in and link the identity themselves
+async fn create_sso_user(
+    claims: &IdTokenClaims,
+    db: &DatabaseConnection,
+) -> Result<user::Model, ApiError> {
+    let (username, email_verified_at) = match (&claims.email, claims.email_verified) {
+        // the provider already verified the address
+        (Some(email), true) => (email.clone(), Some(Utc::now())),
+        _ => (claims.sub.clone(), None),
     };
+    if user::Model::find_by_username(&username, db)
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::UsernameTaken);
+    }
+
+    // sso users get an unguessable password, they can set a real one through a reset
+    let password_hash = hashing::hash_password(token::generate()).await?;
+    let txn = db.begin().await?;
+    let user = user::ActiveModel {
+        username: Set(username),
+        password: Set(password_hash),
+        has_password: Set(false),
+        email_verified_at: Set(email_verified_at),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+    external_identity::Model::link(
+        user.id,
+        &claims.iss,
+        &claims.sub,
+        claims.email.clone(),
+        &txn,
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(user)
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/logout",
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The session was ended"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
 async fn logout(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
-) -> Result<HttpResponse, Error> {
-    match session::Entity::delete_by_id(user.session_id)
+) -> Result<HttpResponse, ApiError> {
+    session::Entity::delete_by_id(user.session_id)
         .exec(db.as_ref())
-        .await
-    {
-        Ok(_) => Ok(HttpResponse::Ok().body("")),
-        Err(_) => Ok(HttpResponse::InternalServerError().body("")),
+        .await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use actix_web::cookie::Cookie;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::header::LOCATION;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use chrono::Utc;
+    use entity::{session, user};
+    use sea_orm::prelude::Uuid;
+    use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set};
+    use serde_json::{json, Value};
+
+    use crate::test_utils::{self, MockIssuer, SmtpSink, Tamper, PASSWORD};
+    use crate::utils::oidc::OidcClient;
+
+    const NEW_PASSWORD: &str = "a different horse, battery and staple";
+
+    // the reset token, which is mailed on its own line without PASSWORD_RESET_URL
+    fn reset_token(message: &str) -> String {
+        message
+            .lines()
+            .find(|line| line.len() == 64 && line.chars().all(|c| c.is_ascii_hexdigit()))
+            .expect("the mail holds no reset token")
+            .to_string()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn forgotten_password_is_reset_from_the_mailed_token() {
+        let db = test_utils::database().await;
+        let mut sink = SmtpSink::start().await;
+        std::env::set_var("SMTP_URL", &sink.url);
+        std::env::remove_var("PASSWORD_RESET_URL");
+        let user = test_utils::create_user(&db, PASSWORD).await;
+        let app = test::init_service(test_utils::app(db.clone(), OidcClient::from_env())).await;
+        let forgot = || {
+            test::TestRequest::post()
+                .uri("/auth/forgot")
+                .set_json(json!({ "username": user.username }))
+                .to_request()
+        };
+
+        // nothing is mailed until the address is verified, so the first mail is for the
+        // second request
+        assert_eq!(
+            test::call_service(&app, forgot()).await.status(),
+            StatusCode::OK
+        );
+        let mut verified: user::ActiveModel = user.clone().into();
+        verified.email_verified_at = Set(Some(Utc::now()));
+        verified.update(&db).await.unwrap();
+        assert_eq!(
+            test::call_service(&app, forgot()).await.status(),
+            StatusCode::OK
+        );
+
+        let message = sink.next_message().await;
+        assert!(message.contains(&format!("To: {}", user.username)));
+        assert!(message.contains("Subject: Reset your password"));
+        let token = reset_token(&message);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/reset")
+            .set_json(json!({ "token": token, "password": NEW_PASSWORD }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // the token is spent
+        let req = test::TestRequest::post()
+            .uri("/auth/reset")
+            .set_json(json!({ "token": token, "password": PASSWORD }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        for (password, status) in [
+            (PASSWORD, StatusCode::FORBIDDEN),
+            (NEW_PASSWORD, StatusCode::OK),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/auth/login")
+                .set_json(json!({ "username": user.username, "password": password }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+
+    // the provider url and state cookie of a login that was started, from the redirect of
+    // /auth/oidc/login or the json of the routes that start one for a logged in user
+    async fn started<B: MessageBody>(res: ServiceResponse<B>) -> (String, Cookie<'static>) {
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let url = match res.headers().get(LOCATION) {
+            Some(location) => location.to_str().unwrap().to_string(),
+            None => {
+                let body: Value = test::read_body_json(res).await;
+                body["data"]["authorization_url"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+        (url, cookie)
+    }
+
+    // the browser's trip through the provider, ending in the request to the callback
+    async fn callback(url: &str, cookie: Option<Cookie<'static>>) -> test::TestRequest {
+        let res = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::FOUND);
+        let location = res.headers()[LOCATION.as_str()].to_str().unwrap();
+        let location = reqwest::Url::parse(location).unwrap();
+        let mut req = test::TestRequest::get().uri(&format!(
+            "{}?{}",
+            location.path(),
+            location.query().unwrap()
+        ));
+        if let Some(cookie) = cookie {
+            req = req.cookie(cookie);
+        }
+        req
+    }
+
+    fn login_request() -> test::TestRequest {
+        test::TestRequest::get().uri("/auth/oidc/login")
+    }
+
+    fn new_identity(issuer: &MockIssuer) -> (String, String) {
+        let subject = Uuid::new_v4().to_string();
+        let email = format!("{}@example.com", Uuid::new_v4());
+        issuer.login(&subject, &email);
+        (subject, email)
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sso_logs_in_to_the_account_of_the_identity() {
+        let db = test_utils::database().await;
+        let issuer = MockIssuer::start().await;
+        let (_, email) = new_identity(&issuer);
+        let app = test::init_service(test_utils::app(db, issuer.client())).await;
+
+        let mut accounts = vec![];
+        for _ in 0..2 {
+            let res = test::call_service(&app, login_request().to_request()).await;
+            assert_eq!(res.status(), StatusCode::FOUND);
+            let (url, cookie) = started(res).await;
+            let res =
+                test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: Value = test::read_body_json(res).await;
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/user")
+                .insert_header((
+                    "Authorization",
+                    format!("Bearer {}", body["token"].as_str().unwrap()),
+                ))
+                .to_request();
+            let body: Value = test::call_and_read_body_json(&app, req).await;
+            let user = &body["data"]["user"];
+            assert_eq!(user["username"], email.as_str());
+            assert_eq!(user["has_password"], false);
+            accounts.push(user["id"].clone());
+        }
+        // the second login found the account the first one made
+        assert_eq!(accounts[0], accounts[1]);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sso_refuses_logins_that_dont_check_out() {
+        let db = test_utils::database().await;
+        let issuer = MockIssuer::start().await;
+        new_identity(&issuer);
+        let app = test::init_service(test_utils::app(db, issuer.client())).await;
+
+        for tamper in [
+            Tamper::Issuer,
+            Tamper::Audience,
+            Tamper::Nonce,
+            Tamper::Challenge,
+        ] {
+            issuer.tamper(tamper);
+            let (url, cookie) =
+                started(test::call_service(&app, login_request().to_request()).await).await;
+            let res =
+                test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{:?}", tamper);
+        }
+
+        // the callback has to come with the cookie of its own login
+        issuer.tamper(Tamper::None);
+        let (url, _) = started(test::call_service(&app, login_request().to_request()).await).await;
+        let res = test::call_service(&app, callback(&url, None).await.to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let (_, other) =
+            started(test::call_service(&app, login_request().to_request()).await).await;
+        let (url, _) = started(test::call_service(&app, login_request().to_request()).await).await;
+        let res = test::call_service(&app, callback(&url, Some(other)).await.to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sso_needs_discovery_of_the_configured_issuer() {
+        let db = test_utils::database().await;
+        let issuer = MockIssuer::start().await;
+        issuer.tamper(Tamper::Discovery);
+        let app = test::init_service(test_utils::app(db, issuer.client())).await;
+
+        let res = test::call_service(&app, login_request().to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn identities_are_linked_to_one_account() {
+        let db = test_utils::database().await;
+        let (owner, owner_bearer) = test_utils::signed_in(&db).await;
+        let (_, other_bearer) = test_utils::signed_in(&db).await;
+        let issuer = MockIssuer::start().await;
+        new_identity(&issuer);
+        let app = test::init_service(test_utils::app(db, issuer.client())).await;
+
+        for (bearer, status) in [
+            (&owner_bearer, StatusCode::OK),
+            // linking again changes nothing
+            (&owner_bearer, StatusCode::OK),
+            (&other_bearer, StatusCode::CONFLICT),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/user/identities/oidc")
+                .insert_header(bearer.clone())
+                .to_request();
+            let (url, cookie) = started(test::call_service(&app, req).await).await;
+            let res =
+                test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+            assert_eq!(res.status(), status);
+        }
+
+        // and logging in with it reaches the account it's linked to
+        let (url, cookie) =
+            started(test::call_service(&app, login_request().to_request()).await).await;
+        let res = test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+        let body: Value = test::read_body_json(res).await;
+        let req = test::TestRequest::get()
+            .uri("/api/v1/user")
+            .insert_header((
+                "Authorization",
+                format!("Bearer {}", body["token"].as_str().unwrap()),
+            ))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"]["user"]["id"], owner.id.to_string());
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn accounts_without_a_password_confirm_at_the_provider() {
+        let db = test_utils::database().await;
+        let issuer = MockIssuer::start().await;
+        new_identity(&issuer);
+        let app = test::init_service(test_utils::app(db, issuer.client())).await;
+
+        // two sessions of an account made by single sign-on
+        let mut bearers = vec![];
+        for _ in 0..2 {
+            let (url, cookie) =
+                started(test::call_service(&app, login_request().to_request()).await).await;
+            let res =
+                test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+            let body: Value = test::read_body_json(res).await;
+            let token = body["token"].as_str().unwrap();
+            bearers.push(("Authorization", format!("Bearer {}", token)));
+        }
+
+        // its only identity can't be unlinked
+        let req = test::TestRequest::get()
+            .uri("/api/v1/user/identities")
+            .insert_header(bearers[0].clone())
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        let identity = body["data"][0]["id"].as_str().unwrap();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/v1/user/identities/{}", identity))
+            .insert_header(bearers[0].clone())
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CONFLICT
+        );
+
+        let delete = |bearer: &(&'static str, String), body: Value| {
+            test::TestRequest::delete()
+                .uri("/api/v1/user")
+                .insert_header(bearer.clone())
+                .set_json(body)
+                .to_request()
+        };
+        let req = delete(&bearers[0], json!({ "password": "a guess" }));
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let reauth = |tamper| {
+            issuer.tamper(tamper);
+            test::TestRequest::post()
+                .uri("/api/v1/user/reauth/oidc")
+                .insert_header(bearers[0].clone())
+                .to_request()
+        };
+        // an old login at the provider doesn't count
+        let (url, cookie) = started(test::call_service(&app, reauth(Tamper::AuthTime)).await).await;
+        let res = test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let (url, cookie) = started(test::call_service(&app, reauth(Tamper::None)).await).await;
+        let res = test::call_service(&app, callback(&url, Some(cookie)).await.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        let reauth_token = body["reauth_token"].as_str().unwrap();
+
+        // the token only confirms for the session that asked for it
+        for (bearer, status) in [
+            (&bearers[1], StatusCode::FORBIDDEN),
+            (&bearers[0], StatusCode::OK),
+        ] {
+            let req = delete(bearer, json!({ "reauth_token": reauth_token }));
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+
+    fn refresh(token: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(json!({ "refresh_token": token }))
+    }
+
+    // a new session of the user and its first refresh token
+    async fn refresh_token(
+        db: &DatabaseConnection,
+        user: &user::Model,
+    ) -> (session::Model, String) {
+        let session = user
+            .new_login_session(session::Device::default(), db)
+            .await
+            .unwrap();
+        let token = session.issue_refresh_token(db).await.unwrap();
+        (session, token)
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn refresh_tokens_rotate_and_a_replay_revokes_the_session() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, PASSWORD).await;
+        let (session, first) = refresh_token(&db, &user).await;
+        let app = test::init_service(test_utils::app(db.clone(), OidcClient::from_env())).await;
+
+        let res = test::call_service(&app, refresh(&first).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(res).await;
+        let second = body["refresh_token"].as_str().unwrap().to_string();
+        assert_ne!(second, first);
+        let req = test::TestRequest::get()
+            .uri("/api/v1/user")
+            .insert_header((
+                "Authorization",
+                format!("Bearer {}", body["token"].as_str().unwrap()),
+            ))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // the first token was used, so whoever sends it again may have stolen it
+        let res = test::call_service(&app, refresh(&first).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(session::Entity::find_by_id(session.session_id)
+            .one(&db)
+            .await
+            .unwrap()
+            .is_none());
+        // which ends the session for the token that replaced it too
+        let res = test::call_service(&app, refresh(&second).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn expired_and_revoked_refresh_tokens_are_rejected() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, PASSWORD).await;
+        let (expired, expired_token) = refresh_token(&db, &user).await;
+        let mut active: session::ActiveModel = expired.into();
+        active.exp = Set(Utc::now().timestamp() - 1);
+        active.update(&db).await.unwrap();
+        let (revoked, revoked_token) = refresh_token(&db, &user).await;
+        session::Entity::delete_by_id(revoked.session_id)
+            .exec(&db)
+            .await
+            .unwrap();
+        let disabled = test_utils::create_user(&db, PASSWORD).await;
+        let (_, disabled_token) = refresh_token(&db, &disabled).await;
+        let mut active: user::ActiveModel = disabled.into();
+        active.disabled = Set(true);
+        active.update(&db).await.unwrap();
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        for token in [
+            expired_token,
+            revoked_token,
+            disabled_token,
+            "not a token".to_string(),
+        ] {
+            let res = test::call_service(&app, refresh(&token).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", token);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], "invalid_token");
+        }
+    }
+
+    fn login(username: &str, ip: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/auth/login")
+            .peer_addr(format!("{}:4000", ip).parse().unwrap())
+            .set_json(json!({ "username": username, "password": "not the password" }))
+    }
+
+    fn retry_after(res: &ServiceResponse<impl MessageBody>) -> Option<&str> {
+        res.headers()
+            .get("Retry-After")
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn logins_are_throttled_per_username_and_address() {
+        let db = test_utils::database().await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        // unknown usernames fail like wrong passwords, without the wait for argon2
+        let username = format!("{}@example.com", Uuid::new_v4());
+        for attempt in 1..=6 {
+            let res = test::call_service(
+                &app,
+                login(&username, &format!("192.0.2.{}", attempt)).to_request(),
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "attempt {}", attempt);
+        }
+        let res = test::call_service(&app, login(&username, "192.0.2.7").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(retry_after(&res), Some("1"));
+
+        // an address gets more attempts, across usernames
+        for attempt in 1..=20 {
+            let res = test::call_service(
+                &app,
+                login(&Uuid::new_v4().to_string(), "198.51.100.1").to_request(),
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "attempt {}", attempt);
+        }
+        let res = test::call_service(
+            &app,
+            login(&Uuid::new_v4().to_string(), "198.51.100.1").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = test::call_service(
+            &app,
+            login(&Uuid::new_v4().to_string(), "198.51.100.1").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(retry_after(&res), Some("1"));
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "too_many_attempts");
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn usernames_differing_in_case_or_compatibility_form_are_taken() {
+        let db = test_utils::database().await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let id = Uuid::new_v4();
+        let signup = |username: String| {
+            test::TestRequest::post()
+                .uri("/auth/signup")
+                .set_json(json!({ "username": username, "password": PASSWORD }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, signup(format!("Foo-{}@x.com", id))).await;
+        assert!(res.status().is_success());
+        // case, surrounding spaces and fullwidth letters all fold to the same name
+        for username in [
+            format!("foo-{}@X.com", id),
+            format!(" FOO-{}@x.COM ", id),
+            format!("ｆｏｏ-{}@ｘ.com", id),
+        ] {
+            let res = test::call_service(&app, signup(username.clone())).await;
+            assert_eq!(res.status(), StatusCode::CONFLICT, "{}", username);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], "username_taken");
+        }
+        // as does the ﬁ ligature to fi
+        let res = test::call_service(&app, signup(format!("ﬁle-{}@x.com", id))).await;
+        assert!(res.status().is_success());
+        let res = test::call_service(&app, signup(format!("FILE-{}@x.com", id))).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        // and any spelling logs in
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": format!("FOO-{}@X.COM", id), "password": PASSWORD }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
     }
 }
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use crate::models::auth::Authenticated;
+use crate::models::error::{ApiError, ErrorBody};
+use crate::models::response::{Envelope, ResponseBody};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use entity::caregiver_grant::{self, Permission};
+use entity::{schedule, user};
+use sea_orm::prelude::Uuid;
+use sea_orm::{
+    ActiveModelBehavior, ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub fn caregiver_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/grants")
+            .route(web::get().to(get_grants))
+            .route(web::post().to(invite_caregiver)),
+    )
+    .service(web::resource("/grants/{id}").route(web::delete().to(delete_grant)))
+    .service(web::resource("/invitations").route(web::get().to(get_invitations)))
+    .service(web::resource("/invitations/{id}/accept").route(web::post().to(accept_invitation)));
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct GrantResponse {
+    id: Uuid,
+    owner_id: Uuid,
+    owner_username: Option<String>,
+    caregiver_id: Uuid,
+    caregiver_username: Option<String>,
+    permission: Permission,
+    schedule_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+    accepted_at: Option<DateTime<Utc>>,
+}
+
+// adds the usernames of both sides, so each user can see who they share with
+async fn grant_responses(
+    grants: Vec<caregiver_grant::Model>,
+    db: &DatabaseConnection,
+) -> Result<Vec<GrantResponse>, ApiError> {
+    let ids = grants
+        .iter()
+        .flat_map(|grant| [grant.owner_id, grant.caregiver_id]);
+    let usernames: HashMap<Uuid, String> = user::Entity::find()
+        .filter(user::Column::Id.is_in(ids))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user.username))
+        .collect();
+
+    Ok(grants
+        .into_iter()
+        .map(|grant| GrantResponse {
+            id: grant.id,
+            owner_id: grant.owner_id,
+            owner_username: usernames.get(&grant.owner_id).cloned(),
+            caregiver_id: grant.caregiver_id,
+            caregiver_username: usernames.get(&grant.caregiver_id).cloned(),
+            permission: grant.permission,
+            schedule_id: grant.schedule_id,
+            created_at: grant.created_at,
+            accepted_at: grant.accepted_at,
+        })
+        .collect())
+}
+
+// grants the user has given to caregivers
+#[utoipa::path(
+    get,
+    path = "/api/v1/caregiver/grants",
+    tag = "caregiver",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Grants the caller gave, oldest first", body = ResponseBody<Vec<GrantResponse>>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn get_grants(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<Envelope<Vec<GrantResponse>>, ApiError> {
+    let grants = caregiver_grant::Entity::find()
+        .filter(caregiver_grant::Column::OwnerId.eq(user.user_id))
+        .order_by_asc(caregiver_grant::Column::CreatedAt)
+        .all(db.get_ref())
+        .await?;
+    Ok(Envelope::ok(grant_responses(grants, db.get_ref()).await?))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct InviteRequest {
+    username: String,
+    permission: Permission,
+    // share a single schedule, or every schedule when left out
+    schedule_id: Option<Uuid>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/caregiver/grants",
+    tag = "caregiver",
+    request_body = InviteRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "The pending grant", body = ResponseBody<GrantResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The caller invited themselves", body = ErrorBody),
+        (status = 404, description = "No such user or schedule", body = ErrorBody),
+        (status = 409, description = "The caregiver already has a grant for these schedules", body = ErrorBody),
+    )
+)]
+async fn invite_caregiver(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    body: web::Json<InviteRequest>,
+) -> Result<Envelope<GrantResponse>, ApiError> {
+    let caregiver = user::Model::find_by_username(&body.username, db.get_ref())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if caregiver.id == user.user_id {
+        return Err(ApiError::invalid(
+            "username",
+            "self_invite",
+            "You can't invite yourself",
+        ));
+    }
+    if let Some(schedule_id) = body.schedule_id {
+        match schedule::Entity::find_by_id(schedule_id)
+            .one(db.get_ref())
+            .await?
+        {
+            Some(schedule) if schedule.user_id == user.user_id => (),
+            _ => return Err(ApiError::NotFound),
+        }
+    }
+
+    // change the permission of a grant by revoking it and inviting again
+    if grant_exists(user.user_id, caregiver.id, body.schedule_id, db.get_ref()).await? {
+        return Err(ApiError::GrantExists);
+    }
+
+    let mut grant = caregiver_grant::ActiveModel::new();
+    grant.owner_id = Set(user.user_id);
+    grant.caregiver_id = Set(caregiver.id);
+    grant.permission = Set(body.permission);
+    grant.schedule_id = Set(body.schedule_id);
+    let grant = match grant.insert(db.get_ref()).await {
+        Ok(grant) => grant,
+        // an invitation racing this one got there first
+        Err(err) => {
+            return match grant_exists(user.user_id, caregiver.id, body.schedule_id, db.get_ref())
+                .await?
+            {
+                true => Err(ApiError::GrantExists),
+                false => Err(err.into()),
+            }
+        }
+    };
+    let mut response = grant_responses(vec![grant], db.get_ref()).await?;
+    Ok(Envelope::created(response.remove(0)))
+}
+
+async fn grant_exists(
+    owner_id: Uuid,
+    caregiver_id: Uuid,
+    schedule_id: Option<Uuid>,
+    db: &DatabaseConnection,
+) -> Result<bool, ApiError> {
+    let schedule = match schedule_id {
+        Some(id) => caregiver_grant::Column::ScheduleId.eq(id),
+        None => caregiver_grant::Column::ScheduleId.is_null(),
+    };
+    Ok(caregiver_grant::Entity::find()
+        .filter(caregiver_grant::Column::OwnerId.eq(owner_id))
+        .filter(caregiver_grant::Column::CaregiverId.eq(caregiver_id))
+        .filter(schedule)
+        .one(db)
+        .await?
+        .is_some())
+}
+
+// the owner revokes a grant, or the caregiver gives it up or declines the invitation
+#[utoipa::path(
+    delete,
+    path = "/api/v1/caregiver/grants/{id}",
+    tag = "caregiver",
+    params(("id" = Uuid, Path, description = "The grant id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The grant was removed"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such grant", body = ErrorBody),
+    )
+)]
+async fn delete_grant(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = caregiver_grant::Entity::delete_many()
+        .filter(caregiver_grant::Column::Id.eq(*id))
+        .filter(
+            Condition::any()
+                .add(caregiver_grant::Column::OwnerId.eq(user.user_id))
+                .add(caregiver_grant::Column::CaregiverId.eq(user.user_id)),
+        )
+        .exec(db.get_ref())
+        .await?
+        .rows_affected;
+    match deleted {
+        0 => Err(ApiError::NotFound),
+        _ => Ok(HttpResponse::Ok().body("")),
+    }
+}
+
+// grants other users have given the user, pending and accepted
+#[utoipa::path(
+    get,
+    path = "/api/v1/caregiver/invitations",
+    tag = "caregiver",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Grants given to the caller, oldest first", body = ResponseBody<Vec<GrantResponse>>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn get_invitations(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<Envelope<Vec<GrantResponse>>, ApiError> {
+    let grants = caregiver_grant::Entity::find()
+        .filter(caregiver_grant::Column::CaregiverId.eq(user.user_id))
+        .order_by_asc(caregiver_grant::Column::CreatedAt)
+        .all(db.get_ref())
+        .await?;
+    Ok(Envelope::ok(grant_responses(grants, db.get_ref()).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/caregiver/invitations/{id}/accept",
+    tag = "caregiver",
+    params(("id" = Uuid, Path, description = "The grant id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The invitation was accepted"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such invitation", body = ErrorBody),
+    )
+)]
+async fn accept_invitation(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let grant = match caregiver_grant::Entity::find_by_id(*id)
+        .one(db.get_ref())
+        .await?
+    {
+        Some(grant) if grant.caregiver_id == user.user_id => grant,
+        _ => return Err(ApiError::NotFound),
+    };
+    if grant.accepted_at.is_some() {
+        return Ok(HttpResponse::Ok().body(""));
+    }
+    let mut active: caregiver_grant::ActiveModel = grant.into();
+    active.accepted_at = Set(Some(Utc::now()));
+    active.update(db.get_ref()).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::json;
+
+    use crate::test_utils::{self, PASSWORD};
+    use crate::utils::oidc::OidcClient;
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn caregivers_are_invited_once() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let caregiver = test_utils::create_user(&db, PASSWORD).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        for (permission, status) in [
+            ("read", StatusCode::CREATED),
+            ("read", StatusCode::CONFLICT),
+            ("manage", StatusCode::CONFLICT),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/caregiver/grants")
+                .insert_header(bearer.clone())
+                .set_json(json!({ "username": caregiver.username, "permission": permission }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use actix_web::web;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use super::{
+    admin_controller, auth_controller, caregiver_controller, drug_controller, event_controller,
+    fhir_controller, schedule_controller, user_controller, well_known_controller,
+};
+
+// every route has to be listed here, schemas are collected from the handlers' annotations
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "DrugData", description = "Medication schedules, pill counts and FDA drug data"),
+    modifiers(&BearerAuth),
+    paths(
+        auth_controller::signup,
+        auth_controller::login,
+        auth_controller::verify_mfa,
+        auth_controller::refresh,
+        auth_controller::forgot_password,
+        auth_controller::reset_password,
+        auth_controller::verify_email,
+        auth_controller::oidc_login,
+        auth_controller::oidc_callback,
+        auth_controller::logout,
+        user_controller::get_user,
+        user_controller::delete_user,
+        user_controller::change_password,
+        user_controller::resend_verification,
+        user_controller::get_identities,
+        user_controller::link_identity,
+        user_controller::unlink_identity,
+        user_controller::reauth,
+        user_controller::enrol_totp,
+        user_controller::disable_totp,
+        user_controller::confirm_totp,
+        user_controller::regenerate_recovery_codes,
+        user_controller::get_sessions,
+        user_controller::delete_other_sessions,
+        user_controller::update_session,
+        user_controller::delete_session,
+        user_controller::export_user,
+        user_controller::get_export,
+        user_controller::download_export,
+        schedule_controller::get_schedules,
+        schedule_controller::add_schedule,
+        schedule_controller::get_schedule_by_id,
+        schedule_controller::update_schedule,
+        schedule_controller::delete_schedule,
+        schedule_controller::get_schedule_history,
+        schedule_controller::log_dose,
+        schedule_controller::batch_schedules,
+        event_controller::get_events,
+        caregiver_controller::get_grants,
+        caregiver_controller::invite_caregiver,
+        caregiver_controller::delete_grant,
+        caregiver_controller::get_invitations,
+        caregiver_controller::accept_invitation,
+        drug_controller::get_drug,
+        fhir_controller::get_medication_statements,
+        fhir_controller::get_medication_requests,
+        fhir_controller::get_medication_administrations,
+        admin_controller::list_users,
+        admin_controller::disable_user,
+        admin_controller::enable_user,
+        admin_controller::revoke_sessions,
+        admin_controller::get_sync,
+        admin_controller::start_sync,
+        well_known_controller::get_jwks,
+    ),
+    tags(
+        (name = "auth", description = "Signing up, logging in and recovering accounts"),
+        (name = "user", description = "The logged in user's account, sessions and exports"),
+        (name = "schedule", description = "Medication schedules and their pill counts"),
+        (name = "events", description = "Live changes to schedules"),
+        (name = "caregiver", description = "Sharing schedules with other users"),
+        (name = "drug", description = "Drugs@FDA products"),
+        (name = "fhir", description = "Schedules and doses as FHIR R4 resources"),
+        (name = "admin", description = "User management, only for admins"),
+        (name = "well-known", description = "Keys for verifying tokens"),
+    )
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+// the spec and an interactive ui to try the api with
+pub fn docs_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+
+    // actix can't list an app's routes, so they are read from the sources of the functions
+    // config_app configures
+    const CONFIG: &str = include_str!("mod.rs");
+    const CONTROLLERS: [&str; 10] = [
+        include_str!("admin_controller.rs"),
+        include_str!("auth_controller.rs"),
+        include_str!("caregiver_controller.rs"),
+        include_str!("docs_controller.rs"),
+        include_str!("drug_controller.rs"),
+        include_str!("event_controller.rs"),
+        include_str!("fhir_controller.rs"),
+        include_str!("schedule_controller.rs"),
+        include_str!("user_controller.rs"),
+        include_str!("well_known_controller.rs"),
+    ];
+    const METHODS: [&str; 5] = ["get", "post", "put", "delete", "patch"];
+
+    // the position of each `prefix"..."` in the source and the quoted text
+    fn quoted<'a>(source: &'a str, prefix: &str) -> Vec<(usize, &'a str)> {
+        source
+            .match_indices(prefix)
+            .map(|(at, _)| {
+                let rest = &source[at + prefix.len()..];
+                (at, &rest[..rest.find('"').unwrap()])
+            })
+            .collect()
+    }
+
+    // the scopes and the service functions configured in them
+    fn scopes(source: &str) -> Vec<(&str, &str)> {
+        let scopes = quoted(source, "web::scope(\"");
+        scopes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (at, scope))| {
+                let end = scopes.get(i + 1).map_or(source.len(), |(next, _)| *next);
+                let rest = &source[*at..end];
+                let start = rest.find(".configure(")? + ".configure(".len();
+                Some((*scope, &rest[start..start + rest[start..].find(')')?]))
+            })
+            .collect()
+    }
+
+    // the method and path of every route a service function adds
+    fn routes(service: &str, prefix: &str, served: &mut BTreeSet<(String, String)>) {
+        let signature = format!("pub fn {}(", service);
+        let source = CONTROLLERS
+            .iter()
+            .find(|source| source.contains(&signature))
+            .unwrap_or_else(|| panic!("{} isn't in a controller listed here", service));
+        let body = &source[source.find(&signature).unwrap()..];
+        let body = &body[..body.find("\n}\n").unwrap()];
+
+        let mut tokens: Vec<(usize, Option<&str>, &str)> = quoted(body, "web::resource(\"")
+            .into_iter()
+            .map(|(at, path)| (at, None, path))
+            .collect();
+        for method in METHODS {
+            for (at, _) in body.match_indices(&format!("web::{}().to(", method)) {
+                tokens.push((at, Some(method), ""));
+            }
+        }
+        tokens.sort();
+        let mut resource = None;
+        for (_, method, path) in tokens {
+            match method {
+                None => resource = Some(path),
+                Some(method) => {
+                    let path = resource.expect("a route outside of a resource");
+                    served.insert((method.to_string(), format!("{}{}", prefix, path)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let (top, api) = CONFIG.split_at(CONFIG.find("fn api_service").unwrap());
+        let mut served = BTreeSet::new();
+        for (scope, service) in scopes(top) {
+            // the versioned and legacy scopes serve api_service, handled below
+            if service != "api_service" {
+                routes(service, scope, &mut served);
+            }
+        }
+        // the legacy /api scope serves the same routes, only /api/v1 is documented
+        for (scope, service) in scopes(api) {
+            routes(service, &format!("/api/v1{}", scope), &mut served);
+        }
+
+        let mut documented = BTreeSet::new();
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let operations = [
+                ("get", &item.get),
+                ("post", &item.post),
+                ("put", &item.put),
+                ("delete", &item.delete),
+                ("patch", &item.patch),
+            ];
+            for (method, operation) in operations {
+                if operation.is_some() {
+                    documented.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+
+        assert!(served.len() > 50, "only found {:?}", served);
+        let undocumented: Vec<_> = served.difference(&documented).collect();
+        let unserved: Vec<_> = documented.difference(&served).collect();
+        assert!(
+            undocumented.is_empty() && unserved.is_empty(),
+            "served but not documented: {:?}\ndocumented but not served: {:?}",
+            undocumented,
+            unserved
+        );
+    }
+}
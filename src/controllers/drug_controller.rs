@@ -1,22 +1,72 @@
 use crate::models::auth::Authenticated;
-use actix_web::{web, Error, HttpResponse};
+use crate::models::dto;
+use crate::models::error::{ApiError, ErrorBody};
+use crate::models::response::{ApiVersion, Envelope, ResponseBody};
+use crate::utils::query_utils::{self, ListParams, Sortable};
+use actix_web::web;
 use entity::product;
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Deserialize;
+use utoipa::IntoParams;
 pub fn drug_service(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/{name}").route(web::get().to(get_drug)));
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DrugQuery {
+    // the dosage form and route, e.g. TABLET;ORAL
+    form: Option<String>,
+    // part of the active ingredient
+    active_ingredient: Option<String>,
+}
+
+const DRUG_SORTABLE: &[Sortable<product::Column>] = &[
+    ("drug_name", product::Column::DrugName),
+    ("form", product::Column::Form),
+    ("strength", product::Column::Strength),
+    ("appl_no", product::Column::ApplNo),
+];
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/drug/{name}",
+    tag = "drug",
+    description = "Sortable by drug_name, form, strength and appl_no, by drug_name by default",
+    params(
+        ("name" = String, Path, description = "Part of the drug name to search for"),
+        ListParams,
+        DrugQuery,
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Products whose drug name contains the search", body = ResponseBody<Vec<dto::Product>>),
+        (status = 400, description = "Invalid filter, sort, cursor or page", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
 async fn get_drug(
     _user: Authenticated,
     db: web::Data<DatabaseConnection>,
     name: web::Path<String>,
-) -> Result<HttpResponse, Error> {
+    query: web::Query<DrugQuery>,
+    params: web::Query<ListParams>,
+    version: ApiVersion,
+) -> Result<Envelope<Vec<dto::Product>>, ApiError> {
     let conn = db.as_ref();
-    let results = product::Entity::find()
-        .filter(product::Column::DrugName.contains(name.as_str()))
-        .all(conn)
-        .await
-        .unwrap();
+    let mut select = product::Entity::find()
+        .filter(product::Column::DrugName.contains(&query_utils::like_escape(&name)));
+    if let Some(form) = &query.form {
+        select = select.filter(product::Column::Form.eq(form.as_str()));
+    }
+    if let Some(active_ingredient) = &query.active_ingredient {
+        select = select.filter(
+            product::Column::ActiveIngredient
+                .contains(&query_utils::like_escape(active_ingredient)),
+        );
+    }
+    let (results, pagination) =
+        query_utils::list(select, &params, DRUG_SORTABLE, "drug_name", version, conn).await?;
 
-    Ok(HttpResponse::Ok().json(results))
+    Ok(Envelope::list(dto::list(&results), pagination))
 }
@@ -0,0 +1,40 @@
+use crate::models::auth::Authenticated;
+use crate::models::dto;
+use crate::models::error::ErrorBody;
+use crate::utils::events::EventHub;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{rt, web, HttpResponse};
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+
+pub fn event_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("").route(web::get().to(get_events)));
+}
+
+// live changes to the schedules the caller can see, so several devices stay in sync
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    tag = "events",
+    description = "A text/event-stream of events as json, each in a data field. A resync event means events were missed and what's shown should be fetched again. The stream ends when the access token expires or the session ends",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The stream of events", body = dto::Event, content_type = "text/event-stream"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn get_events(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    hub: web::Data<EventHub>,
+) -> HttpResponse {
+    let expires_in = (user.exp - Utc::now().timestamp()).max(0) as u64;
+    let until = rt::time::Instant::now() + Duration::from_secs(expires_in);
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        // nginx would otherwise hold events back in its buffer
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(hub.stream(user.user_id, user.session_id, db.get_ref().clone(), until))
+}
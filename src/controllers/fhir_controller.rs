@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+
+use crate::fhir::medication::{MedicationAdministration, MedicationRequest, MedicationStatement};
+use crate::fhir::{Bundle, BundleLink, OperationOutcome, Resource, CONTENT_TYPE};
+use crate::models::auth::Authenticated;
+use crate::models::error::{ApiError, ErrorBody};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use entity::accounting_entry::{self, EntryKind};
+use entity::caregiver_grant::{self, ScheduleScope};
+use entity::{product, schedule};
+use sea_orm::prelude::Uuid;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+pub fn fhir_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/MedicationStatement").route(web::get().to(get_medication_statements)),
+    )
+    .service(web::resource("/MedicationRequest").route(web::get().to(get_medication_requests)))
+    .service(
+        web::resource("/MedicationAdministration")
+            .route(web::get().to(get_medication_administrations)),
+    );
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PatientSearch {
+    patient: String,
+}
+
+// the patient a search is for and which of their schedules the user can see. users can search
+// their own records, as "me" or by id, and those of anyone who shared schedules with them
+async fn resolve_patient(
+    user: &Authenticated,
+    patient: &str,
+    db: &web::Data<DatabaseConnection>,
+) -> Result<Option<(Uuid, ScheduleScope)>, ApiError> {
+    let owner = match patient {
+        "me" => user.user_id,
+        _ => match Uuid::parse_str(patient.trim_start_matches("Patient/")) {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        },
+    };
+    let scope =
+        caregiver_grant::Model::visible_schedules(user.user_id, owner, db.get_ref()).await?;
+    Ok(scope.map(|scope| (owner, scope)))
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AdministrationSearch {
+    patient: String,
+    // only return doses recorded after this instant, for incremental syncs
+    #[serde(rename = "_since")]
+    since: Option<DateTime<Utc>>,
+    #[serde(rename = "_count")]
+    count: Option<usize>,
+    // 1 based page number
+    #[serde(rename = "_page")]
+    page: Option<usize>,
+}
+
+fn invalid(diagnostics: &str) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type(CONTENT_TYPE)
+        .json(OperationOutcome::error("invalid", diagnostics))
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type(CONTENT_TYPE)
+        .json(OperationOutcome::error(
+            "forbidden",
+            "Only patient=me or a patient who shared their records can be searched",
+        ))
+}
+
+// loads the user's schedules along with the FDA product each one is linked to
+async fn get_schedules_with_products(
+    db: &web::Data<DatabaseConnection>,
+    user_id: Uuid,
+    scope: &ScheduleScope,
+) -> Result<Vec<(schedule::Model, Option<product::Model>)>, ApiError> {
+    let schedules = schedule::Entity::find()
+        .filter(schedule::Column::UserId.eq(user_id))
+        .filter(scope.condition())
+        .all(db.get_ref())
+        .await?;
+
+    let applications: Vec<String> = schedules
+        .iter()
+        .filter_map(|s| s.product_appl_no.clone())
+        .collect();
+    let mut by_key: HashMap<(String, String), product::Model> = HashMap::new();
+    if !applications.is_empty() {
+        let products = product::Entity::find()
+            .filter(product::Column::ApplNo.is_in(applications))
+            .all(db.get_ref())
+            .await?;
+        for product in products {
+            by_key.insert(
+                (product.appl_no.clone(), product.product_no.clone()),
+                product,
+            );
+        }
+    }
+
+    Ok(schedules
+        .into_iter()
+        .map(|s| {
+            let product = match (&s.product_appl_no, &s.product_no) {
+                (Some(appl_no), Some(product_no)) => {
+                    by_key.get(&(appl_no.clone(), product_no.clone())).cloned()
+                }
+                _ => None,
+            };
+            (s, product)
+        })
+        .collect())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/fhir/MedicationStatement",
+    tag = "fhir",
+    params(PatientSearch),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "A searchset Bundle of MedicationStatements, one per schedule", content_type = "application/fhir+json", body = Resource),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The patient shared nothing with the caller, as an OperationOutcome", content_type = "application/fhir+json", body = Resource),
+    )
+)]
+async fn get_medication_statements(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    search: web::Query<PatientSearch>,
+) -> Result<HttpResponse, ApiError> {
+    let (patient, scope) = match resolve_patient(&user, &search.patient, &db).await? {
+        Some(resolved) => resolved,
+        None => return Ok(forbidden()),
+    };
+    let resources = get_schedules_with_products(&db, patient, &scope)
+        .await?
+        .iter()
+        .map(|(schedule, product)| {
+            (
+                schedule.id.to_string(),
+                Resource::MedicationStatement(MedicationStatement::from_schedule(
+                    schedule,
+                    product.as_ref(),
+                )),
+            )
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .json(Resource::Bundle(Bundle::searchset(resources))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/fhir/MedicationRequest",
+    tag = "fhir",
+    params(PatientSearch),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "A searchset Bundle of MedicationRequests, one per schedule", content_type = "application/fhir+json", body = Resource),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The patient shared nothing with the caller, as an OperationOutcome", content_type = "application/fhir+json", body = Resource),
+    )
+)]
+async fn get_medication_requests(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    search: web::Query<PatientSearch>,
+) -> Result<HttpResponse, ApiError> {
+    let (patient, scope) = match resolve_patient(&user, &search.patient, &db).await? {
+        Some(resolved) => resolved,
+        None => return Ok(forbidden()),
+    };
+    let resources = get_schedules_with_products(&db, patient, &scope)
+        .await?
+        .iter()
+        .map(|(schedule, product)| {
+            (
+                schedule.id.to_string(),
+                Resource::MedicationRequest(MedicationRequest::from_schedule(
+                    schedule,
+                    product.as_ref(),
+                )),
+            )
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .json(Resource::Bundle(Bundle::searchset(resources))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/fhir/MedicationAdministration",
+    tag = "fhir",
+    params(AdministrationSearch),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "One page of a searchset Bundle of MedicationAdministrations", content_type = "application/fhir+json", body = Resource),
+        (status = 400, description = "The page is out of range, as an OperationOutcome", content_type = "application/fhir+json", body = Resource),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The patient shared nothing with the caller, as an OperationOutcome", content_type = "application/fhir+json", body = Resource),
+    )
+)]
+async fn get_medication_administrations(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    search: web::Query<AdministrationSearch>,
+) -> Result<HttpResponse, ApiError> {
+    let (patient, scope) = match resolve_patient(&user, &search.patient, &db).await? {
+        Some(resolved) => resolved,
+        None => return Ok(forbidden()),
+    };
+    let count = search
+        .count
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = search.page.unwrap_or(1).max(1);
+    // offsets past a bigint can't be fetched and would overflow on the way
+    if (page - 1)
+        .checked_mul(count)
+        .is_none_or(|offset| offset > i64::MAX as usize)
+    {
+        return Ok(invalid("_page is too large for the _count"));
+    }
+
+    let schedules: HashMap<_, _> = get_schedules_with_products(&db, patient, &scope)
+        .await?
+        .into_iter()
+        .map(|(schedule, product)| (schedule.id, (schedule, product)))
+        .collect();
+
+    // initial counts and corrections change the count without anything being taken
+    let mut query = accounting_entry::Entity::find()
+        .filter(accounting_entry::Column::ScheduleId.is_in(schedules.keys().cloned()))
+        .filter(accounting_entry::Column::Kind.is_in([EntryKind::Dose, EntryKind::Missed]));
+    if let Some(since) = search.since {
+        query = query.filter(accounting_entry::Column::Timestamp.gt(since));
+    }
+    let paginator = query
+        .order_by_asc(accounting_entry::Column::Timestamp)
+        .order_by_asc(accounting_entry::Column::Id)
+        .paginate(db.get_ref(), count);
+
+    let total = paginator.num_items().await?;
+    let entries = paginator.fetch_page(page - 1).await?;
+
+    let resources = entries
+        .iter()
+        .filter_map(|entry| {
+            let (schedule, product) = schedules.get(&entry.schedule_id)?;
+            Some((
+                entry.id.to_string(),
+                Resource::MedicationAdministration(
+                    MedicationAdministration::from_accounting_entry(
+                        entry,
+                        schedule,
+                        product.as_ref(),
+                    ),
+                ),
+            ))
+        })
+        .collect();
+
+    let page_url = |page: usize| {
+        let mut url = format!(
+            "{}?patient={}&_count={}&_page={}",
+            req.path(),
+            search.patient,
+            count,
+            page
+        );
+        // use Z rather than +00:00, a literal + in a query string decodes to a space
+        if let Some(since) = search.since {
+            url.push_str(&format!(
+                "&_since={}",
+                since.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ));
+        }
+        url
+    };
+    let mut link = vec![BundleLink {
+        relation: "self".to_string(),
+        url: page_url(page),
+    }];
+    if page * count < total {
+        link.push(BundleLink {
+            relation: "next".to_string(),
+            url: page_url(page + 1),
+        });
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .json(Resource::Bundle(Bundle::page(resources, total, link))))
+}
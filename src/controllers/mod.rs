@@ -1,22 +1,94 @@
-use actix_web::web;
+use crate::middleware::deprecation::DeprecationMiddlewareFactory;
+use crate::middleware::idempotency::IdempotencyMiddlewareFactory;
+use crate::models::error::ApiError;
+use crate::models::response::ApiVersion;
+use actix_web::{web, HttpResponse};
+use admin_controller::admin_service;
 use auth_controller::auth_service;
+use caregiver_controller::caregiver_service;
+use docs_controller::docs_service;
 use drug_controller::drug_service;
+use event_controller::event_service;
+use fhir_controller::fhir_service;
 use log::info;
 use schedule_controller::schedule_service;
-use user_controller::user_service;
+use user_controller::{download_service, user_service};
+use well_known_controller::well_known_service;
 
+pub mod admin_controller;
 pub mod auth_controller;
+pub mod caregiver_controller;
+pub mod docs_controller;
 pub mod drug_controller;
+pub mod event_controller;
+pub mod fhir_controller;
 pub mod schedule_controller;
 pub mod user_controller;
+pub mod well_known_controller;
+
+// 2026-10-19, when /api/v1 replaced the unversioned routes
+const LEGACY_API_DEPRECATED_AT: i64 = 1792368000;
+// 2027-04-19, six months later, when they will be removed
+const LEGACY_API_SUNSET_AT: i64 = 1808092800;
 
 pub fn config_app(cfg: &mut web::ServiceConfig) {
     info!("Configuring routes");
-    cfg.service(
+    // malformed bodies, paths and queries get the same error body as everything else
+    cfg.app_data(
+        web::JsonConfig::default().error_handler(|err, _| {
+            ApiError::invalid("body", "invalid_json", err.to_string()).into()
+        }),
+    )
+    .app_data(
+        web::PathConfig::default().error_handler(|err, _| {
+            ApiError::invalid("path", "invalid_path", err.to_string()).into()
+        }),
+    )
+    .app_data(web::QueryConfig::default().error_handler(|err, _| {
+        ApiError::invalid("query", "invalid_query", err.to_string()).into()
+    }))
+    // registered before the /api scope, which would otherwise answer the docs paths with a 404
+    .configure(docs_service)
+    // registered before /api, which would otherwise take its requests
+    .service(
+        web::scope("/api/v1")
+            .app_data(ApiVersion::V1)
+            .wrap(IdempotencyMiddlewareFactory {})
+            .configure(api_service),
+    )
+    .service(
         web::scope("/api")
-            .service(web::scope("/drug").configure(drug_service))
-            .service(web::scope("/user").configure(user_service))
-            .service(web::scope("/schedule").configure(schedule_service)),
+            .app_data(ApiVersion::Legacy)
+            .wrap(IdempotencyMiddlewareFactory {})
+            .wrap(DeprecationMiddlewareFactory {
+                deprecated_at: LEGACY_API_DEPRECATED_AT,
+                sunset_at: LEGACY_API_SUNSET_AT,
+                prefix: "/api",
+                successor: "/api/v1",
+            })
+            .configure(api_service),
+    )
+    .service(
+        web::scope("/auth")
+            .wrap(IdempotencyMiddlewareFactory {})
+            .configure(auth_service),
     )
-    .service(web::scope("/auth").configure(auth_service));
+    .service(web::scope("/download").configure(download_service))
+    .service(web::scope("/.well-known").configure(well_known_service))
+    .default_service(web::to(not_found));
+}
+
+// the routes of every api version, versions only differ in how responses are rendered
+fn api_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/drug").configure(drug_service))
+        .service(web::scope("/user").configure(user_service))
+        .service(web::scope("/schedule").configure(schedule_service))
+        .service(web::scope("/events").configure(event_service))
+        .service(web::scope("/caregiver").configure(caregiver_service))
+        .service(web::scope("/fhir").configure(fhir_service))
+        .service(web::scope("/admin").configure(admin_service));
+}
+
+async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound)
 }
@@ -1,190 +1,1014 @@
 use crate::models::auth::Authenticated;
+use crate::models::dto;
+use crate::models::error::{ApiError, ErrorBody};
+use crate::models::response::{ApiVersion, Envelope, FieldError, ResponseBody};
+use crate::services::schedules::{self, Dose, NewSchedule, ScheduleChanges};
+use crate::utils::cron_utils::{describe_schedule, ScheduleDefinition};
+use crate::utils::query_utils::{self, ListParams, Sortable};
 use crate::utils::validate_cron_expression;
-use actix_web::{error, web, Error, HttpResponse};
+use actix_web::http::header::{EntityTag, IfMatch, ETAG};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use entity::caregiver_grant::{self, Permission};
 use entity::{accounting_entry, schedule};
 use futures::{try_join, TryFutureExt};
+use sea_orm::prelude::Json;
 use sea_orm::{
-    ActiveModelBehavior, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
-    QueryFilter, QueryOrder, Set,
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
 pub fn schedule_service(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("")
             .route(web::get().to(get_schedules))
             .route(web::post().to(add_schedule)),
     )
+    // before /{id}, which would take the path and answer 405
+    .service(web::resource("/batch").route(web::post().to(batch_schedules)))
     .service(
         web::resource("/{id}")
             .route(web::get().to(get_schedule_by_id))
             .route(web::put().to(update_schedule))
             .route(web::delete().to(delete_schedule)),
-    );
+    )
+    .service(web::resource("/{id}/history").route(web::get().to(get_schedule_history)))
+    .service(web::resource("/{id}/doses").route(web::post().to(log_dose)));
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScheduleListQuery {
+    // list the schedules another user shared, defaults to the caller's own
+    owner: Option<sea_orm::prelude::Uuid>,
+    // part of the drug name
+    drug_name: Option<String>,
+    added_after: Option<DateTime<Utc>>,
+    added_before: Option<DateTime<Utc>>,
 }
 
+const SCHEDULE_SORTABLE: &[Sortable<schedule::Column>] = &[
+    ("drug_name", schedule::Column::DrugName),
+    ("added_at", schedule::Column::AddedAt),
+    ("pill_count", schedule::Column::PillCount),
+];
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/schedule",
+    tag = "schedule",
+    description = "Sortable by drug_name, added_at and pill_count, oldest first by default",
+    params(ListParams, ScheduleListQuery),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The schedules the caller can see", body = ResponseBody<Vec<dto::Schedule>>),
+        (status = 400, description = "Invalid filter, sort, cursor or page", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "The owner shared nothing with the caller", body = ErrorBody),
+    )
+)]
 async fn get_schedules(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
-) -> Result<HttpResponse, Error> {
-    let query = schedule::Entity::find()
-        .filter(schedule::Column::UserId.eq(user.user_id))
-        .all(db.get_ref())
-        .await;
+    query: web::Query<ScheduleListQuery>,
+    params: web::Query<ListParams>,
+    version: ApiVersion,
+) -> Result<Envelope<Vec<dto::Schedule>>, ApiError> {
+    let owner = query.owner.unwrap_or(user.user_id);
+    let scope = caregiver_grant::Model::visible_schedules(user.user_id, owner, db.get_ref())
+        .await?
+        .ok_or(ApiError::Forbidden)?;
+    let mut select = schedule::Entity::find()
+        .filter(schedule::Column::UserId.eq(owner))
+        .filter(scope.condition());
+    if let Some(drug_name) = &query.drug_name {
+        select = select
+            .filter(schedule::Column::DrugName.contains(&query_utils::like_escape(drug_name)));
+    }
+    if let Some(added_after) = query.added_after {
+        select = select.filter(schedule::Column::AddedAt.gte(added_after));
+    }
+    if let Some(added_before) = query.added_before {
+        select = select.filter(schedule::Column::AddedAt.lt(added_before));
+    }
+    let (schedules, pagination) = query_utils::list(
+        select,
+        &params,
+        SCHEDULE_SORTABLE,
+        "added_at",
+        version,
+        db.get_ref(),
+    )
+    .await?;
+
+    Ok(Envelope::list(dto::list(&schedules), pagination))
+}
 
-    match query {
-        Ok(schedules) => Ok(HttpResponse::Ok().json(schedules)),
-        Err(_) => Ok(HttpResponse::InternalServerError().body("")),
+// a schedule is given either as a raw cron expression or as a definition that compiles to one
+fn compile_schedule(
+    cron: &Option<String>,
+    definition: &Option<ScheduleDefinition>,
+) -> Result<Option<(String, Option<Json>)>, ApiError> {
+    match (cron, definition) {
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => Err(ApiError::invalid(
+            "definition",
+            "conflicts_with_cron",
+            "Provide either a cron expression or a definition, not both",
+        )),
+        (Some(cron), None) => {
+            if !validate_cron_expression(cron.clone()) {
+                return Err(ApiError::invalid(
+                    "cron",
+                    "invalid_cron",
+                    "Invalid Cron expression",
+                ));
+            }
+            Ok(Some((cron.clone(), None)))
+        }
+        (None, Some(definition)) => {
+            let cron = definition.to_cron().map_err(|message| {
+                ApiError::invalid("definition", "invalid_definition", message)
+            })?;
+            let json = serde_json::to_value(definition).map_err(ApiError::internal)?;
+            Ok(Some((cron, Some(json))))
+        }
     }
 }
 
-async fn get_schedule_from_db(
-    db: &web::Data<DatabaseConnection>,
-    id: web::Path<sea_orm::prelude::Uuid>,
-    user_id: sea_orm::prelude::Uuid,
-) -> Result<schedule::Model, Error> {
-    let query = schedule::Entity::find()
-        .filter(schedule::Column::Id.eq(id.clone()))
-        .filter(schedule::Column::UserId.eq(user_id))
-        .one(db.get_ref())
-        .await;
-
-    match query {
-        Ok(option) => match option {
-            Some(schedule) => Ok(schedule),
-            None => Err(error::ErrorNotFound("")),
-        },
-        Err(_) => Err(error::ErrorInternalServerError("")),
-    }
-}
-
-async fn log_accounting_entry(
-    db: &web::Data<DatabaseConnection>,
-    old: &i32,
-    new: &i32,
-    sched_id: &sea_orm::prelude::Uuid,
-) -> Result<(), Error> {
-    let mut entry = accounting_entry::ActiveModel::new();
-    entry.amount = Set(new - old);
-    entry.schedule_id = Set(sched_id.clone());
-
-    match entry.insert(db.get_ref()).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(error::ErrorInternalServerError("")),
-    }
-}
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, ToSchema)]
 struct ScheduleDetailResponse {
-    schedule: schedule::Model,
-    history: Vec<accounting_entry::Model>,
+    schedule: dto::Schedule,
+    description: String,
+    history: Vec<dto::AccountingEntry>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/schedule/{id}",
+    tag = "schedule",
+    params(("id" = Uuid, Path, description = "The schedule id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The schedule with its pill count history", body = ResponseBody<ScheduleDetailResponse>,
+            headers(("ETag" = String, description = "The schedule's version, for If-Match"))),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such schedule", body = ErrorBody),
+    )
+)]
 async fn get_schedule_by_id(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
     id: web::Path<sea_orm::prelude::Uuid>,
-) -> Result<HttpResponse, Error> {
+) -> Result<Envelope<ScheduleDetailResponse>, ApiError> {
     let history = accounting_entry::Entity::find()
-        .filter(accounting_entry::Column::ScheduleId.eq(id.clone()))
+        .filter(accounting_entry::Column::ScheduleId.eq(*id))
         .order_by_asc(accounting_entry::Column::Timestamp)
         .all(db.get_ref())
-        .map_err(|_| error::ErrorInternalServerError(""));
-    let model = get_schedule_from_db(&db, id, user.user_id);
+        .map_err(ApiError::from);
+    let model = schedules::find(db.get_ref(), *id, user.user_id, Permission::Read);
 
     let result = try_join!(model, history)?;
-    Ok(HttpResponse::Ok().json(ScheduleDetailResponse {
-        schedule: result.0,
-        history: result.1,
-    }))
+    Ok(Envelope::ok(ScheduleDetailResponse {
+        description: describe_schedule(&result.0),
+        schedule: dto::Schedule::from(&result.0),
+        history: dto::list(&result.1),
+    })
+    .header((ETAG, schedules::etag(&result.0))))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+}
+
+const HISTORY_SORTABLE: &[Sortable<accounting_entry::Column>] = &[
+    ("timestamp", accounting_entry::Column::Timestamp),
+    ("amount", accounting_entry::Column::Amount),
+];
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/schedule/{id}/history",
+    tag = "schedule",
+    description = "Sortable by timestamp and amount, oldest first by default",
+    params(("id" = Uuid, Path, description = "The schedule id"), ListParams, HistoryQuery),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Changes to the schedule's pill count", body = ResponseBody<Vec<dto::AccountingEntry>>),
+        (status = 400, description = "Invalid filter, sort, cursor or page", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such schedule", body = ErrorBody),
+    )
+)]
+async fn get_schedule_history(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<sea_orm::prelude::Uuid>,
+    query: web::Query<HistoryQuery>,
+    params: web::Query<ListParams>,
+    version: ApiVersion,
+) -> Result<Envelope<Vec<dto::AccountingEntry>>, ApiError> {
+    let schedule = schedules::find(db.get_ref(), *id, user.user_id, Permission::Read).await?;
+    let mut select = accounting_entry::Entity::find()
+        .filter(accounting_entry::Column::ScheduleId.eq(schedule.id));
+    if let Some(after) = query.after {
+        select = select.filter(accounting_entry::Column::Timestamp.gte(after));
+    }
+    if let Some(before) = query.before {
+        select = select.filter(accounting_entry::Column::Timestamp.lt(before));
+    }
+    let (entries, pagination) = query_utils::list(
+        select,
+        &params,
+        HISTORY_SORTABLE,
+        "timestamp",
+        version,
+        db.get_ref(),
+    )
+    .await?;
+
+    Ok(Envelope::list(dto::list(&entries), pagination))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct DoseRequest {
+    // the dose was skipped, nothing comes off the pill count
+    #[serde(default)]
+    missed: bool,
+    // pills taken, defaults to the schedule's pill_amount
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pills: Option<i32>,
+}
+#[utoipa::path(
+    post,
+    path = "/api/v1/schedule/{id}/doses",
+    tag = "schedule",
+    params(("id" = Uuid, Path, description = "The schedule id")),
+    request_body = DoseRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The accounting entry recording the dose", body = ResponseBody<dto::AccountingEntry>,
+            headers(("ETag" = String, description = "The schedule's version after the dose"))),
+        (status = 400, description = "No pill count to take", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Only read access to the schedule", body = ErrorBody),
+        (status = 404, description = "No such schedule", body = ErrorBody),
+    )
+)]
+async fn log_dose(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<sea_orm::prelude::Uuid>,
+    body: web::Json<DoseRequest>,
+) -> Result<Envelope<dto::AccountingEntry>, ApiError> {
+    let dose = match body.missed {
+        true => Dose::Missed,
+        false => Dose::Taken(body.pills),
+    };
+    let (schedule, entry) = schedules::log_dose(db.get_ref(), user.user_id, *id, dose).await?;
+    Ok(Envelope::ok(dto::AccountingEntry::from(&entry)).header((ETAG, schedules::etag(&schedule))))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ScheduleRequest {
-    cron: String,
+    // add the schedule for a user who granted manage access to all their schedules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_id: Option<sea_orm::prelude::Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cron: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    definition: Option<ScheduleDefinition>,
     drug_name: String,
+    // the FDA product taken, from /api/v1/drug
+    #[serde(skip_serializing_if = "Option::is_none")]
+    product: Option<dto::ProductRef>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pill_count: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pill_amount: Option<i32>,
 }
+#[utoipa::path(
+    post,
+    path = "/api/v1/schedule",
+    tag = "schedule",
+    request_body = ScheduleRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The new schedule", body = ResponseBody<dto::Schedule>,
+            headers(("ETag" = String, description = "The schedule's version, for If-Match"))),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "Invalid cron expression or definition", body = ErrorBody),
+        (status = 403, description = "No manage access to the owner's schedules", body = ErrorBody),
+    )
+)]
 async fn add_schedule(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
     body: web::Json<ScheduleRequest>,
-) -> Result<HttpResponse, Error> {
-    let pill_count = body.pill_count.unwrap_or(0);
-    let pill_amount = body.pill_amount.unwrap_or(0);
-
-    if !validate_cron_expression(body.cron.clone()) {
-        return Ok(HttpResponse::BadRequest().body("Invalid Cron expression"));
-    }
-
-    let mut schedule = schedule::ActiveModel::new();
-    schedule.user_id = Set(user.user_id);
-    schedule.drug_name = Set(body.drug_name.clone());
-    schedule.cron = Set(body.cron.clone());
-    schedule.pill_count = Set(pill_count);
-    schedule.pill_amount = Set(pill_amount);
-    let query = schedule.insert(db.get_ref()).await;
-    match query {
-        Ok(result) => {
-            log_accounting_entry(&db, &0, &pill_count, &result.id).await?;
-            Ok(HttpResponse::Ok().json(result))
-        }
-        Err(_) => Ok(HttpResponse::InternalServerError().body("")),
-    }
+) -> Result<Envelope<dto::Schedule>, ApiError> {
+    let new = new_schedule(&body, user.user_id)?;
+    let result = schedules::create(db.get_ref(), user.user_id, new).await?;
+    Ok(Envelope::ok(dto::Schedule::from(&result)).header((ETAG, schedules::etag(&result))))
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+fn new_schedule(
+    body: &ScheduleRequest,
+    user_id: sea_orm::prelude::Uuid,
+) -> Result<NewSchedule, ApiError> {
+    let (cron, definition) = compile_schedule(&body.cron, &body.definition)?.ok_or_else(|| {
+        ApiError::invalid(
+            "cron",
+            "required",
+            "A cron expression or definition is required",
+        )
+    })?;
+
+    Ok(NewSchedule {
+        owner_id: body.owner_id.unwrap_or(user_id),
+        drug_name: body.drug_name.clone(),
+        cron,
+        definition,
+        product: body.product.clone(),
+        pill_count: body.pill_count.unwrap_or(0),
+        pill_amount: body.pill_amount.unwrap_or(0),
+    })
+}
+
+#[derive(Serialize, Deserialize, PartialEq, ToSchema)]
 struct UpdateScheduleReq {
     #[serde(skip_serializing_if = "Option::is_none")]
     cron: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    definition: Option<ScheduleDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    product: Option<dto::ProductRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pill_count: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pill_amount: Option<i32>,
 }
+#[utoipa::path(
+    put,
+    path = "/api/v1/schedule/{id}",
+    tag = "schedule",
+    params(
+        ("id" = Uuid, Path, description = "The schedule id"),
+        ("If-Match" = String, Header, description = "The ETag the change is based on"),
+    ),
+    request_body = UpdateScheduleReq,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The updated schedule", body = ResponseBody<dto::Schedule>,
+            headers(("ETag" = String, description = "The schedule's new version"))),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "Invalid cron expression or definition", body = ErrorBody),
+        (status = 403, description = "Only read access to the schedule", body = ErrorBody),
+        (status = 404, description = "No such schedule", body = ErrorBody),
+        (status = 412, description = "The schedule changed since the If-Match version", body = ErrorBody),
+        (status = 428, description = "No If-Match", body = ErrorBody),
+    )
+)]
 async fn update_schedule(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
     id: web::Path<sea_orm::prelude::Uuid>,
     body: web::Json<UpdateScheduleReq>,
-) -> Result<HttpResponse, Error> {
-    let model = get_schedule_from_db(&db, id, user.user_id).await?;
-    let mut active_model: schedule::ActiveModel = model.into();
-
-    if let Some(cron) = body.cron.to_owned() {
-        active_model.cron = Set(cron);
-    }
-    if let Some(pill_amount) = body.pill_amount.to_owned() {
-        active_model.pill_amount = Set(pill_amount);
-    }
-    if let Some(pill_count) = body.pill_count {
-        log_accounting_entry(
-            &db,
-            active_model.pill_count.as_ref(),
-            &pill_count,
-            active_model.id.as_ref(),
-        )
-        .await?;
+    if_match: Option<web::Header<IfMatch>>,
+    version: ApiVersion,
+) -> Result<Envelope<dto::Schedule>, ApiError> {
+    let changes = schedule_changes(&body)?;
+    let if_match = required_if_match(if_match, version)?;
+    let result = schedules::update(db.get_ref(), user.user_id, *id, if_match, changes).await?;
+    Ok(Envelope::ok(dto::Schedule::from(&result)).header((ETAG, schedules::etag(&result))))
+}
+
+fn schedule_changes(body: &UpdateScheduleReq) -> Result<ScheduleChanges, ApiError> {
+    Ok(ScheduleChanges {
+        cron: compile_schedule(&body.cron, &body.definition)?,
+        product: body.product.clone(),
+        pill_count: body.pill_count,
+        pill_amount: body.pill_amount,
+    })
+}
 
-        active_model.pill_count = Set(pill_count);
+#[utoipa::path(
+    delete,
+    path = "/api/v1/schedule/{id}",
+    tag = "schedule",
+    params(
+        ("id" = Uuid, Path, description = "The schedule id"),
+        ("If-Match" = String, Header, description = "The ETag the client last read"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The schedule was deleted"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Only read access to the schedule", body = ErrorBody),
+        (status = 404, description = "No such schedule", body = ErrorBody),
+        (status = 412, description = "The schedule changed since the If-Match version", body = ErrorBody),
+        (status = 428, description = "No If-Match", body = ErrorBody),
+    )
+)]
+async fn delete_schedule(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<sea_orm::prelude::Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    version: ApiVersion,
+) -> Result<HttpResponse, ApiError> {
+    let if_match = required_if_match(if_match, version)?;
+    schedules::delete(db.get_ref(), user.user_id, *id, if_match).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+// changes under v1 have to say which version they are based on, so they can't silently
+// overwrite someone else's. the legacy routes predate ETags and their clients never send one
+fn required_if_match(
+    if_match: Option<web::Header<IfMatch>>,
+    version: ApiVersion,
+) -> Result<Option<IfMatch>, ApiError> {
+    match (if_match.map(web::Header::into_inner), version) {
+        // a request without If-Match parses as an empty list of tags
+        (Some(IfMatch::Items(tags)), ApiVersion::V1) if tags.is_empty() => {
+            Err(ApiError::PreconditionRequired)
+        }
+        (None, ApiVersion::V1) => Err(ApiError::PreconditionRequired),
+        (if_match, _) => Ok(if_match),
     }
+}
+
+// more operations than this have to be split over several batches
+const MAX_BATCH_OPERATIONS: usize = 100;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum BatchMode {
+    // nothing is applied unless every operation succeeds
+    #[default]
+    AllOrNothing,
+    // every operation that succeeds is applied
+    BestEffort,
+}
 
-    match active_model.update(db.get_ref()).await {
-        Ok(result) => Ok(HttpResponse::Ok().json(result)),
-        Err(_) => Ok(HttpResponse::InternalServerError().body("")),
+#[derive(Deserialize, ToSchema)]
+struct BatchRequest {
+    #[serde(default)]
+    mode: BatchMode,
+    operations: Vec<BatchOperation>,
+}
+
+// version is the ETag the operation is based on, required like If-Match on a single request
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum BatchOperation {
+    Create(ScheduleRequest),
+    Update(BatchUpdate),
+    Delete {
+        id: sea_orm::prelude::Uuid,
+        version: Option<i32>,
+    },
+}
+
+impl BatchOperation {
+    fn product(&self) -> Option<&dto::ProductRef> {
+        match self {
+            BatchOperation::Create(body) => body.product.as_ref(),
+            BatchOperation::Update(update) => update.changes.product.as_ref(),
+            BatchOperation::Delete { .. } => None,
+        }
     }
 }
 
-async fn delete_schedule(
+#[derive(Deserialize, ToSchema)]
+struct BatchUpdate {
+    id: sea_orm::prelude::Uuid,
+    version: Option<i32>,
+    #[serde(flatten)]
+    changes: UpdateScheduleReq,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BatchResponse {
+    // whether any operation was applied
+    applied: bool,
+    // one per operation, in the order they were sent
+    results: Vec<BatchResult>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BatchResult {
+    index: usize,
+    outcome: BatchOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<dto::Schedule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<BatchError>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum BatchOutcome {
+    Created,
+    Updated,
+    Deleted,
+    Failed,
+    // succeeded, but was undone because another operation of the batch failed
+    RolledBack,
+}
+
+// the same code, message and details a single request would have failed with
+#[derive(Serialize, ToSchema)]
+struct BatchError {
+    code: &'static str,
+    message: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/schedule/batch",
+    tag = "schedule",
+    description = "Applies up to 100 operations in order. all_or_nothing, the default, applies \
+        nothing unless every operation succeeds, best_effort applies each one that does",
+    request_body = BatchRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every operation was applied", body = ResponseBody<BatchResponse>),
+        (status = 207, description = "Some operations failed, see each result", body = ResponseBody<BatchResponse>),
+        (status = 400, description = "No operations, or too many", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn batch_schedules(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
-    id: web::Path<sea_orm::prelude::Uuid>,
-) -> Result<HttpResponse, Error> {
-    let model = get_schedule_from_db(&db, id, user.user_id).await?;
-    let active_model: schedule::ActiveModel = model.into();
-    match active_model.delete(db.get_ref()).await {
-        Ok(_) => Ok(HttpResponse::Ok().body("")),
-        Err(_) => Ok(HttpResponse::InternalServerError().body("")),
+    body: web::Json<BatchRequest>,
+) -> Result<Envelope<BatchResponse>, ApiError> {
+    let BatchRequest { mode, operations } = body.into_inner();
+    if operations.is_empty() || operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(ApiError::invalid(
+            "operations",
+            "invalid_length",
+            format!("Send 1 to {} operations", MAX_BATCH_OPERATIONS),
+        ));
+    }
+
+    // product references are checked together up front, operations naming an unknown one
+    // fail without being applied
+    let (indices, products): (Vec<usize>, Vec<&dto::ProductRef>) = operations
+        .iter()
+        .enumerate()
+        .filter_map(|(index, operation)| Some((index, operation.product()?)))
+        .unzip();
+    let mut rejected: HashMap<usize, ApiError> = indices
+        .into_iter()
+        .zip(schedules::check_products(db.get_ref(), &products).await?)
+        .filter_map(|(index, checked)| Some((index, checked.err()?)))
+        .collect();
+
+    let mut results = Vec::with_capacity(operations.len());
+    let failed = match mode {
+        // each operation runs in a savepoint, so a failed one leaves the others intact
+        // and every operation is still checked
+        BatchMode::AllOrNothing => {
+            let txn = db.begin().await?;
+            for (index, operation) in operations.into_iter().enumerate() {
+                let result = match rejected.remove(&index) {
+                    Some(err) => Err(err),
+                    None => apply(&txn, user.user_id, operation).await,
+                };
+                results.push(batch_result(index, result)?);
+            }
+            let failed = results.iter().any(|r| r.outcome == BatchOutcome::Failed);
+            if failed {
+                txn.rollback().await?;
+                for result in results.iter_mut() {
+                    if result.outcome != BatchOutcome::Failed {
+                        result.outcome = BatchOutcome::RolledBack;
+                        result.schedule = None;
+                    }
+                }
+            } else {
+                txn.commit().await?;
+            }
+            failed
+        }
+        BatchMode::BestEffort => {
+            for (index, operation) in operations.into_iter().enumerate() {
+                let result = match rejected.remove(&index) {
+                    Some(err) => Err(err),
+                    None => apply(db.get_ref(), user.user_id, operation).await,
+                };
+                results.push(batch_result(index, result)?);
+            }
+            results.iter().any(|r| r.outcome == BatchOutcome::Failed)
+        }
+    };
+
+    let applied = results
+        .iter()
+        .any(|r| !matches!(r.outcome, BatchOutcome::Failed | BatchOutcome::RolledBack));
+    let status = match failed {
+        true => StatusCode::MULTI_STATUS,
+        false => StatusCode::OK,
+    };
+    Ok(Envelope::ok(BatchResponse { applied, results }).status(status))
+}
+
+async fn apply<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    user_id: sea_orm::prelude::Uuid,
+    operation: BatchOperation,
+) -> Result<(BatchOutcome, Option<dto::Schedule>), ApiError> {
+    match operation {
+        BatchOperation::Create(body) => {
+            let new = new_schedule(&body, user_id)?;
+            let schedule = schedules::create(db, user_id, new).await?;
+            Ok((BatchOutcome::Created, Some(dto::Schedule::from(&schedule))))
+        }
+        BatchOperation::Update(update) => {
+            let changes = schedule_changes(&update.changes)?;
+            let if_match = version_match(update.version)?;
+            let schedule = schedules::update(db, user_id, update.id, if_match, changes).await?;
+            Ok((BatchOutcome::Updated, Some(dto::Schedule::from(&schedule))))
+        }
+        BatchOperation::Delete { id, version } => {
+            schedules::delete(db, user_id, id, version_match(version)?).await?;
+            Ok((BatchOutcome::Deleted, None))
+        }
+    }
+}
+
+// missing here fails only the operation, where a required field would fail the whole batch
+fn version_match(version: Option<i32>) -> Result<Option<IfMatch>, ApiError> {
+    let version = version.ok_or(ApiError::PreconditionRequired)?;
+    Ok(Some(IfMatch::Items(vec![EntityTag::new_strong(
+        version.to_string(),
+    )])))
+}
+
+// an operation failing because of the request is part of the result, anything else fails the
+// whole batch like it would fail a single request
+fn batch_result(
+    index: usize,
+    result: Result<(BatchOutcome, Option<dto::Schedule>), ApiError>,
+) -> Result<BatchResult, ApiError> {
+    match result {
+        Ok((outcome, schedule)) => Ok(BatchResult {
+            index,
+            outcome,
+            schedule,
+            error: None,
+        }),
+        Err(err @ ApiError::Internal(_)) => Err(err),
+        Err(err) => Ok(BatchResult {
+            index,
+            outcome: BatchOutcome::Failed,
+            schedule: None,
+            error: Some(BatchError {
+                code: err.code(),
+                message: err.message(),
+                details: match err {
+                    ApiError::Validation(details) => details,
+                    _ => Vec::new(),
+                },
+            }),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use entity::{product, schedule};
+    use sea_orm::{ActiveModelBehavior, EntityTrait, Set};
+    use serde_json::{json, Value};
+
+    use crate::test_utils;
+    use crate::utils::oidc::OidcClient;
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn batches_reject_unknown_products_per_operation() {
+        let db = test_utils::database().await;
+        let known = product::Entity::find()
+            .one(&db)
+            .await
+            .unwrap()
+            .expect("the product import ran");
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        let create = |product: Value| json!({ "op": "create", "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "product": product });
+        let known = json!({ "appl_no": known.appl_no, "product_no": known.product_no });
+        let unknown = json!({ "appl_no": "000000", "product_no": "999" });
+        let operations = [
+            create(known.clone()),
+            create(unknown.clone()),
+            create(Value::Null),
+            create(unknown),
+        ];
+        for (mode, outcomes) in [
+            (
+                "all_or_nothing",
+                ["rolled_back", "failed", "rolled_back", "failed"],
+            ),
+            ("best_effort", ["created", "failed", "created", "failed"]),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/schedule/batch")
+                .insert_header(bearer.clone())
+                .set_json(json!({ "mode": mode, "operations": operations }))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+            let body: Value = test::read_body_json(res).await;
+            let results = body["data"]["results"].as_array().unwrap();
+            for (result, outcome) in results.iter().zip(outcomes) {
+                assert_eq!(result["outcome"], outcome);
+                if outcome == "failed" {
+                    assert_eq!(result["error"]["details"][0]["code"], "unknown_product");
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn legacy_routes_return_bare_data_and_announce_their_successor() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let req = test::TestRequest::post()
+            .uri("/api/v1/schedule")
+            .insert_header(bearer.clone())
+            .set_json(json!({ "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "pill_count": 30 }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let created: Value = test::read_body_json(res).await;
+        let id = created["data"]["id"].as_str().unwrap().to_owned();
+
+        // v1 wraps everything in a response body and isn't deprecated
+        for uri in [
+            "/api/v1/schedule".to_owned(),
+            format!("/api/v1/schedule/{}", id),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&uri)
+                .insert_header(bearer.clone())
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            for header in ["deprecation", "sunset", "link"] {
+                assert!(!res.headers().contains_key(header), "{} on {}", header, uri);
+            }
+            let body: Value = test::read_body_json(res).await;
+            assert!(body["data"].is_object() || body["data"].is_array());
+        }
+        let req = test::TestRequest::get()
+            .uri("/api/v1/schedule")
+            .insert_header(bearer.clone())
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"][0]["id"], id);
+        assert!(body["pagination"].is_object());
+
+        // the legacy routes return the bare data, marked as deprecated
+        let req = test::TestRequest::get()
+            .uri("/api/schedule")
+            .insert_header(bearer.clone())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(headers.get("deprecation").unwrap(), "@1792368000");
+        assert_eq!(
+            headers.get("sunset").unwrap(),
+            "Mon, 19 Apr 2027 00:00:00 GMT"
+        );
+        assert_eq!(
+            headers.get("link").unwrap(),
+            "</api/v1/schedule>; rel=\"successor-version\""
+        );
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body[0]["id"], id);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/schedule/{}", id))
+            .insert_header(bearer)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("link").unwrap().to_str().unwrap(),
+            format!("</api/v1/schedule/{}>; rel=\"successor-version\"", id)
+        );
+        assert!(res.headers().contains_key("sunset"));
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["schedule"]["id"], id);
+        assert!(body.get("data").is_none());
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn name_filters_match_wildcards_literally_and_legacy_lists_are_capped() {
+        let db = test_utils::database().await;
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        let names = ["100% Ibuprofen", "Ibu_profen", "Ibuprofen", "C:\\Ibuprofen"]
+            .into_iter()
+            .chain(std::iter::repeat_n("Aspirin", 200));
+        schedule::Entity::insert_many(names.map(|name| schedule::ActiveModel {
+            user_id: Set(user.id),
+            drug_name: Set(name.to_owned()),
+            pill_count: Set(30),
+            pill_amount: Set(1),
+            cron: Set("0 0 8 * * *".to_owned()),
+            ..schedule::ActiveModel::new()
+        }))
+        .exec(&db)
+        .await
+        .unwrap();
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        for (filter, matches) in [
+            ("%25", vec!["100% Ibuprofen"]),
+            ("_", vec!["Ibu_profen"]),
+            ("%5C", vec!["C:\\Ibuprofen"]),
+            (
+                "prof",
+                vec!["100% Ibuprofen", "C:\\Ibuprofen", "Ibu_profen", "Ibuprofen"],
+            ),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/api/v1/schedule?drug_name={}&sort=drug_name",
+                    filter
+                ))
+                .insert_header(bearer.clone())
+                .to_request();
+            let body: Value = test::call_and_read_body_json(&app, req).await;
+            let names: Vec<&str> = body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|schedule| schedule["drug_name"].as_str().unwrap())
+                .collect();
+            assert_eq!(names, matches, "drug_name={}", filter);
+        }
+
+        // 204 schedules, the legacy list stops at the largest page
+        for (query, len) in [
+            ("", 200),
+            ("?page_size=1000", 200),
+            ("?page_num=1", 4),
+            ("?page_size=3", 3),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/schedule{}", query))
+                .insert_header(bearer.clone())
+                .to_request();
+            let body: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body.as_array().unwrap().len(), len, "{}", query);
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn changes_need_the_current_etag_and_bump_the_version() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let req = test::TestRequest::post()
+            .uri("/api/v1/schedule")
+            .insert_header(bearer.clone())
+            .set_json(json!({ "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "pill_count": 30 }))
+            .to_request();
+        let created: Value = test::call_and_read_body_json(&app, req).await;
+        let uri = format!(
+            "/api/v1/schedule/{}",
+            created["data"]["id"].as_str().unwrap()
+        );
+        let put = |if_match: Option<&str>, pill_count: i32| {
+            let req = test::TestRequest::put()
+                .uri(&uri)
+                .insert_header(bearer.clone())
+                .set_json(json!({ "pill_count": pill_count }));
+            match if_match {
+                Some(if_match) => req.insert_header(("If-Match", if_match)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(bearer.clone())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get("etag").unwrap(), "\"1\"");
+
+        let res = test::call_service(&app, put(None, 20)).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "precondition_required");
+
+        let res = test::call_service(&app, put(Some("\"1\""), 20)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("etag").unwrap(), "\"2\"");
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["data"]["version"], 2);
+        assert_eq!(body["data"]["pill_count"], 20);
+
+        // the change made with the old ETag is refused and the schedule is left as it was
+        let res = test::call_service(&app, put(Some("\"1\""), 10)).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "precondition_failed");
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(bearer.clone())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get("etag").unwrap(), "\"2\"");
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["data"]["schedule"]["pill_count"], 20);
+
+        let res = test::call_service(&app, put(Some("*"), 15)).await;
+        assert_eq!(res.headers().get("etag").unwrap(), "\"3\"");
+
+        for (if_match, status) in [
+            (None, StatusCode::PRECONDITION_REQUIRED),
+            (Some("\"2\""), StatusCode::PRECONDITION_FAILED),
+            (Some("\"3\""), StatusCode::OK),
+        ] {
+            let req = test::TestRequest::delete()
+                .uri(&uri)
+                .insert_header(bearer.clone());
+            let req = match if_match {
+                Some(if_match) => req.insert_header(("If-Match", if_match)),
+                None => req,
+            };
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), status, "{:?}", if_match);
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn batch_changes_need_a_version_and_legacy_ones_dont() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/schedule")
+                .insert_header(bearer.clone())
+                .set_json(
+                    json!({ "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "pill_count": 30 }),
+                )
+                .to_request();
+            let created: Value = test::call_and_read_body_json(&app, req).await;
+            ids.push(created["data"]["id"].as_str().unwrap().to_owned());
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/schedule/batch")
+            .insert_header(bearer.clone())
+            .set_json(json!({ "mode": "best_effort", "operations": [
+                { "op": "update", "id": ids[0], "pill_count": 20 },
+                { "op": "delete", "id": ids[0] },
+                { "op": "update", "id": ids[0], "version": 1, "pill_count": 20 },
+            ] }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        let results = body["data"]["results"].as_array().unwrap();
+        assert_eq!(results[0]["error"]["code"], "precondition_required");
+        assert_eq!(results[1]["error"]["code"], "precondition_required");
+        assert_eq!(results[2]["outcome"], "updated");
+        assert_eq!(results[2]["schedule"]["version"], 2);
+
+        // old clients don't know about ETags
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/schedule/{}", ids[1]))
+            .insert_header(bearer.clone())
+            .set_json(json!({ "pill_count": 20 }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["version"], 2);
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/schedule/{}", ids[1]))
+            .insert_header(bearer)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
     }
 }
@@ -1,41 +1,1313 @@
 use crate::models::auth::Authenticated;
-use actix_web::{web, Error, HttpResponse};
-use entity::{session, user};
-use sea_orm::{DatabaseConnection, EntityTrait};
+use crate::models::dto;
+use crate::models::error::{ApiError, ErrorBody};
+use crate::models::response::{ApiVersion, Envelope, ResponseBody};
+use crate::utils::client_ip::client_ip;
+use crate::utils::export_utils::{ExportData, BACKGROUND_EXPORT_ROWS};
+use crate::utils::hashing::{self, verify_password};
+use crate::utils::login_throttle::{password_keys, LoginThrottle, ThrottleKey};
+use crate::utils::mail_utils::send_email_verification;
+use crate::utils::oidc::{OidcClient, Purpose};
+use crate::utils::password_policy::check_password;
+use crate::utils::query_utils::{self, ListParams, Sortable};
+use crate::utils::token_utils::{
+    decode_download_token, decode_reauth_token, encode_download_token,
+};
+use crate::utils::totp;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, SET_COOKIE};
+use actix_web::http::StatusCode;
+use actix_web::{rt, web, Either, HttpRequest, HttpResponse};
+use chrono::{DateTime, TimeZone, Utc};
+use entity::export_job::{self, ExportStatus};
+use entity::{accounting_entry, external_identity, recovery_code, schedule, session, user};
+use log::{error, info};
+use sea_orm::prelude::Uuid;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
+};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 pub fn user_service(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("").route(web::get().to(get_user)));
+    cfg.service(
+        web::resource("")
+            .route(web::get().to(get_user))
+            .route(web::delete().to(delete_user)),
+    )
+    .service(web::resource("/password").route(web::post().to(change_password)))
+    .service(web::resource("/email/verification").route(web::post().to(resend_verification)))
+    .service(web::resource("/identities").route(web::get().to(get_identities)))
+    .service(web::resource("/identities/oidc").route(web::post().to(link_identity)))
+    .service(web::resource("/identities/{id}").route(web::delete().to(unlink_identity)))
+    .service(web::resource("/reauth/oidc").route(web::post().to(reauth)))
+    .service(
+        web::resource("/mfa/totp")
+            .route(web::post().to(enrol_totp))
+            .route(web::delete().to(disable_totp)),
+    )
+    .service(web::resource("/mfa/totp/confirm").route(web::post().to(confirm_totp)))
+    .service(web::resource("/mfa/recovery_codes").route(web::post().to(regenerate_recovery_codes)))
+    .service(
+        web::resource("/sessions")
+            .route(web::get().to(get_sessions))
+            .route(web::delete().to(delete_other_sessions)),
+    )
+    .service(
+        web::resource("/sessions/{id}")
+            .route(web::put().to(update_session))
+            .route(web::delete().to(delete_session)),
+    )
+    .service(web::resource("/export").route(web::post().to(export_user)))
+    .service(web::resource("/export/{id}").route(web::get().to(get_export)));
 }
 
-#[derive(Serialize, Deserialize)]
+// signed export links are the credential, so downloads live outside the authenticated api.
+// the token goes in the query, which the access log leaves out
+pub fn download_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("").route(web::get().to(download_export)));
+}
+
+// device labels are shown in lists, so they are kept short
+const MAX_DEVICE_LABEL_LENGTH: usize = 100;
+
+#[derive(Serialize, ToSchema)]
+struct SessionResponse {
+    id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_address: Option<String>,
+    created: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+    // whether this is the session making the request
+    current: bool,
+}
+impl SessionResponse {
+    fn new(session: &session::Model, current: &Uuid) -> SessionResponse {
+        SessionResponse {
+            id: session.session_id,
+            device_label: session.device_label.clone(),
+            user_agent: session.user_agent.clone(),
+            ip_address: session.ip_address.clone(),
+            created: Utc.timestamp_opt(session.iat, 0).single(),
+            last_seen: session
+                .last_seen
+                .and_then(|last_seen| Utc.timestamp_opt(last_seen, 0).single()),
+            current: &session.session_id == current,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 struct UserResponse {
-    user: user::Model,
-    sessions: Vec<session::Model>,
+    user: dto::User,
+    sessions: Vec<SessionResponse>,
 }
 impl UserResponse {
-    fn new(query: Vec<(user::Model, Vec<session::Model>)>) -> UserResponse {
-        let result = query.get(0).unwrap();
+    fn new(user: user::Model, sessions: Vec<session::Model>, current: &Uuid) -> UserResponse {
         UserResponse {
-            user: result.0.clone(),
-            sessions: result.1.clone(),
+            user: dto::User::from(&user),
+            sessions: sessions
+                .iter()
+                .map(|s| SessionResponse::new(s, current))
+                .collect(),
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/user",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user and their sessions", body = ResponseBody<UserResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
 async fn get_user(
     user: Authenticated,
     db: web::Data<DatabaseConnection>,
-) -> Result<HttpResponse, Error> {
-    let query = user::Entity::find_by_id(user.user_id)
+) -> Result<Envelope<UserResponse>, ApiError> {
+    let (model, sessions) = user::Entity::find_by_id(user.user_id)
         .find_with_related(session::Entity)
         .all(db.as_ref())
-        .await;
-    // if there was a database error, return an internal server error
-    if query.is_err() {
-        return Ok(HttpResponse::InternalServerError().body(""));
+        .await?
+        .pop()
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(Envelope::ok(UserResponse::new(
+        model,
+        sessions,
+        &user.session_id,
+    )))
+}
+
+const SESSION_SORTABLE: &[Sortable<session::Column>] = &[
+    ("last_seen", session::Column::LastSeen),
+    ("created", session::Column::Iat),
+];
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/sessions",
+    tag = "user",
+    description = "Sortable by last_seen and created, most recently used first by default",
+    params(ListParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The caller's sessions", body = ResponseBody<Vec<SessionResponse>>),
+        (status = 400, description = "Invalid sort, cursor or page", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn get_sessions(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    params: web::Query<ListParams>,
+    version: ApiVersion,
+) -> Result<Envelope<Vec<SessionResponse>>, ApiError> {
+    let select = session::Entity::find().filter(session::Column::UserId.eq(user.user_id));
+    let (sessions, pagination) = query_utils::list(
+        select,
+        &params,
+        SESSION_SORTABLE,
+        "-last_seen",
+        version,
+        db.get_ref(),
+    )
+    .await?;
+
+    Ok(Envelope::list(
+        sessions
+            .iter()
+            .map(|s| SessionResponse::new(s, &user.session_id))
+            .collect(),
+        pagination,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct UpdateSessionRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_label: Option<String>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/sessions/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "The session id")),
+    request_body = UpdateSessionRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The updated session", body = ResponseBody<SessionResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The label is too long", body = ErrorBody),
+        (status = 404, description = "No such session", body = ErrorBody),
+    )
+)]
+async fn update_session(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+    body: web::Json<UpdateSessionRequest>,
+) -> Result<Envelope<SessionResponse>, ApiError> {
+    let label = body.device_label.as_ref().map(|l| l.trim().to_string());
+    if let Some(label) = &label {
+        if label.chars().count() > MAX_DEVICE_LABEL_LENGTH {
+            return Err(ApiError::invalid(
+                "device_label",
+                "too_long",
+                format!(
+                    "Device label must be at most {} characters",
+                    MAX_DEVICE_LABEL_LENGTH
+                ),
+            ));
+        }
+    }
+
+    let model = session::Entity::find()
+        .filter(session::Column::SessionId.eq(*id))
+        .filter(session::Column::UserId.eq(user.user_id))
+        .one(db.get_ref())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let mut active_model: session::ActiveModel = model.into();
+    active_model.device_label = Set(label.filter(|l| !l.is_empty()));
+    let result = active_model.update(db.get_ref()).await?;
+    Ok(Envelope::ok(SessionResponse::new(
+        &result,
+        &user.session_id,
+    )))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/sessions/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "The session id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The session was ended"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such session", body = ErrorBody),
+    )
+)]
+async fn delete_session(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = session::Entity::delete_many()
+        .filter(session::Column::SessionId.eq(*id))
+        .filter(session::Column::UserId.eq(user.user_id))
+        .exec(db.get_ref())
+        .await?
+        .rows_affected;
+
+    match deleted {
+        0 => Err(ApiError::NotFound),
+        _ => Ok(HttpResponse::Ok().body("")),
+    }
+}
+
+// logs out every device except the one making the request
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/sessions",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every other session was ended"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn delete_other_sessions(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, ApiError> {
+    session::Entity::delete_many()
+        .filter(session::Column::UserId.eq(user.user_id))
+        .filter(session::Column::SessionId.ne(user.session_id))
+        .exec(db.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user",
+    tag = "user",
+    request_body = PasswordConfirmation,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The account and its data were deleted"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "No password or reauth token was given", body = ErrorBody),
+        (status = 403, description = "The password or reauth token is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong passwords", body = ErrorBody),
+    )
+)]
+async fn delete_user(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<PasswordConfirmation>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    // deleting an account is irreversible, so the user has to prove who they are again
+    confirm(
+        &req,
+        &throttle,
+        &user,
+        &model,
+        "password",
+        &body.password,
+        &body.reauth_token,
+    )
+    .await?;
+
+    model.delete_account(db.get_ref()).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ChangePasswordRequest {
+    current_password: Option<String>,
+    // instead of the current password, for accounts that don't have one yet
+    reauth_token: Option<String>,
+    new_password: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password",
+    tag = "user",
+    request_body = ChangePasswordRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The password was changed, other sessions were ended"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The new password is too weak, or no current password or reauth token was given", body = ErrorBody),
+        (status = 403, description = "The password or reauth token is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong passwords", body = ErrorBody),
+    )
+)]
+async fn change_password(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    check_password("new_password", &body.new_password, &model.username)?;
+    confirm(
+        &req,
+        &throttle,
+        &user,
+        &model,
+        "current_password",
+        &body.current_password,
+        &body.reauth_token,
+    )
+    .await?;
+
+    let password_hash = hashing::hash_password(body.new_password.to_owned()).await?;
+    model
+        .change_password(password_hash, user.session_id, db.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+// mails a new verification link, for when the first one expired or got lost
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/email/verification",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "A verification link will be mailed"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The username isn't an email address", body = ErrorBody),
+        (status = 409, description = "The address is already verified", body = ErrorBody),
+    )
+)]
+async fn resend_verification(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    if !user::is_email(&model.username) {
+        return Err(ApiError::invalid(
+            "username",
+            "not_an_email",
+            "Username is not an email address",
+        ));
+    }
+    if model.email_verified_at.is_some() {
+        return Err(ApiError::EmailVerified);
+    }
+    send_email_verification(model, db);
+    Ok(HttpResponse::Accepted().body(""))
+}
+
+async fn find_user(user: &Authenticated, db: &DatabaseConnection) -> Result<user::Model, ApiError> {
+    user::Entity::find_by_id(user.user_id)
+        .one(db)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct TotpEnrolmentResponse {
+    secret: String,
+    // otpauth:// uri to render as a qr code
+    provisioning_uri: String,
+}
+
+// confirms a sensitive action. accounts without a password send the token from logging in at
+// the identity provider again instead, see reauth
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PasswordConfirmation {
+    password: Option<String>,
+    reauth_token: Option<String>,
+}
+
+async fn confirm(
+    req: &HttpRequest,
+    throttle: &LoginThrottle,
+    user: &Authenticated,
+    model: &user::Model,
+    password_field: &str,
+    password: &Option<String>,
+    reauth_token: &Option<String>,
+) -> Result<(), ApiError> {
+    if let Some(token) = reauth_token {
+        // only good for the session that logged in at the provider
+        return match decode_reauth_token(token) {
+            Ok(claims)
+                if claims.reauth_user_id == model.id
+                    && claims.reauth_session_id == user.session_id =>
+            {
+                Ok(())
+            }
+            _ => Err(ApiError::InvalidCredentials),
+        };
+    }
+    match password {
+        Some(password) if model.has_password => {
+            // throttled like logins, or a stolen access token could guess the password
+            let keys = password_keys(&model.username, client_ip(req));
+            if let Some(retry_after) = throttle.retry_after(&keys) {
+                return Err(ApiError::TooManyAttempts(retry_after));
+            }
+            match verify_password(model, password.to_owned()).await? {
+                true => {
+                    throttle.clear(&keys[0]);
+                    Ok(())
+                }
+                false => {
+                    throttle.record_failure(&keys);
+                    Err(ApiError::InvalidCredentials)
+                }
+            }
+        }
+        _ if model.has_password => Err(ApiError::invalid(
+            password_field,
+            "required",
+            "The password is required",
+        )),
+        _ => Err(ApiError::invalid(
+            "reauth_token",
+            "required",
+            "Log in at the identity provider again to confirm",
+        )),
+    }
+}
+
+// starts enrolment, the secret isn't used for login until a code from it is confirmed.
+// the password is asked for, so a stolen token can't bind its own authenticator to the account
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/mfa/totp",
+    tag = "user",
+    request_body = PasswordConfirmation,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The secret to add to an authenticator", body = ResponseBody<TotpEnrolmentResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "No password or reauth token was given", body = ErrorBody),
+        (status = 403, description = "The password or reauth token is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong passwords", body = ErrorBody),
+        (status = 409, description = "Two factor authentication is already enabled", body = ErrorBody),
+    )
+)]
+async fn enrol_totp(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<PasswordConfirmation>,
+) -> Result<Envelope<TotpEnrolmentResponse>, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    confirm(
+        &req,
+        &throttle,
+        &user,
+        &model,
+        "password",
+        &body.password,
+        &body.reauth_token,
+    )
+    .await?;
+    if model.totp_enabled {
+        return Err(ApiError::TotpEnabled);
+    }
+    let secret = totp::generate_secret();
+    let encrypted = totp::encrypt_secret(&secret).map_err(ApiError::internal)?;
+    let response = TotpEnrolmentResponse {
+        secret: totp::encode_secret(&secret),
+        provisioning_uri: totp::provisioning_uri(&secret, &model.username),
+    };
+    model.start_totp_enrolment(encrypted, db.get_ref()).await?;
+    Ok(Envelope::ok(response))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ConfirmTotpRequest {
+    code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/mfa/totp/confirm",
+    tag = "user",
+    request_body = ConfirmTotpRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Two factor authentication is enabled", body = ResponseBody<RecoveryCodesResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "The code is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong codes", body = ErrorBody),
+        (status = 409, description = "Already enabled, or enrolment hasn't started", body = ErrorBody),
+    )
+)]
+async fn confirm_totp(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<ConfirmTotpRequest>,
+) -> Result<Envelope<RecoveryCodesResponse>, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    if model.totp_enabled {
+        return Err(ApiError::TotpEnabled);
+    }
+    let secret = model
+        .totp_secret
+        .as_deref()
+        .map(totp::decrypt_secret)
+        .ok_or(ApiError::TotpNotEnrolled)?
+        .map_err(ApiError::internal)?;
+    // six digit codes are guessable, so failures count like those at login
+    let keys = [ThrottleKey::Mfa(model.id)];
+    if let Some(retry_after) = throttle.retry_after(&keys) {
+        return Err(ApiError::TooManyAttempts(retry_after));
+    }
+    let verified = match totp::verify(&secret, &body.code, Utc::now().timestamp()) {
+        Some(step) => model.claim_totp_step(step, db.get_ref()).await?,
+        None => false,
+    };
+    if !verified {
+        throttle.record_failure(&keys);
+        return Err(ApiError::invalid("code", "invalid_code", "Invalid code"));
+    }
+    throttle.clear(&keys[0]);
+
+    let recovery_codes = model.enable_totp(db.get_ref()).await?;
+    Ok(Envelope::ok(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/mfa/totp",
+    tag = "user",
+    request_body = PasswordConfirmation,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Two factor authentication is disabled"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "No password or reauth token was given", body = ErrorBody),
+        (status = 403, description = "The password or reauth token is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong passwords", body = ErrorBody),
+    )
+)]
+async fn disable_totp(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<PasswordConfirmation>,
+) -> Result<HttpResponse, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    confirm(
+        &req,
+        &throttle,
+        &user,
+        &model,
+        "password",
+        &body.password,
+        &body.reauth_token,
+    )
+    .await?;
+    model.disable_totp(db.get_ref()).await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+// replaces every recovery code, for when they are lost or running out
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/mfa/recovery_codes",
+    tag = "user",
+    request_body = PasswordConfirmation,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The new recovery codes", body = ResponseBody<RecoveryCodesResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 400, description = "No password or reauth token was given", body = ErrorBody),
+        (status = 403, description = "The password or reauth token is wrong", body = ErrorBody),
+        (status = 429, description = "Too many wrong passwords", body = ErrorBody),
+        (status = 409, description = "Two factor authentication isn't enabled", body = ErrorBody),
+    )
+)]
+async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    throttle: web::Data<LoginThrottle>,
+    body: web::Json<PasswordConfirmation>,
+) -> Result<Envelope<RecoveryCodesResponse>, ApiError> {
+    let model = find_user(&user, db.get_ref()).await?;
+    confirm(
+        &req,
+        &throttle,
+        &user,
+        &model,
+        "password",
+        &body.password,
+        &body.reauth_token,
+    )
+    .await?;
+    if !model.totp_enabled {
+        return Err(ApiError::TotpNotEnabled);
+    }
+    let recovery_codes = recovery_code::Model::regenerate(model.id, db.get_ref()).await?;
+    Ok(Envelope::ok(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/identities",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Linked identities, oldest first", body = ResponseBody<Vec<dto::Identity>>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn get_identities(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<Envelope<Vec<dto::Identity>>, ApiError> {
+    let identities = external_identity::Entity::find()
+        .filter(external_identity::Column::UserId.eq(user.user_id))
+        .order_by_asc(external_identity::Column::CreatedAt)
+        .all(db.get_ref())
+        .await?;
+    Ok(Envelope::ok(dto::list(&identities)))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct AuthorizationResponse {
+    authorization_url: String,
+}
+
+// the login has to finish in the browser that got the state cookie, so clients call this
+// from the browser they send to the provider
+async fn start_oidc(
+    oidc: &OidcClient,
+    purpose: Purpose,
+) -> Result<Envelope<AuthorizationResponse>, ApiError> {
+    if oidc.config.is_none() {
+        return Err(ApiError::NotFound);
+    }
+    match oidc.authorization_url(purpose).await {
+        Ok((authorization_url, cookie)) => {
+            Ok(Envelope::ok(AuthorizationResponse { authorization_url })
+                .header((SET_COOKIE, cookie.to_string())))
+        }
+        Err(err) => {
+            error!("Failed to start single sign-on: {:?}", err);
+            Err(ApiError::Upstream)
+        }
+    }
+}
+
+// the client sends the browser to the returned url, the callback then links the identity
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/identities/oidc",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The url to send the browser to", body = ResponseBody<AuthorizationResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "Single sign-on isn't configured", body = ErrorBody),
+        (status = 502, description = "The identity provider is unavailable", body = ErrorBody),
+    )
+)]
+async fn link_identity(
+    user: Authenticated,
+    oidc: web::Data<OidcClient>,
+) -> Result<Envelope<AuthorizationResponse>, ApiError> {
+    start_oidc(&oidc, Purpose::Link(user.user_id)).await
+}
+
+// accounts without a password confirm sensitive actions by logging in at the provider again,
+// the callback then returns a reauth_token for this session
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/reauth/oidc",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The url to send the browser to", body = ResponseBody<AuthorizationResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "Single sign-on isn't configured", body = ErrorBody),
+        (status = 502, description = "The identity provider is unavailable", body = ErrorBody),
+    )
+)]
+async fn reauth(
+    user: Authenticated,
+    oidc: web::Data<OidcClient>,
+) -> Result<Envelope<AuthorizationResponse>, ApiError> {
+    let purpose = Purpose::Reauth {
+        user_id: user.user_id,
+        session_id: user.session_id,
+    };
+    start_oidc(&oidc, purpose).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/identities/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "The identity id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The identity was unlinked"),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such identity", body = ErrorBody),
+        (status = 409, description = "It's the only way left to log in", body = ErrorBody),
+    )
+)]
+async fn unlink_identity(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let txn = db.begin().await?;
+    // locked so two unlinks can't each leave the other's identity as the last one
+    let model = user::Entity::find_by_id(user.user_id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let deleted = external_identity::Entity::delete_many()
+        .filter(external_identity::Column::Id.eq(*id))
+        .filter(external_identity::Column::UserId.eq(user.user_id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    if !model.has_password {
+        let remaining = external_identity::Entity::find()
+            .filter(external_identity::Column::UserId.eq(user.user_id))
+            .count(&txn)
+            .await?;
+        if remaining == 0 {
+            return Err(ApiError::LastLoginMethod);
+        }
+    }
+    txn.commit().await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+fn zip_response(bytes: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("export.zip".to_string())],
+        })
+        .body(bytes)
+}
+
+#[derive(Serialize, ToSchema)]
+struct ExportJobResponse {
+    id: sea_orm::prelude::Uuid,
+    status: ExportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_url: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/export",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The export, for small accounts", content_type = "application/zip"),
+        (status = 202, description = "The export was started in the background", body = ResponseBody<ExportJobResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    )
+)]
+async fn export_user(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+) -> Result<Either<HttpResponse, Envelope<ExportJobResponse>>, ApiError> {
+    let rows = accounting_entry::Entity::find()
+        .inner_join(schedule::Entity)
+        .filter(schedule::Column::UserId.eq(user.user_id))
+        .count(db.get_ref())
+        .await?;
+
+    // small exports are returned straight away
+    if rows < BACKGROUND_EXPORT_ROWS {
+        let data = ExportData::collect(db.get_ref(), user.user_id).await?;
+        let bytes = data.to_zip()?;
+        return Ok(Either::Left(zip_response(bytes)));
+    }
+
+    // the job and its archive are saved in the database, so any instance can report on it
+    let id = export_job::Model::start(user.user_id, db.get_ref())
+        .await?
+        .id;
+    let user_id = user.user_id;
+    rt::spawn(async move {
+        info!("Starting export job {}", id);
+        let archive = match ExportData::collect(db.get_ref(), user_id).await {
+            Ok(data) => data.to_zip(),
+            Err(err) => Err(err.into()),
+        };
+        let archive = match archive {
+            Ok(bytes) => {
+                info!("Export job {} finished", id);
+                Some(bytes)
+            }
+            Err(err) => {
+                error!("Export job {} failed: {:?}", id, err);
+                None
+            }
+        };
+        if let Err(err) = export_job::Model::finish(id, archive, db.get_ref()).await {
+            error!("Failed to save export job {}: {:?}", id, err);
+        }
+    });
+
+    Ok(Either::Right(
+        Envelope::ok(ExportJobResponse {
+            id,
+            status: ExportStatus::Pending,
+            download_url: None,
+        })
+        .status(StatusCode::ACCEPTED),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/export/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "The export job id")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The export job, with a download link once ready", body = ResponseBody<ExportJobResponse>),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such export", body = ErrorBody),
+    )
+)]
+async fn get_export(
+    user: Authenticated,
+    db: web::Data<DatabaseConnection>,
+    id: web::Path<sea_orm::prelude::Uuid>,
+) -> Result<Envelope<ExportJobResponse>, ApiError> {
+    let status = match export_job::Model::status(*id, user.user_id, db.get_ref()).await? {
+        Some(status) => status,
+        None => return Err(ApiError::NotFound),
+    };
+    // every poll of a finished job hands out a fresh short lived link
+    let download_url = match status {
+        ExportStatus::Ready => Some(format!(
+            "/download?token={}",
+            encode_download_token(*id).map_err(ApiError::internal)?
+        )),
+        _ => None,
+    };
+    Ok(Envelope::ok(ExportJobResponse {
+        id: *id,
+        status,
+        download_url,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DownloadQuery {
+    // the signed token from the export's download link
+    token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/download",
+    tag = "user",
+    params(DownloadQuery),
+    responses(
+        (status = 200, description = "The export", content_type = "application/zip"),
+        (status = 403, description = "The link is invalid or expired", body = ErrorBody),
+        (status = 404, description = "The export was deleted", body = ErrorBody),
+    )
+)]
+async fn download_export(
+    db: web::Data<DatabaseConnection>,
+    query: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let claims = decode_download_token(&query.token).map_err(|_| ApiError::LinkExpired)?;
+    match export_job::Model::archive(claims.job_id, db.get_ref()).await? {
+        Some(bytes) => Ok(zip_response(bytes)),
+        None => Err(ApiError::NotFound),
     }
-    let result = query.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::io::{Cursor, Read};
+
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use chrono::Utc;
+    use entity::caregiver_grant::{self, Permission};
+    use entity::{
+        accounting_entry, audit_log, export_job, external_identity, schedule, session, user,
+    };
+    use sea_orm::prelude::Uuid;
+    use sea_orm::{
+        ActiveModelBehavior, ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait,
+        QueryFilter, Set,
+    };
+    use serde_json::{json, Value};
+
+    use crate::test_utils::{self, PASSWORD};
+    use crate::utils::oidc::OidcClient;
+
+    const EXPORT_TABLES: [&str; 5] = [
+        "user",
+        "sessions",
+        "schedules",
+        "accounting_entries",
+        "doses",
+    ];
+
+    fn unzip(bytes: &[u8]) -> zip::ZipArchive<Cursor<&[u8]>> {
+        zip::ZipArchive::new(Cursor::new(bytes)).unwrap()
+    }
+
+    fn read_file(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> String {
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap_or_else(|_| panic!("{} is missing", name))
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn deleting_an_account_deletes_everything_of_it_and_audits_no_personal_data() {
+        let db = test_utils::database().await;
+        let started = Utc::now();
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        let caregiver = test_utils::create_user(&db, PASSWORD).await;
+        let app = test::init_service(test_utils::app(db.clone(), OidcClient::from_env())).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/schedule")
+            .insert_header(bearer.clone())
+            .set_json(json!({ "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "pill_count": 30 }))
+            .to_request();
+        let schedule: Value = test::call_and_read_body_json(&app, req).await;
+        let schedule_id: Uuid = schedule["data"]["id"].as_str().unwrap().parse().unwrap();
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/v1/schedule/{}/doses", schedule_id))
+            .insert_header(bearer.clone())
+            .set_json(json!({ "pills": 2 }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        // grants the user gave and was given
+        for (owner_id, caregiver_id) in [(user.id, caregiver.id), (caregiver.id, user.id)] {
+            let mut grant = caregiver_grant::ActiveModel::new();
+            grant.owner_id = Set(owner_id);
+            grant.caregiver_id = Set(caregiver_id);
+            grant.permission = Set(Permission::Read);
+            grant.accepted_at = Set(Some(Utc::now()));
+            grant.insert(&db).await.unwrap();
+        }
+        let mut identity = external_identity::ActiveModel::new();
+        identity.user_id = Set(user.id);
+        identity.issuer = Set("https://issuer.example.com".to_string());
+        identity.subject = Set(Uuid::new_v4().to_string());
+        identity.email = Set(Some(user.username.clone()));
+        let identity = identity.insert(&db).await.unwrap();
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/user")
+            .insert_header(bearer)
+            .set_json(json!({ "password": PASSWORD }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        assert!(user::Entity::find_by_id(user.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .is_none());
+        let schedules = schedule::Entity::find()
+            .filter(schedule::Column::UserId.eq(user.id))
+            .count(&db)
+            .await
+            .unwrap();
+        let entries = accounting_entry::Entity::find()
+            .filter(accounting_entry::Column::ScheduleId.eq(schedule_id))
+            .count(&db)
+            .await
+            .unwrap();
+        let sessions = session::Entity::find()
+            .filter(session::Column::UserId.eq(user.id))
+            .count(&db)
+            .await
+            .unwrap();
+        let grants = caregiver_grant::Entity::find()
+            .filter(
+                caregiver_grant::Column::OwnerId
+                    .eq(user.id)
+                    .or(caregiver_grant::Column::CaregiverId.eq(user.id)),
+            )
+            .count(&db)
+            .await
+            .unwrap();
+        let identities = external_identity::Entity::find()
+            .filter(external_identity::Column::UserId.eq(user.id))
+            .count(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            (schedules, entries, sessions, grants, identities),
+            (0, 0, 0, 0, 0)
+        );
+
+        // other tests delete accounts too, so only this one's counts are looked for
+        let audits = audit_log::Entity::find()
+            .filter(audit_log::Column::Action.eq("account_deleted"))
+            .filter(audit_log::Column::Timestamp.gte(started))
+            .all(&db)
+            .await
+            .unwrap();
+        let counts = json!({ "schedules": 1, "accounting_entries": 2, "sessions": 1 });
+        assert!(audits
+            .iter()
+            .any(|audit| audit.detail.as_ref() == Some(&counts)));
+        for audit in audits {
+            let audit = serde_json::to_string(&audit).unwrap();
+            for personal in [
+                user.id.to_string(),
+                user.username.clone(),
+                identity.subject.clone(),
+            ] {
+                assert!(!audit.contains(&personal), "{} holds {}", audit, personal);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn exports_hold_every_table_as_csv_and_json() {
+        let db = test_utils::database().await;
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/schedule")
+            .insert_header(bearer.clone())
+            .set_json(json!({ "drug_name": "Ibuprofen", "cron": "0 0 8 * * *", "pill_count": 30 }))
+            .to_request();
+        let schedule: Value = test::call_and_read_body_json(&app, req).await;
+        for dose in [json!({ "pills": 2 }), json!({ "missed": true })] {
+            let req = test::TestRequest::post()
+                .uri(&format!(
+                    "/api/v1/schedule/{}/doses",
+                    schedule["data"]["id"].as_str().unwrap()
+                ))
+                .insert_header(bearer.clone())
+                .set_json(dose)
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/user/export")
+            .insert_header(bearer)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let bytes = test::read_body(res).await;
+        let mut archive = unzip(&bytes);
+
+        let mut expected: BTreeSet<String> = EXPORT_TABLES
+            .iter()
+            .flat_map(|table| [format!("{}.json", table), format!("{}.csv", table)])
+            .collect();
+        expected.insert("manifest.json".to_string());
+        let names: BTreeSet<String> = archive.file_names().map(str::to_string).collect();
+        assert_eq!(names, expected);
+
+        let manifest: Value =
+            serde_json::from_str(&read_file(&mut archive, "manifest.json")).unwrap();
+        assert_eq!(manifest["version"], 2);
+        assert_eq!(manifest["user_id"], user.id.to_string());
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), EXPORT_TABLES.len());
+        // the initial count is an accounting entry, the dose and the missed dose are doses
+        for (file, (table, records)) in files.iter().zip(EXPORT_TABLES.iter().zip([1, 1, 1, 1, 2]))
+        {
+            assert_eq!(file["table"], *table);
+            assert_eq!(file["records"], records);
+            assert_eq!(file["json"], format!("{}.json", table));
+            assert_eq!(file["csv"], format!("{}.csv", table));
 
-    Ok(HttpResponse::Ok().json(UserResponse::new(result)))
+            let json: Value =
+                serde_json::from_str(&read_file(&mut archive, &format!("{}.json", table))).unwrap();
+            assert_eq!(json.as_array().unwrap().len(), records);
+            // a header line and one per record
+            let csv = read_file(&mut archive, &format!("{}.csv", table));
+            assert_eq!(csv.lines().count(), records + 1);
+        }
+
+        let profile = read_file(&mut archive, "user.json");
+        assert!(profile.contains(&user.username));
+        assert!(!profile.contains("\"password\""));
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn background_exports_are_downloaded_from_the_database() {
+        let db = test_utils::database().await;
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        let (_, other) = test_utils::signed_in(&db).await;
+        let job = export_job::Model::start(user.id, &db).await.unwrap();
+        let app = test::init_service(test_utils::app(db.clone(), OidcClient::from_env())).await;
+
+        let status = |bearer: (&'static str, String)| {
+            test::TestRequest::get()
+                .uri(&format!("/api/v1/user/export/{}", job.id))
+                .insert_header(bearer)
+                .to_request()
+        };
+        let body: Value = test::call_and_read_body_json(&app, status(bearer.clone())).await;
+        assert_eq!(body["data"]["status"], "pending");
+        assert!(body["data"].get("download_url").is_none());
+        // jobs are only visible to the user who started them
+        let res = test::call_service(&app, status(other)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        export_job::Model::finish(job.id, Some(b"archive".to_vec()), &db)
+            .await
+            .unwrap();
+        let body: Value = test::call_and_read_body_json(&app, status(bearer)).await;
+        assert_eq!(body["data"]["status"], "ready");
+        let url = body["data"]["download_url"].as_str().unwrap();
+        assert!(url.starts_with("/download?token="));
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(url).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await.as_ref(), b"archive");
+        let req = test::TestRequest::get()
+            .uri("/download?token=forged")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn password_confirmations_are_throttled_like_logins() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+        let delete = |password: &str| {
+            test::TestRequest::delete()
+                .uri("/api/v1/user")
+                .insert_header(bearer.clone())
+                .set_json(json!({ "password": password }))
+                .to_request()
+        };
+
+        for attempt in 1..=6 {
+            let res = test::call_service(&app, delete("not the password")).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "attempt {}", attempt);
+        }
+        // locked out, even with the right password
+        let res = test::call_service(&app, delete(PASSWORD)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "1");
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn totp_enrolment_needs_the_password() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        for (body, status) in [
+            (json!({}), StatusCode::BAD_REQUEST),
+            (
+                json!({ "password": "not the password" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (json!({ "password": PASSWORD }), StatusCode::OK),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/user/mfa/totp")
+                .insert_header(bearer.clone())
+                .set_json(body)
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sessions_are_paged_by_cursor_except_on_the_legacy_routes() {
+        let db = test_utils::database().await;
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        for _ in 0..2 {
+            test_utils::bearer(&db, &user).await;
+        }
+        let app = test::init_service(test_utils::app(db, OidcClient::from_env())).await;
+
+        let get = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(bearer.clone())
+                .to_request()
+        };
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/v1/user/sessions?page_size=2")).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        assert!(body["pagination"]["next_cursor"].is_string());
+
+        // the legacy routes get every session in their largest page, or pages by offset
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/user/sessions")).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, get("/api/user/sessions?page_size=2&page_num=1"))
+                .await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let res = test::call_service(
+            &app,
+            get(&format!("/api/v1/user/sessions?page_num={}", u64::MAX)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,20 @@
+use crate::models::error::ApiError;
+use crate::utils::keys::{JwkSet, KEYS};
+use actix_web::{web, HttpResponse};
+
+pub fn well_known_service(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/jwks.json").route(web::get().to(get_jwks)));
+}
+
+// public keys for other services to verify our tokens with
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "well-known",
+    responses(
+        (status = 200, description = "The keys tokens are currently verified with", body = JwkSet),
+    )
+)]
+async fn get_jwks() -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(KEYS.jwks()))
+}
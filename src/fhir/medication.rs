@@ -0,0 +1,218 @@
+use entity::accounting_entry::{self, EntryKind};
+use entity::{product, schedule};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{
+    date_time, CodeableConcept, Coding, Dosage, DoseAndRate, Period, Quantity, Reference, Timing,
+    TimingRepeat, FDA_APPLICATION_SYSTEM,
+};
+use crate::utils::cron_utils::{describe_schedule, ScheduleDefinition, TimeOfDay};
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationStatement {
+    pub id: String,
+    pub status: String,
+    pub medication_codeable_concept: CodeableConcept,
+    pub subject: Reference,
+    pub effective_period: Period,
+    pub date_asserted: String,
+    pub dosage: Vec<Dosage>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationRequest {
+    pub id: String,
+    pub status: String,
+    pub intent: String,
+    pub medication_codeable_concept: CodeableConcept,
+    pub subject: Reference,
+    pub authored_on: String,
+    pub dosage_instruction: Vec<Dosage>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationAdministration {
+    pub id: String,
+    pub status: String,
+    pub medication_codeable_concept: CodeableConcept,
+    pub subject: Reference,
+    pub effective_date_time: String,
+    pub supporting_information: Vec<Reference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dosage: Option<AdministrationDosage>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdministrationDosage {
+    pub dose: Quantity,
+}
+
+impl MedicationStatement {
+    pub fn from_schedule(
+        schedule: &schedule::Model,
+        product: Option<&product::Model>,
+    ) -> MedicationStatement {
+        MedicationStatement {
+            id: schedule.id.to_string(),
+            // schedules have no end date, so every stored one is being taken
+            status: "active".to_string(),
+            medication_codeable_concept: medication(schedule, product),
+            subject: Reference::patient(&schedule.user_id),
+            effective_period: Period {
+                start: date_time(&schedule.added_at),
+            },
+            date_asserted: date_time(&schedule.added_at),
+            dosage: vec![dosage(schedule)],
+        }
+    }
+}
+
+impl MedicationRequest {
+    pub fn from_schedule(
+        schedule: &schedule::Model,
+        product: Option<&product::Model>,
+    ) -> MedicationRequest {
+        MedicationRequest {
+            id: schedule.id.to_string(),
+            status: "active".to_string(),
+            // schedules are entered by the patient, not ordered by a prescriber
+            intent: "plan".to_string(),
+            medication_codeable_concept: medication(schedule, product),
+            subject: Reference::patient(&schedule.user_id),
+            authored_on: date_time(&schedule.added_at),
+            dosage_instruction: vec![dosage(schedule)],
+        }
+    }
+}
+
+impl MedicationAdministration {
+    // a dose or missed dose on the schedule's ledger, other entries aren't administrations
+    pub fn from_accounting_entry(
+        entry: &accounting_entry::Model,
+        schedule: &schedule::Model,
+        product: Option<&product::Model>,
+    ) -> MedicationAdministration {
+        let missed = entry.kind == EntryKind::Missed;
+        MedicationAdministration {
+            id: entry.id.to_string(),
+            status: match missed {
+                true => "not-done",
+                false => "completed",
+            }
+            .to_string(),
+            medication_codeable_concept: medication(schedule, product),
+            subject: Reference::patient(&schedule.user_id),
+            effective_date_time: date_time(&entry.timestamp),
+            supporting_information: vec![Reference {
+                reference: format!("MedicationStatement/{}", schedule.id),
+            }],
+            // nothing was given for a missed dose
+            dosage: (!missed).then(|| AdministrationDosage {
+                dose: Quantity {
+                    value: -entry.amount,
+                    unit: "pill".to_string(),
+                },
+            }),
+        }
+    }
+}
+
+// code the medication with the FDA application of the matching product when there is one
+fn medication(schedule: &schedule::Model, product: Option<&product::Model>) -> CodeableConcept {
+    let coding = match product {
+        Some(product) => vec![Coding {
+            system: FDA_APPLICATION_SYSTEM.to_string(),
+            code: product.appl_no.clone(),
+            display: product
+                .drug_name
+                .as_ref()
+                .map(|name| match &product.strength {
+                    Some(strength) => format!("{} {}", name, strength),
+                    None => name.clone(),
+                }),
+        }],
+        None => vec![],
+    };
+    CodeableConcept {
+        coding,
+        text: Some(schedule.drug_name.clone()),
+    }
+}
+
+fn dosage(schedule: &schedule::Model) -> Dosage {
+    let description = describe_schedule(schedule);
+    let dose_and_rate = match schedule.pill_amount {
+        0 => vec![],
+        amount => vec![DoseAndRate {
+            dose_quantity: Quantity {
+                value: amount,
+                unit: "pill".to_string(),
+            },
+        }],
+    };
+    Dosage {
+        text: description.clone(),
+        timing: Timing {
+            repeat: ScheduleDefinition::of(schedule)
+                .or_else(|| ScheduleDefinition::from_cron(&schedule.cron))
+                .map(repeat),
+            code: CodeableConcept {
+                coding: vec![],
+                text: Some(description),
+            },
+        },
+        dose_and_rate,
+    }
+}
+
+fn time_of_day(time: &TimeOfDay) -> String {
+    format!("{}:00", time)
+}
+
+fn repeat(definition: ScheduleDefinition) -> TimingRepeat {
+    match definition {
+        ScheduleDefinition::Interval { hours, start } => TimingRepeat {
+            frequency: Some(24 / hours),
+            period: Some(1),
+            period_unit: Some("d".to_string()),
+            time_of_day: (0..24 / hours)
+                .map(|i| TimeOfDay {
+                    hour: (start.hour + i * hours) % 24,
+                    minute: start.minute,
+                })
+                .map(|time| time_of_day(&time))
+                .collect(),
+            ..Default::default()
+        },
+        ScheduleDefinition::Daily { times } => TimingRepeat {
+            frequency: Some(times.len() as u32),
+            period: Some(1),
+            period_unit: Some("d".to_string()),
+            time_of_day: times.iter().map(time_of_day).collect(),
+            ..Default::default()
+        },
+        ScheduleDefinition::Weekly { days, time } => TimingRepeat {
+            day_of_week: days.iter().map(|d| d.to_string().to_lowercase()).collect(),
+            time_of_day: vec![time_of_day(&time)],
+            ..Default::default()
+        },
+        ScheduleDefinition::EveryNDays {
+            days,
+            time,
+            start_date,
+        } => TimingRepeat {
+            bounds_period: Some(Period {
+                start: start_date.to_string(),
+            }),
+            frequency: Some(1),
+            period: Some(days),
+            period_unit: Some("d".to_string()),
+            time_of_day: vec![time_of_day(&time)],
+            ..Default::default()
+        },
+    }
+}
@@ -0,0 +1,356 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use sea_orm::prelude::Uuid;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub mod medication;
+
+pub const CONTENT_TYPE: &str = "application/fhir+json";
+
+// Drugs@FDA application numbers, the products table has no NDC codes
+pub const FDA_APPLICATION_SYSTEM: &str =
+    "https://www.fda.gov/drugs/drug-approvals-and-databases/drugsfda-data-files";
+
+// FHIR R4 resources returned by the api, serialized with their resourceType
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "resourceType")]
+pub enum Resource {
+    Bundle(Bundle),
+    MedicationStatement(medication::MedicationStatement),
+    MedicationRequest(medication::MedicationRequest),
+    MedicationAdministration(medication::MedicationAdministration),
+    OperationOutcome(OperationOutcome),
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Bundle {
+    #[serde(rename = "type")]
+    pub bundle_type: String,
+    pub total: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub link: Vec<BundleLink>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entry: Vec<BundleEntry>,
+}
+
+impl Bundle {
+    // wraps every resource as a match of a search
+    pub fn searchset(resources: Vec<(String, Resource)>) -> Bundle {
+        let total = resources.len();
+        Bundle::page(resources, total, vec![])
+    }
+
+    // one page of a search, total counts the matches across every page
+    pub fn page(resources: Vec<(String, Resource)>, total: usize, link: Vec<BundleLink>) -> Bundle {
+        let entry: Vec<BundleEntry> = resources
+            .into_iter()
+            .map(|(id, resource)| BundleEntry {
+                full_url: format!("urn:uuid:{}", id),
+                resource,
+                search: BundleSearch {
+                    mode: "match".to_string(),
+                },
+            })
+            .collect();
+        Bundle {
+            bundle_type: "searchset".to_string(),
+            total,
+            link,
+            entry,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BundleLink {
+    pub relation: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleEntry {
+    pub full_url: String,
+    // bundles are resources themselves, the schema would recurse forever
+    #[schema(no_recursion)]
+    pub resource: Resource,
+    pub search: BundleSearch,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BundleSearch {
+    pub mode: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OperationOutcome {
+    pub issue: Vec<OperationOutcomeIssue>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OperationOutcomeIssue {
+    pub severity: String,
+    pub code: String,
+    pub diagnostics: String,
+}
+
+impl OperationOutcome {
+    pub fn error(code: &str, diagnostics: &str) -> Resource {
+        Resource::OperationOutcome(OperationOutcome {
+            issue: vec![OperationOutcomeIssue {
+                severity: "error".to_string(),
+                code: code.to_string(),
+                diagnostics: diagnostics.to_string(),
+            }],
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Reference {
+    pub reference: String,
+}
+
+impl Reference {
+    pub fn patient(user_id: &Uuid) -> Reference {
+        Reference {
+            reference: format!("Patient/{}", user_id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CodeableConcept {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub coding: Vec<Coding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Coding {
+    pub system: String,
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Quantity {
+    pub value: i32,
+    pub unit: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Period {
+    pub start: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Timing {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<TimingRepeat>,
+    pub code: CodeableConcept,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TimingRepeat {
+    // when the repetitions start, for intervals counted from a date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds_period: Option<Period>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period_unit: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub day_of_week: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub time_of_day: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Dosage {
+    pub text: String,
+    pub timing: Timing,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dose_and_rate: Vec<DoseAndRate>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DoseAndRate {
+    pub dose_quantity: Quantity,
+}
+
+// FHIR dateTime with a timezone and whole seconds
+pub fn date_time(value: &DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use entity::accounting_entry::{self, EntryKind};
+    use entity::{product, schedule};
+    use octofhir_fhirschema::{get_schemas, FhirValidator, FhirVersion, InMemorySchemaProvider};
+    use sea_orm::prelude::Uuid;
+    use serde_json::{json, Value};
+
+    use super::medication::{MedicationAdministration, MedicationRequest, MedicationStatement};
+    use super::{Bundle, BundleLink, OperationOutcome, Resource};
+
+    fn validator() -> FhirValidator {
+        let schemas = get_schemas(FhirVersion::R4)
+            .iter()
+            .map(|(name, schema)| (name.clone(), Arc::new(schema.clone())))
+            .collect::<HashMap<_, _>>();
+        FhirValidator::new(Arc::new(InMemorySchemaProvider::from_map(schemas)))
+    }
+
+    // the validator doesn't descend into bundle entries, so each resource is checked on its own
+    async fn assert_valid(validator: &FhirValidator, resource: &Resource) {
+        let json = serde_json::to_value(resource).unwrap();
+        let mut resources = vec![json.clone()];
+        if let Some(Value::Array(entries)) = json.get("entry") {
+            resources.extend(entries.iter().map(|entry| entry["resource"].clone()));
+        }
+        for resource in resources {
+            let resource_type = resource["resourceType"].as_str().unwrap().to_string();
+            let result = validator
+                .validate(&resource, vec![resource_type.clone()])
+                .await;
+            assert!(
+                result.valid,
+                "{} is not valid R4: {:?}\n{}",
+                resource_type, result.errors, resource
+            );
+        }
+    }
+
+    fn schedule(cron: &str, product: Option<&product::Model>) -> schedule::Model {
+        schedule::Model {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            drug_name: "Ibuprofen".to_string(),
+            pill_count: 20,
+            pill_amount: 2,
+            cron: cron.to_string(),
+            definition: None,
+            added_at: Utc::now(),
+            updated_by: None,
+            version: 1,
+            product_appl_no: product.map(|p| p.appl_no.clone()),
+            product_no: product.map(|p| p.product_no.clone()),
+        }
+    }
+
+    fn product() -> product::Model {
+        product::Model {
+            product_no: "001".to_string(),
+            appl_no: "017463".to_string(),
+            form: Some("TABLET;ORAL".to_string()),
+            strength: Some("400MG".to_string()),
+            reference_drug: Some(0),
+            drug_name: Some("MOTRIN".to_string()),
+            active_ingredient: Some("IBUPROFEN".to_string()),
+            reference_standard: Some(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn bundles_are_valid_r4() {
+        let validator = validator();
+        let product = product();
+        let schedules = [
+            schedule("0 0 8,20 * * *", Some(&product)),
+            schedule("0 30 9 * * Mon,Wed,Fri", None),
+            schedule("0 30 6/8 * * *", Some(&product)),
+            schedule("0 0 9 1,15 * *", None),
+            schedule::Model {
+                definition: Some(json!({
+                    "type": "every_n_days",
+                    "days": 2,
+                    "time": "09:00",
+                    "start_date": "2026-10-20",
+                })),
+                ..schedule("0 0 9 * * *", None)
+            },
+        ];
+        let linked = |s: &schedule::Model| s.product_no.as_ref().map(|_| &product);
+
+        let statements = schedules
+            .iter()
+            .map(|s| {
+                let resource = MedicationStatement::from_schedule(s, linked(s));
+                (s.id.to_string(), Resource::MedicationStatement(resource))
+            })
+            .collect();
+        assert_valid(&validator, &Resource::Bundle(Bundle::searchset(statements))).await;
+
+        let requests = schedules
+            .iter()
+            .map(|s| {
+                let resource = MedicationRequest::from_schedule(s, linked(s));
+                (s.id.to_string(), Resource::MedicationRequest(resource))
+            })
+            .collect();
+        assert_valid(&validator, &Resource::Bundle(Bundle::searchset(requests))).await;
+
+        let dose = |kind, amount| accounting_entry::Model {
+            id: Uuid::new_v4(),
+            schedule_id: schedules[0].id,
+            amount,
+            kind,
+            timestamp: Utc::now(),
+            actor_id: Some(schedules[0].user_id),
+        };
+        let administrations = [dose(EntryKind::Dose, -2), dose(EntryKind::Missed, 0)]
+            .iter()
+            .map(|entry| {
+                let resource = MedicationAdministration::from_accounting_entry(
+                    entry,
+                    &schedules[0],
+                    Some(&product),
+                );
+                (entry.id.to_string(), Resource::MedicationAdministration(resource))
+            })
+            .collect();
+        let link = vec![BundleLink {
+            relation: "next".to_string(),
+            url: "/api/v1/fhir/MedicationAdministration?patient=1&_page=2".to_string(),
+        }];
+        let page = Bundle::page(administrations, 3, link);
+        assert_valid(&validator, &Resource::Bundle(page)).await;
+
+        assert_valid(&validator, &Resource::Bundle(Bundle::searchset(vec![]))).await;
+        assert_valid(
+            &validator,
+            &OperationOutcome::error("forbidden", "Nothing was shared"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn validator_rejects_unknown_elements() {
+        let validator = validator();
+        let resource = serde_json::json!({
+            "resourceType": "MedicationStatement",
+            "status": "active",
+            "subject": {"reference": "Patient/1"},
+            "medicationCodeableConcept": {"text": "Ibuprofen"},
+            "pillCount": 20,
+        });
+        let result = validator
+            .validate(&resource, vec!["MedicationStatement".to_string()])
+            .await;
+        assert!(!result.valid);
+    }
+}
+
@@ -7,12 +7,24 @@ use migration::{Migrator, MigratorTrait};
 use std::env;
 
 use crate::controllers::config_app;
+use crate::utils::events::EventHub;
+use crate::utils::fda_sync::FdaSync;
+use crate::utils::login_throttle::LoginThrottle;
+use crate::utils::oidc::OidcClient;
 mod controllers;
 mod constants;
+mod fhir;
 mod middleware;
 mod models;
+mod services;
+#[cfg(test)]
+mod test_utils;
 mod utils;
 
+// the default format with the request id, to find the log line for an error a client reports.
+// the request line is logged without its query, which can carry tokens like a download link's
+const LOG_FORMAT: &str = r#"%a "%{request_line}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}i"#;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     info!("Application Started");
@@ -21,17 +33,40 @@ async fn main() -> std::io::Result<()> {
         .filter_level(log::LevelFilter::Info)
         .init();
 
+    info!("Loading token signing keys");
+    lazy_static::initialize(&utils::keys::KEYS);
+    lazy_static::initialize(&utils::password_policy::PASSWORD_POLICY);
+    lazy_static::initialize(&utils::totp::SECRET_CIPHER);
+    lazy_static::initialize(&utils::client_ip::PROXIES);
+
     info!("Setting up database connection");
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
     let db = sea_orm::Database::connect(&db_url).await.unwrap();
 
     Migrator::up(&db, None).await.unwrap();
 
+    let login_throttle = web::Data::new(LoginThrottle::default());
+    let fda_sync = web::Data::new(FdaSync::default());
+    let oidc = web::Data::new(OidcClient::from_env());
+    let event_hub = web::Data::new(EventHub::default());
+    EventHub::listen(event_hub.clone(), db_url.clone());
+    utils::jobs::start_session_purge(db.clone());
+    utils::jobs::start_idempotency_key_purge(db.clone());
+    utils::jobs::start_throttle_purge(login_throttle.clone());
+    utils::jobs::start_export_purge(db.clone());
+
     HttpServer::new(move || {
         App::new()
-            .wrap(Logger::default())
+            .wrap(Logger::new(LOG_FORMAT).custom_request_replace("request_line", |req| {
+                format!("{} {} {:?}", req.method(), req.path(), req.version())
+            }))
             .wrap(middleware::auth::AuthenticateMiddlewareFactory {})
+            .wrap(middleware::request_id::RequestIdMiddlewareFactory {})
             .app_data(web::Data::new(db.clone()))
+            .app_data(login_throttle.clone())
+            .app_data(fda_sync.clone())
+            .app_data(oidc.clone())
+            .app_data(event_hub.clone())
             .configure(config_app)
     })
     .bind(("::", 8080))?
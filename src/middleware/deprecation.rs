@@ -0,0 +1,101 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, LINK},
+    Error,
+};
+use chrono::{TimeZone, Utc};
+use futures::{future::LocalBoxFuture, FutureExt};
+
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+// marks every response of a scope as deprecated (RFC 9745), announces when it goes away
+// (RFC 8594) and links the same route in the version replacing it, so clients can find out
+// what to migrate to from any response
+pub struct DeprecationMiddlewareFactory {
+    // unix time the routes were deprecated at
+    pub deprecated_at: i64,
+    // unix time the routes will be removed at
+    pub sunset_at: i64,
+    // the scope's prefix, and the prefix of the scope replacing it
+    pub prefix: &'static str,
+    pub successor: &'static str,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for DeprecationMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeprecationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeprecationMiddleware {
+            service: Rc::new(service),
+            deprecation: HeaderValue::from_str(&format!("@{}", self.deprecated_at)).unwrap(),
+            // an HTTP-date, unlike the deprecation's structured field date
+            sunset: HeaderValue::from_str(
+                &Utc.timestamp_opt(self.sunset_at, 0)
+                    .unwrap()
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            )
+            .unwrap(),
+            prefix: self.prefix,
+            successor: self.successor,
+        }))
+    }
+}
+
+pub struct DeprecationMiddleware<S> {
+    service: Rc<S>,
+    deprecation: HeaderValue,
+    sunset: HeaderValue,
+    prefix: &'static str,
+    successor: &'static str,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let deprecation = self.deprecation.clone();
+        let sunset = self.sunset.clone();
+        let path = req.path().strip_prefix(self.prefix).unwrap_or("");
+        let link = HeaderValue::from_str(&format!(
+            "<{}{}>; rel=\"successor-version\"",
+            self.successor, path
+        ));
+
+        async move {
+            let mut res = srv.call(req).await?;
+            res.headers_mut().insert(DEPRECATION, deprecation);
+            res.headers_mut().insert(SUNSET, sunset);
+            if let Ok(link) = link {
+                res.headers_mut().insert(LINK, link);
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderName, HeaderValue, CONTENT_TYPE, ETAG, LOCATION},
+        Method, StatusCode,
+    },
+    web, Error, HttpMessage, HttpResponse,
+};
+use entity::idempotency_key::{self, Claim};
+use entity::session;
+use futures::{future::LocalBoxFuture, FutureExt};
+use log::error;
+use sea_orm::prelude::Uuid;
+use sea_orm::DatabaseConnection;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::models::error::ApiError;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+const MAX_KEY_LENGTH: usize = 255;
+// the headers handlers set that belong to the replayed response
+const REPLAYED_HEADERS: [HeaderName; 3] = [CONTENT_TYPE, ETAG, LOCATION];
+
+// runs a POST or PUT sent with an Idempotency-Key once, and answers retries with the same key
+// with the saved response, so a retry after a lost response doesn't apply a change twice.
+// keys are per user, requests without a logged in user are passed through
+pub struct IdempotencyMiddlewareFactory {}
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let key = match *req.method() {
+            Method::POST | Method::PUT => req
+                .headers()
+                .get(IDEMPOTENCY_KEY)
+                .map(|key| key.to_str().ok().map(str::to_string)),
+            _ => None,
+        };
+        let user_id = req
+            .extensions()
+            .get::<session::Claims>()
+            .map(|claims| claims.user_id);
+        let db = req.app_data::<web::Data<DatabaseConnection>>().cloned();
+        let (key, user_id, db) = match (key, user_id, db) {
+            (Some(key), Some(user_id), Some(db)) => (key, user_id, db),
+            _ => {
+                return srv
+                    .call(req)
+                    .map(|res| res.map(ServiceResponse::map_into_boxed_body))
+                    .boxed_local()
+            }
+        };
+
+        async move {
+            let key = match key {
+                Some(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key,
+                _ => {
+                    let err = ApiError::invalid(
+                        "Idempotency-Key",
+                        "invalid_idempotency_key",
+                        format!("Send a key of 1 to {} characters", MAX_KEY_LENGTH),
+                    );
+                    return Ok(req.error_response(err));
+                }
+            };
+            let body = req.extract::<web::Bytes>().await?;
+            let fingerprint = fingerprint(&req, &body);
+            req.set_payload(body.into());
+
+            match idempotency_key::Model::claim(user_id, &key, &fingerprint, db.get_ref()).await {
+                Ok(Claim::Claimed) => (),
+                Ok(Claim::Existing(existing)) => {
+                    return Ok(match replay(existing, &fingerprint) {
+                        Ok(response) => req.into_response(response),
+                        Err(err) => req.error_response(err),
+                    });
+                }
+                Err(err) => return Ok(req.error_response(ApiError::from(err))),
+            }
+
+            let res = match srv.call(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    release(user_id, &key, &db).await;
+                    return Err(err);
+                }
+            };
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let body = match body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(_) => {
+                    release(user_id, &key, &db).await;
+                    let err = ApiError::internal(anyhow::anyhow!("Failed to read the response"));
+                    return Ok(ServiceResponse::new(req, HttpResponse::from_error(err)));
+                }
+            };
+
+            // server errors aren't saved, the retry may well succeed
+            if res.status().is_server_error() {
+                release(user_id, &key, &db).await;
+            } else {
+                let headers: Map<String, Value> = REPLAYED_HEADERS
+                    .iter()
+                    .filter_map(|name| {
+                        let value = res.headers().get(name)?.to_str().ok()?;
+                        Some((name.to_string(), Value::from(value)))
+                    })
+                    .collect();
+                let status = res.status().as_u16() as i32;
+                let saved = idempotency_key::Model::complete(
+                    user_id,
+                    &key,
+                    status,
+                    Value::Object(headers),
+                    body.to_vec(),
+                    db.get_ref(),
+                )
+                .await;
+                // the change was made, so the client still gets its response
+                if let Err(err) = saved {
+                    error!("Failed to save the response for idempotency key: {:?}", err);
+                }
+            }
+
+            Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+        }
+        .boxed_local()
+    }
+}
+
+// the same key has to come with the same request
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(req.uri().to_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn replay(existing: idempotency_key::Model, fingerprint: &str) -> Result<HttpResponse, ApiError> {
+    if existing.fingerprint != fingerprint {
+        return Err(ApiError::IdempotencyKeyReused);
+    }
+    let status = existing
+        .status
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .ok_or(ApiError::IdempotencyKeyInUse)?;
+
+    let mut response = HttpResponse::build(status);
+    if let Some(Value::Object(headers)) = existing.headers {
+        for (name, value) in headers {
+            if let (Ok(name), Some(Ok(value))) = (
+                HeaderName::try_from(name),
+                value.as_str().map(HeaderValue::from_str),
+            ) {
+                response.insert_header((name, value));
+            }
+        }
+    }
+    response.insert_header((IDEMPOTENT_REPLAYED, HeaderValue::from_static("true")));
+    Ok(response.body(existing.body.unwrap_or_default()))
+}
+
+async fn release(user_id: Uuid, key: &str, db: &DatabaseConnection) {
+    if let Err(err) = idempotency_key::Model::release(user_id, key, db).await {
+        error!("Failed to release idempotency key: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{test, App};
+    use chrono::Utc;
+    use sea_orm::{ActiveModelTrait, Set};
+    use serde_json::json;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::middleware::auth::AuthenticateMiddlewareFactory;
+    use crate::test_utils;
+
+    // how often the handlers ran, and what the slow one waits for
+    #[derive(Default)]
+    struct Calls {
+        count: AtomicUsize,
+        started: Notify,
+        finish: Notify,
+    }
+
+    async fn count(calls: web::Data<Calls>) -> HttpResponse {
+        let count = calls.count.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Created()
+            .insert_header((ETAG, format!("\"{}\"", count)))
+            .json(json!({ "count": count }))
+    }
+
+    // fails the first time, like a request that hit a database outage
+    async fn flaky(calls: web::Data<Calls>) -> HttpResponse {
+        match calls.count.fetch_add(1, Ordering::SeqCst) {
+            0 => HttpResponse::ServiceUnavailable().finish(),
+            _ => HttpResponse::Created().json(json!({ "ok": true })),
+        }
+    }
+
+    async fn slow(calls: web::Data<Calls>) -> HttpResponse {
+        calls.started.notify_one();
+        calls.finish.notified().await;
+        count(calls).await
+    }
+
+    fn app(
+        db: DatabaseConnection,
+        calls: web::Data<Calls>,
+    ) -> App<
+        impl actix_web::dev::ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .wrap(AuthenticateMiddlewareFactory {})
+            .app_data(web::Data::new(db))
+            .app_data(calls)
+            .service(
+                web::scope("")
+                    .wrap(IdempotencyMiddlewareFactory {})
+                    .route("/count", web::post().to(count))
+                    .route("/flaky", web::post().to(flaky))
+                    .route("/slow", web::post().to(slow)),
+            )
+    }
+
+    fn post(uri: &str, bearer: &(&'static str, String), key: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(bearer.clone())
+            .insert_header((IDEMPOTENCY_KEY, key))
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn retries_get_the_saved_response_and_other_requests_are_refused() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let (_, other) = test_utils::signed_in(&db).await;
+        let calls = web::Data::new(Calls::default());
+        let app = test::init_service(app(db, calls.clone())).await;
+
+        let res = test::call_service(&app, post("/count", &bearer, "a").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        let first = test::read_body(res).await;
+
+        let res = test::call_service(&app, post("/count", &bearer, "a").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"1\"");
+        assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(test::read_body(res).await, first);
+        assert_eq!(calls.count.load(Ordering::SeqCst), 1);
+
+        // the same key with another body or route
+        for req in [
+            post("/count", &bearer, "a").set_payload("{}"),
+            post("/flaky", &bearer, "a"),
+        ] {
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], "idempotency_key_reused");
+        }
+        assert_eq!(calls.count.load(Ordering::SeqCst), 1);
+
+        // keys are per user
+        let res = test::call_service(&app, post("/count", &other, "a").to_request()).await;
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        assert_eq!(calls.count.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn server_errors_release_the_key_for_a_retry() {
+        let db = test_utils::database().await;
+        let (_, bearer) = test_utils::signed_in(&db).await;
+        let calls = web::Data::new(Calls::default());
+        let app = test::init_service(app(db, calls.clone())).await;
+
+        for (status, replayed) in [
+            (StatusCode::SERVICE_UNAVAILABLE, false),
+            (StatusCode::CREATED, false),
+            (StatusCode::CREATED, true),
+        ] {
+            let res = test::call_service(&app, post("/flaky", &bearer, "b").to_request()).await;
+            assert_eq!(res.status(), status);
+            assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED).is_some(), replayed);
+        }
+        assert_eq!(calls.count.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn duplicates_of_a_running_request_are_refused_however_long_it_takes() {
+        let db = test_utils::database().await;
+        let (user, bearer) = test_utils::signed_in(&db).await;
+        let calls = web::Data::new(Calls::default());
+        let app = test::init_service(app(db.clone(), calls.clone())).await;
+
+        let first = test::call_service(&app, post("/slow", &bearer, "c").to_request());
+        let duplicates = async {
+            calls.started.notified().await;
+            // long past when a claim used to be taken over
+            idempotency_key::ActiveModel {
+                user_id: Set(user.id),
+                key: Set("c".to_string()),
+                created_at: Set(Utc::now() - chrono::Duration::hours(1)),
+                ..Default::default()
+            }
+            .update(&db)
+            .await
+            .unwrap();
+            for _ in 0..2 {
+                let res = test::call_service(&app, post("/slow", &bearer, "c").to_request()).await;
+                assert_eq!(res.status(), StatusCode::CONFLICT);
+                let body: Value = test::read_body_json(res).await;
+                assert_eq!(body["code"], "idempotency_key_in_use");
+            }
+            calls.finish.notify_one();
+        };
+        let (first, _) = futures::join!(first, duplicates);
+        assert_eq!(first.status(), StatusCode::CREATED);
+
+        let res = test::call_service(&app, post("/slow", &bearer, "c").to_request()).await;
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert_eq!(calls.count.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn concurrent_claims_of_a_key_have_one_winner() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, test_utils::PASSWORD).await;
+
+        let claims = futures::future::join_all(
+            (0..8).map(|_| idempotency_key::Model::claim(user.id, "d", "print", &db)),
+        )
+        .await;
+        let claimed = claims
+            .iter()
+            .filter(|claim| matches!(claim, Ok(Claim::Claimed)))
+            .count();
+        assert_eq!(claimed, 1);
+        for claim in claims {
+            match claim.unwrap() {
+                Claim::Claimed => (),
+                Claim::Existing(existing) => assert_eq!(existing.status, None),
+            }
+        }
+
+        // released claims and expired keys can be claimed again
+        idempotency_key::Model::release(user.id, "d", &db)
+            .await
+            .unwrap();
+        let claim = idempotency_key::Model::claim(user.id, "d", "print", &db).await;
+        assert!(matches!(claim, Ok(Claim::Claimed)));
+        idempotency_key::Model::complete(user.id, "d", 201, json!({}), Vec::new(), &db)
+            .await
+            .unwrap();
+        let claim = idempotency_key::Model::claim(user.id, "d", "print", &db).await;
+        assert!(matches!(claim, Ok(Claim::Existing(existing)) if existing.status == Some(201)));
+        idempotency_key::ActiveModel {
+            user_id: Set(user.id),
+            key: Set("d".to_string()),
+            expires_at: Set(Utc::now() - chrono::Duration::seconds(1)),
+            ..Default::default()
+        }
+        .update(&db)
+        .await
+        .unwrap();
+        let claim = idempotency_key::Model::claim(user.id, "d", "print", &db).await;
+        assert!(matches!(claim, Ok(Claim::Claimed)));
+    }
+}
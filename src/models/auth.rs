@@ -1,32 +1,71 @@
-use actix_web::error;
-use actix_web::Error;
+use std::marker::PhantomData;
+
 use actix_web::{FromRequest, HttpMessage};
 use entity::session;
+use entity::user::Role;
 use futures::future::{ready, Ready};
 
-use crate::constants;
-
+use crate::models::error::ApiError;
 
-pub struct Authenticated(entity::session::Model);
+pub struct Authenticated(entity::session::Claims);
 
 impl FromRequest for Authenticated {
-    type Error = Error;
+    type Error = ApiError;
     type Future = Ready<Result<Self, Self::Error>>;
 
     fn from_request(
         req: &actix_web::HttpRequest,
         _payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        let value = req.extensions().get::<session::Model>().cloned();
+        let value = req.extensions().get::<session::Claims>().cloned();
         let result = match value {
             Some(v) => Ok(Authenticated(v)),
-            None => Err(error::ErrorUnauthorized(constants::MESSAGE_INVALID_TOKEN)),
+            None => Err(ApiError::InvalidToken),
         };
         ready(result)
     }
 }
 impl std::ops::Deref for Authenticated {
-    type Target = session::Model;
+    type Target = session::Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// a role an endpoint can require, named by a marker type so it can be checked in the extractor
+pub trait RoleMarker {
+    const ROLE: Role;
+}
+
+pub struct Admin;
+
+impl RoleMarker for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+// an authenticated request whose token carries the role R
+pub struct RequireRole<R: RoleMarker>(entity::session::Claims, PhantomData<R>);
+
+impl<R: RoleMarker> FromRequest for RequireRole<R> {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        _payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let value = req.extensions().get::<session::Claims>().cloned();
+        let result = match value {
+            Some(v) if v.role == R::ROLE => Ok(RequireRole(v, PhantomData)),
+            Some(_) => Err(ApiError::Forbidden),
+            None => Err(ApiError::InvalidToken),
+        };
+        ready(result)
+    }
+}
+impl<R: RoleMarker> std::ops::Deref for RequireRole<R> {
+    type Target = session::Claims;
 
     fn deref(&self) -> &Self::Target {
         &self.0
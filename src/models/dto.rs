@@ -0,0 +1,223 @@
+use chrono::{DateTime, Utc};
+use entity::accounting_entry::{self, EntryKind};
+use entity::user::Role;
+use entity::{external_identity, product, schedule, user};
+use sea_orm::prelude::Uuid;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::utils::cron_utils::ScheduleDefinition;
+
+// what the api returns for each entity. these are kept apart from the sea-orm models so a
+// column can change without changing the api, the conversions are the only place both meet
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub totp_enabled: bool,
+    pub role: Role,
+    pub disabled: bool,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    // without one, actions are confirmed by logging in at the identity provider again
+    pub has_password: bool,
+}
+
+impl From<&user::Model> for User {
+    fn from(model: &user::Model) -> Self {
+        User {
+            id: model.id,
+            username: model.username.clone(),
+            created: model.created,
+            updated: model.updated,
+            totp_enabled: model.totp_enabled,
+            role: model.role,
+            disabled: model.disabled,
+            email_verified_at: model.email_verified_at,
+            has_password: model.has_password,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Schedule {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub drug_name: String,
+    pub pill_count: i32,
+    pub pill_amount: i32,
+    pub cron: String,
+    pub definition: Option<ScheduleDefinition>,
+    pub product: Option<ProductRef>,
+    pub added_at: DateTime<Utc>,
+    pub updated_by: Option<Uuid>,
+    // the ETag of the schedule
+    pub version: i32,
+}
+
+impl From<&schedule::Model> for Schedule {
+    fn from(model: &schedule::Model) -> Self {
+        Schedule {
+            id: model.id,
+            user_id: model.user_id,
+            drug_name: model.drug_name.clone(),
+            pill_count: model.pill_count,
+            pill_amount: model.pill_amount,
+            cron: model.cron.clone(),
+            definition: ScheduleDefinition::of(model),
+            product: match (&model.product_appl_no, &model.product_no) {
+                (Some(appl_no), Some(product_no)) => Some(ProductRef {
+                    appl_no: appl_no.clone(),
+                    product_no: product_no.clone(),
+                }),
+                _ => None,
+            },
+            added_at: model.added_at,
+            updated_by: model.updated_by,
+            version: model.version,
+        }
+    }
+}
+
+// an FDA product by its key, as the drug endpoints return it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProductRef {
+    pub appl_no: String,
+    pub product_no: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountingEntry {
+    pub id: Uuid,
+    pub schedule_id: Uuid,
+    pub amount: i32,
+    pub kind: EntryKind,
+    pub timestamp: DateTime<Utc>,
+    pub actor_id: Option<Uuid>,
+}
+
+impl From<&accounting_entry::Model> for AccountingEntry {
+    fn from(model: &accounting_entry::Model) -> Self {
+        AccountingEntry {
+            id: model.id,
+            schedule_id: model.schedule_id,
+            amount: model.amount,
+            kind: model.kind,
+            timestamp: model.timestamp,
+            actor_id: model.actor_id,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+pub enum EventType {
+    #[serde(rename = "schedule.created")]
+    ScheduleCreated,
+    #[serde(rename = "schedule.updated")]
+    ScheduleUpdated,
+    #[serde(rename = "schedule.deleted")]
+    ScheduleDeleted,
+    // a dose was taken or missed, the entry's kind tells which
+    #[serde(rename = "dose.logged")]
+    DoseLogged,
+    // any other accounting entry, the initial count or a correction
+    #[serde(rename = "accounting_entry.created")]
+    AccountingEntryCreated,
+}
+
+// a change to a schedule, pushed to everyone who can see it. small enough for a postgres
+// notification, clients fetch the schedule for anything else
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub schedule_id: Uuid,
+    pub owner_id: Uuid,
+    // the schedule's version and pill count after the change, unset once it's deleted
+    pub version: Option<i32>,
+    pub pill_count: Option<i32>,
+    // the accounting entry that was written, for dose and accounting entry events
+    pub entry: Option<AccountingEntry>,
+}
+
+impl Event {
+    pub fn schedule(event_type: EventType, schedule: &schedule::Model) -> Self {
+        let deleted = event_type == EventType::ScheduleDeleted;
+        Event {
+            event_type,
+            schedule_id: schedule.id,
+            owner_id: schedule.user_id,
+            version: (!deleted).then_some(schedule.version),
+            pill_count: (!deleted).then_some(schedule.pill_count),
+            entry: None,
+        }
+    }
+
+    pub fn entry(schedule: &schedule::Model, entry: &accounting_entry::Model) -> Self {
+        let event_type = match entry.kind {
+            EntryKind::Dose | EntryKind::Missed => EventType::DoseLogged,
+            EntryKind::Initial | EntryKind::Correction => EventType::AccountingEntryCreated,
+        };
+        Event {
+            entry: Some(AccountingEntry::from(entry)),
+            ..Event::schedule(event_type, schedule)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Identity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&external_identity::Model> for Identity {
+    fn from(model: &external_identity::Model) -> Self {
+        Identity {
+            id: model.id,
+            user_id: model.user_id,
+            issuer: model.issuer.clone(),
+            subject: model.subject.clone(),
+            email: model.email.clone(),
+            created_at: model.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Product {
+    pub product_no: String,
+    pub appl_no: String,
+    pub form: Option<String>,
+    pub strength: Option<String>,
+    pub reference_drug: Option<i32>,
+    pub drug_name: Option<String>,
+    pub active_ingredient: Option<String>,
+    pub reference_standard: Option<i32>,
+}
+
+impl From<&product::Model> for Product {
+    fn from(model: &product::Model) -> Self {
+        Product {
+            product_no: model.product_no.clone(),
+            appl_no: model.appl_no.clone(),
+            form: model.form.clone(),
+            strength: model.strength.clone(),
+            reference_drug: model.reference_drug,
+            drug_name: model.drug_name.clone(),
+            active_ingredient: model.active_ingredient.clone(),
+            reference_standard: model.reference_standard,
+        }
+    }
+}
+
+// converts a list of models
+pub fn list<'a, M: 'a, T: From<&'a M>>(models: &'a [M]) -> Vec<T> {
+    models.iter().map(T::from).collect()
+}
@@ -0,0 +1,338 @@
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use log::error;
+use sea_orm::DbErr;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::middleware::request_id;
+use crate::models::response::FieldError;
+
+// every error a handler can return. the code of each variant is part of the api, clients
+// match on it, so codes are never renamed
+#[derive(Debug)]
+pub enum ApiError {
+    // the request is malformed, with one entry per invalid field
+    Validation(Vec<FieldError>),
+    InvalidCredentials,
+    InvalidToken,
+    SsoFailed,
+    AccountDisabled,
+    Forbidden,
+    LinkExpired,
+    NotFound,
+    UsernameTaken,
+    IdentityLinked,
+    // the caregiver already has a grant for the same schedules
+    GrantExists,
+    // unlinking would leave an account without a password no way to log in
+    LastLoginMethod,
+    EmailVerified,
+    TotpEnabled,
+    TotpNotEnabled,
+    TotpNotEnrolled,
+    // the resource changed since the version the client sent in If-Match
+    PreconditionFailed,
+    // a change was sent without If-Match, so it could overwrite a newer one
+    PreconditionRequired,
+    // an Idempotency-Key was sent again with a different request
+    IdempotencyKeyReused,
+    // the first request with an Idempotency-Key is still running
+    IdempotencyKeyInUse,
+    TooManyAttempts(Duration),
+    Upstream,
+    Busy,
+    // logged with the request id, the client only sees a generic message
+    Internal(anyhow::Error),
+}
+
+// the body of every error response
+#[derive(Serialize, ToSchema)]
+#[schema(as = Error)]
+pub struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "<[FieldError]>::is_empty")]
+    #[schema(value_type = Vec<FieldError>)]
+    details: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    // a validation error for a single field
+    pub fn invalid(field: &str, code: &str, message: impl Into<String>) -> ApiError {
+        ApiError::Validation(vec![FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        }])
+    }
+
+    pub fn internal(err: impl Into<anyhow::Error>) -> ApiError {
+        ApiError::Internal(err.into())
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::InvalidToken => "invalid_token",
+            ApiError::SsoFailed => "sso_failed",
+            ApiError::AccountDisabled => "account_disabled",
+            ApiError::Forbidden => "forbidden",
+            ApiError::LinkExpired => "link_expired",
+            ApiError::NotFound => "not_found",
+            ApiError::UsernameTaken => "username_taken",
+            ApiError::IdentityLinked => "identity_linked",
+            ApiError::GrantExists => "grant_exists",
+            ApiError::LastLoginMethod => "last_login_method",
+            ApiError::EmailVerified => "email_verified",
+            ApiError::TotpEnabled => "totp_enabled",
+            ApiError::TotpNotEnabled => "totp_not_enabled",
+            ApiError::TotpNotEnrolled => "totp_not_enrolled",
+            ApiError::PreconditionFailed => "precondition_failed",
+            ApiError::PreconditionRequired => "precondition_required",
+            ApiError::IdempotencyKeyReused => "idempotency_key_reused",
+            ApiError::IdempotencyKeyInUse => "idempotency_key_in_use",
+            ApiError::TooManyAttempts(_) => "too_many_attempts",
+            ApiError::Upstream => "upstream_unavailable",
+            ApiError::Busy => "server_busy",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "The request is invalid",
+            ApiError::InvalidCredentials => "Username, password or code is incorrect",
+            ApiError::InvalidToken => crate::constants::MESSAGE_INVALID_TOKEN,
+            ApiError::SsoFailed => "Single sign-on failed",
+            ApiError::AccountDisabled => "Account is disabled",
+            ApiError::Forbidden => "You don't have access to this resource",
+            ApiError::LinkExpired => "Link is invalid or expired",
+            ApiError::NotFound => "Not found",
+            ApiError::UsernameTaken => "An account with this username already exists",
+            ApiError::IdentityLinked => "This identity is already linked to another account",
+            ApiError::GrantExists => "This caregiver already has a grant for these schedules",
+            ApiError::LastLoginMethod => "Set a password or link another identity first",
+            ApiError::EmailVerified => "Email address is already verified",
+            ApiError::TotpEnabled => "Two factor authentication is already enabled",
+            ApiError::TotpNotEnabled => "Two factor authentication is not enabled",
+            ApiError::TotpNotEnrolled => "Two factor enrolment has not started",
+            ApiError::PreconditionFailed => "It was changed since you last read it",
+            ApiError::PreconditionRequired => "Send the ETag you last read in If-Match",
+            ApiError::IdempotencyKeyReused => "This idempotency key was used for another request",
+            ApiError::IdempotencyKeyInUse => "A request with this idempotency key is still running",
+            ApiError::TooManyAttempts(_) => "Too many failed attempts, try again later",
+            ApiError::Upstream => "Identity provider is unavailable",
+            ApiError::Busy => "Server is busy, try again later",
+            ApiError::Internal(_) => "Something went wrong",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidToken | ApiError::SsoFailed => StatusCode::UNAUTHORIZED,
+            // wrong passwords are also used to confirm actions of a logged in user, a 401
+            // there would make clients throw away a valid token
+            ApiError::InvalidCredentials
+            | ApiError::AccountDisabled
+            | ApiError::Forbidden
+            | ApiError::LinkExpired => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::UsernameTaken
+            | ApiError::IdentityLinked
+            | ApiError::GrantExists
+            | ApiError::LastLoginMethod
+            | ApiError::EmailVerified
+            | ApiError::TotpEnabled
+            | ApiError::TotpNotEnabled
+            | ApiError::TotpNotEnrolled
+            | ApiError::IdempotencyKeyInUse => StatusCode::CONFLICT,
+            ApiError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream => StatusCode::BAD_GATEWAY,
+            ApiError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let request_id = request_id::current();
+        if let ApiError::Internal(err) = self {
+            error!(
+                "Request {} failed: {:?}",
+                request_id.as_deref().unwrap_or("-"),
+                err
+            );
+        }
+        let details = match self {
+            ApiError::Validation(errors) => errors.as_slice(),
+            _ => &[],
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::TooManyAttempts(retry_after) = self {
+            // round up so clients never retry a moment too early
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            details,
+            request_id,
+        })
+    }
+}
+
+impl From<DbErr> for ApiError {
+    fn from(err: DbErr) -> ApiError {
+        ApiError::Internal(err.into())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> ApiError {
+        ApiError::Internal(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::{test, web, App, HttpResponse};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::middleware::request_id::{RequestIdMiddlewareFactory, REQUEST_ID};
+
+    // one variant of each status, with its code and message
+    fn error(index: usize) -> ApiError {
+        match index {
+            0 => ApiError::invalid("drug_name", "required", "A drug name is required"),
+            1 => ApiError::InvalidToken,
+            2 => ApiError::InvalidCredentials,
+            3 => ApiError::NotFound,
+            4 => ApiError::UsernameTaken,
+            5 => ApiError::PreconditionFailed,
+            6 => ApiError::IdempotencyKeyReused,
+            7 => ApiError::PreconditionRequired,
+            8 => ApiError::TooManyAttempts(Duration::from_millis(1500)),
+            9 => ApiError::Upstream,
+            10 => ApiError::Busy,
+            _ => ApiError::internal(anyhow::anyhow!("connection refused by 10.0.0.3")),
+        }
+    }
+
+    const EXPECTED: [(StatusCode, &str); 12] = [
+        (StatusCode::BAD_REQUEST, "validation_failed"),
+        (StatusCode::UNAUTHORIZED, "invalid_token"),
+        (StatusCode::FORBIDDEN, "invalid_credentials"),
+        (StatusCode::NOT_FOUND, "not_found"),
+        (StatusCode::CONFLICT, "username_taken"),
+        (StatusCode::PRECONDITION_FAILED, "precondition_failed"),
+        (StatusCode::UNPROCESSABLE_ENTITY, "idempotency_key_reused"),
+        (StatusCode::PRECONDITION_REQUIRED, "precondition_required"),
+        (StatusCode::TOO_MANY_REQUESTS, "too_many_attempts"),
+        (StatusCode::BAD_GATEWAY, "upstream_unavailable"),
+        (StatusCode::SERVICE_UNAVAILABLE, "server_busy"),
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+    ];
+
+    async fn failing(index: web::Path<usize>) -> Result<HttpResponse, ApiError> {
+        Err(error(*index))
+    }
+
+    #[actix_web::test]
+    async fn errors_have_their_status_and_the_same_body_shape() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIdMiddlewareFactory {})
+                .route("/{index}", web::get().to(failing)),
+        )
+        .await;
+
+        for (index, (status, code)) in EXPECTED.iter().enumerate() {
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}", index))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), *status, "{}", code);
+            assert_eq!(
+                res.headers().get(RETRY_AFTER).is_some(),
+                *status == StatusCode::TOO_MANY_REQUESTS,
+                "{}",
+                code
+            );
+            if *status == StatusCode::TOO_MANY_REQUESTS {
+                // rounded up to whole seconds
+                assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "2");
+            }
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], *code);
+            assert_eq!(body["message"], error(index).message());
+            assert!(body["request_id"].is_string(), "{}", code);
+            // details are only there for validation errors
+            let keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+            match *status == StatusCode::BAD_REQUEST {
+                true => {
+                    assert_eq!(keys, ["code", "details", "message", "request_id"]);
+                    assert_eq!(body["details"][0]["field"], "drug_name");
+                    assert_eq!(body["details"][0]["code"], "required");
+                }
+                false => assert_eq!(keys, ["code", "message", "request_id"], "{}", code),
+            }
+        }
+
+        // internal errors don't tell clients what went wrong
+        let req = test::TestRequest::get().uri("/11").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(!String::from_utf8_lossy(&body).contains("10.0.0.3"));
+    }
+
+    #[actix_web::test]
+    async fn the_request_id_in_the_body_is_the_one_in_the_header() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIdMiddlewareFactory {})
+                .route("/{index}", web::get().to(failing)),
+        )
+        .await;
+
+        for sent in [None, Some("from-the-proxy-42")] {
+            let mut req = test::TestRequest::get().uri("/3");
+            if let Some(id) = sent {
+                req = req.insert_header((REQUEST_ID, id));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            let header = res
+                .headers()
+                .get(REQUEST_ID)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            if let Some(id) = sent {
+                assert_eq!(header, id);
+            }
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["request_id"], header);
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod response;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod dto;
+pub mod error;
\ No newline at end of file
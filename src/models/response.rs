@@ -1,43 +1,248 @@
-use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 
-#[derive(Debug, Serialize, Deserialize)]
+use actix_web::body::BoxBody;
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderName, HeaderValue, TryIntoHeaderPair};
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder};
+use futures::future::{ready, Ready};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// the version of the api a route is served under, set as app data on each scope. versions
+// share handlers and only differ in how responses are rendered, a v2 gets its own variant
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    // the unversioned /api routes, kept for old clients and marked deprecated
+    Legacy,
+    V1,
+}
+
+impl ApiVersion {
+    pub fn of(req: &HttpRequest) -> ApiVersion {
+        req.app_data::<ApiVersion>()
+            .copied()
+            .unwrap_or(ApiVersion::V1)
+    }
+}
+
+// for handlers whose results differ by version, not just their rendering
+impl FromRequest for ApiVersion {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(ApiVersion::of(req)))
+    }
+}
+
+// every json response of a versioned route
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ResponseBody<T> {
-    pub message: String,
     pub data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+    pub links: Links,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Pagination {
+    // zero based, only when paging by offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_num: Option<u64>,
+    pub page_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_elements: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<u64>,
+    // pass as the cursor to fetch the next page, only when paging by cursor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl Pagination {
+    pub fn offset(page_num: u64, page_size: u64, total_elements: u64) -> Pagination {
+        Pagination {
+            page_num: Some(page_num),
+            page_size,
+            total_elements: Some(total_elements),
+            total_pages: Some(total_elements.div_ceil(page_size.max(1))),
+            next_cursor: None,
+        }
+    }
+
+    pub fn cursor(page_size: u64, next_cursor: Option<String>) -> Pagination {
+        Pagination {
+            page_num: None,
+            page_size,
+            total_elements: None,
+            total_pages: None,
+            next_cursor,
+        }
+    }
 }
 
-impl<T> ResponseBody<T> {
-    pub fn _new(message: &str, data: T) -> ResponseBody<T> {
-        ResponseBody {
-            message: message.to_string(),
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub self_link: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+// what handlers return, rendered as a ResponseBody under /api/v1 and as the bare data on the
+// legacy routes, so their clients don't break
+pub struct Envelope<T> {
+    status: StatusCode,
+    data: T,
+    pagination: Option<Pagination>,
+    // lists paged before v1 had their own shape on the legacy routes
+    legacy_page: bool,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl<T> Envelope<T> {
+    pub fn ok(data: T) -> Envelope<T> {
+        Envelope {
+            status: StatusCode::OK,
             data,
+            pagination: None,
+            legacy_page: false,
+            headers: Vec::new(),
         }
     }
+
+    pub fn created(data: T) -> Envelope<T> {
+        Envelope::ok(data).status(StatusCode::CREATED)
+    }
+
+    pub fn status(mut self, status: StatusCode) -> Envelope<T> {
+        self.status = status;
+        self
+    }
+
+    // headers that can't be built are left out
+    pub fn header(mut self, header: impl TryIntoHeaderPair) -> Envelope<T> {
+        if let Ok(pair) = header.try_into_pair() {
+            self.headers.push(pair);
+        }
+        self
+    }
 }
 
-#[derive(Serialize)]
-pub struct Page<T> {
-    pub message: String,
-    pub data: Vec<T>,
-    pub page_num: i64,
-    pub page_size: i64,
-    pub total_elements: i64,
-}
-
-impl<T> Page<T> {
-    pub fn _new(
-        message: &str,
-        data: Vec<T>,
-        page_num: i64,
-        page_size: i64,
-        total_elements: i64,
-    ) -> Page<T> {
-        Page {
-            message: message.to_string(),
+impl<T> Envelope<Vec<T>> {
+    // one page of a list that was already paged on the legacy routes
+    pub fn page(data: Vec<T>, pagination: Pagination) -> Self {
+        Envelope {
+            status: StatusCode::OK,
             data,
-            page_num,
-            page_size,
-            total_elements,
+            pagination: Some(pagination),
+            legacy_page: true,
+            headers: Vec::new(),
         }
     }
-}
\ No newline at end of file
+
+    // one page of a list, the legacy routes only get the data
+    pub fn list(data: Vec<T>, pagination: Pagination) -> Self {
+        Envelope {
+            status: StatusCode::OK,
+            data,
+            pagination: Some(pagination),
+            legacy_page: false,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl<T: Serialize> Responder for Envelope<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let version = ApiVersion::of(req);
+        let mut response = HttpResponse::build(self.status);
+        for header in self.headers {
+            response.insert_header(header);
+        }
+        match (version, self.pagination) {
+            (ApiVersion::Legacy, Some(pagination)) if self.legacy_page => response.json(Page {
+                message: "",
+                data: self.data,
+                page_num: pagination.page_num.unwrap_or(0),
+                page_size: pagination.page_size,
+                total_elements: pagination.total_elements.unwrap_or(0),
+            }),
+            (ApiVersion::Legacy, _) => response.json(self.data),
+            (ApiVersion::V1, pagination) => {
+                let links = links(req, pagination.as_ref());
+                response.json(ResponseBody {
+                    data: self.data,
+                    pagination,
+                    links,
+                })
+            }
+        }
+    }
+}
+
+// the shape paginated lists had on the legacy routes
+#[derive(Serialize)]
+struct Page<T> {
+    message: &'static str,
+    data: T,
+    page_num: u64,
+    page_size: u64,
+    total_elements: u64,
+}
+
+fn links(req: &HttpRequest, pagination: Option<&Pagination>) -> Links {
+    let self_link = req.uri().to_string();
+    let pagination = match pagination {
+        Some(pagination) => pagination,
+        None => {
+            return Links {
+                self_link,
+                next: None,
+                prev: None,
+            }
+        }
+    };
+    match (pagination.page_num, pagination.total_pages) {
+        (Some(page), Some(total_pages)) => Links {
+            self_link,
+            next: (page + 1 < total_pages)
+                .then(|| page_link(req, "page_num", &(page + 1).to_string())),
+            prev: (page > 0).then(|| page_link(req, "page_num", &(page - 1).to_string())),
+        },
+        // cursors only lead forward
+        _ => Links {
+            self_link,
+            next: pagination
+                .next_cursor
+                .as_ref()
+                .map(|cursor| page_link(req, "cursor", cursor)),
+            prev: None,
+        },
+    }
+}
+
+// the request's url paging to another page, keeping every other query parameter
+fn page_link(req: &HttpRequest, key: &str, value: &str) -> String {
+    let mut query: Vec<(String, String)> =
+        serde_urlencoded::from_str(req.query_string()).unwrap_or_default();
+    query.retain(|(k, _)| k != "page_num" && k != "cursor");
+    query.push((key.to_string(), value.to_string()));
+    format!(
+        "{}?{}",
+        req.path(),
+        serde_urlencoded::to_string(query).unwrap_or_default()
+    )
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
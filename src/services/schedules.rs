@@ -0,0 +1,485 @@
+use actix_web::http::header::{EntityTag, IfMatch};
+use entity::accounting_entry::{self, EntryKind};
+use entity::caregiver_grant::{self, Permission};
+use entity::{product, schedule};
+use sea_orm::prelude::{Json, Uuid};
+use sea_orm::{
+    ActiveModelBehavior, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait,
+    DatabaseTransaction, EntityTrait, QueryFilter, QuerySelect, Set, TransactionTrait,
+};
+
+use crate::models::dto::{Event, EventType, ProductRef};
+use crate::models::error::ApiError;
+use crate::utils::events;
+
+// schedule changes go through here. each one runs in a single transaction, so a schedule's
+// pill_count always equals the sum of its accounting entries, and every change records who
+// made it and bumps the version. given a transaction they run in a savepoint of it, so
+// several changes can be applied together. everyone who can see the schedule is told about
+// each change once it commits
+
+pub struct NewSchedule {
+    pub owner_id: Uuid,
+    pub drug_name: String,
+    pub cron: String,
+    pub definition: Option<Json>,
+    pub product: Option<ProductRef>,
+    pub pill_count: i32,
+    pub pill_amount: i32,
+}
+
+pub struct ScheduleChanges {
+    // a raw cron expression comes without a definition, which clears the stored one
+    pub cron: Option<(String, Option<Json>)>,
+    pub product: Option<ProductRef>,
+    pub pill_count: Option<i32>,
+    pub pill_amount: Option<i32>,
+}
+
+// a dose the patient took or skipped
+pub enum Dose {
+    // the pills taken, the schedule's pill_amount when not given
+    Taken(Option<i32>),
+    Missed,
+}
+
+// loads a schedule the user owns or was granted, schedules they can't see at all are not found
+pub async fn find<C: ConnectionTrait>(
+    db: &C,
+    id: Uuid,
+    user_id: Uuid,
+    required: Permission,
+) -> Result<schedule::Model, ApiError> {
+    let schedule = schedule::Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    check_permission(db, schedule, user_id, required).await
+}
+
+pub fn etag(schedule: &schedule::Model) -> EntityTag {
+    EntityTag::new_strong(schedule.version.to_string())
+}
+
+pub async fn create<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: Uuid,
+    new: NewSchedule,
+) -> Result<schedule::Model, ApiError> {
+    match caregiver_grant::Model::permission_for_all(actor_id, new.owner_id, db).await? {
+        Some(Permission::Manage) => (),
+        _ => return Err(ApiError::Forbidden),
+    }
+    if let Some(product) = &new.product {
+        check_product(db, product).await?;
+    }
+
+    let mut schedule = schedule::ActiveModel::new();
+    schedule.user_id = Set(new.owner_id);
+    schedule.updated_by = Set(Some(actor_id));
+    schedule.drug_name = Set(new.drug_name);
+    schedule.cron = Set(new.cron);
+    schedule.definition = Set(new.definition);
+    if let Some(product) = new.product {
+        schedule.product_appl_no = Set(Some(product.appl_no));
+        schedule.product_no = Set(Some(product.product_no));
+    }
+    schedule.pill_count = Set(new.pill_count);
+    schedule.pill_amount = Set(new.pill_amount);
+
+    let txn = db.begin().await?;
+    let schedule = schedule.insert(&txn).await?;
+    let entry = log_accounting_entry(
+        &txn,
+        EntryKind::Initial,
+        0,
+        schedule.pill_count,
+        schedule.id,
+        actor_id,
+    )
+    .await?;
+    let changes = [
+        Event::schedule(EventType::ScheduleCreated, &schedule),
+        Event::entry(&schedule, &entry),
+    ];
+    announce(&txn, &schedule, &changes).await?;
+    txn.commit().await?;
+    Ok(schedule)
+}
+
+// with if_match only the version the client last read is changed
+pub async fn update<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: Uuid,
+    id: Uuid,
+    if_match: Option<IfMatch>,
+    changes: ScheduleChanges,
+) -> Result<schedule::Model, ApiError> {
+    let txn = db.begin().await?;
+    let schedule = lock(&txn, id, actor_id, if_match).await?;
+    let old_count = schedule.pill_count;
+    let version = schedule.version;
+
+    let mut active_model: schedule::ActiveModel = schedule.into();
+    active_model.updated_by = Set(Some(actor_id));
+    active_model.version = Set(version + 1);
+    if let Some((cron, definition)) = changes.cron {
+        active_model.cron = Set(cron);
+        active_model.definition = Set(definition);
+    }
+    if let Some(product) = changes.product {
+        check_product(&txn, &product).await?;
+        active_model.product_appl_no = Set(Some(product.appl_no));
+        active_model.product_no = Set(Some(product.product_no));
+    }
+    if let Some(pill_amount) = changes.pill_amount {
+        active_model.pill_amount = Set(pill_amount);
+    }
+    let mut entry = None;
+    if let Some(pill_count) = changes.pill_count {
+        entry = Some(
+            log_accounting_entry(
+                &txn,
+                EntryKind::Correction,
+                old_count,
+                pill_count,
+                id,
+                actor_id,
+            )
+            .await?,
+        );
+        active_model.pill_count = Set(pill_count);
+    }
+
+    let schedule = active_model.update(&txn).await?;
+    let mut changes = vec![Event::schedule(EventType::ScheduleUpdated, &schedule)];
+    changes.extend(entry.map(|entry| Event::entry(&schedule, &entry)));
+    announce(&txn, &schedule, &changes).await?;
+    txn.commit().await?;
+    Ok(schedule)
+}
+
+// taken doses come off the pill count, missed ones only leave an entry
+pub async fn log_dose<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: Uuid,
+    id: Uuid,
+    dose: Dose,
+) -> Result<(schedule::Model, accounting_entry::Model), ApiError> {
+    let txn = db.begin().await?;
+    let schedule = lock(&txn, id, actor_id, None).await?;
+    let (schedule, entry) = match dose {
+        Dose::Taken(pills) => {
+            let pills = pills.unwrap_or(schedule.pill_amount);
+            if pills <= 0 {
+                return Err(ApiError::invalid(
+                    "pills",
+                    "too_small",
+                    "A dose is at least one pill, give pills when the schedule has no pill_amount",
+                ));
+            }
+            let old_count = schedule.pill_count;
+            let version = schedule.version;
+            let entry = log_accounting_entry(
+                &txn,
+                EntryKind::Dose,
+                old_count,
+                old_count - pills,
+                id,
+                actor_id,
+            )
+            .await?;
+            let mut active_model: schedule::ActiveModel = schedule.into();
+            active_model.updated_by = Set(Some(actor_id));
+            active_model.version = Set(version + 1);
+            active_model.pill_count = Set(old_count - pills);
+            (active_model.update(&txn).await?, entry)
+        }
+        Dose::Missed => {
+            let entry = log_accounting_entry(&txn, EntryKind::Missed, 0, 0, id, actor_id).await?;
+            (schedule, entry)
+        }
+    };
+    announce(&txn, &schedule, &[Event::entry(&schedule, &entry)]).await?;
+    txn.commit().await?;
+    Ok((schedule, entry))
+}
+
+pub async fn delete<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: Uuid,
+    id: Uuid,
+    if_match: Option<IfMatch>,
+) -> Result<(), ApiError> {
+    let txn = db.begin().await?;
+    let schedule = lock(&txn, id, actor_id, if_match).await?;
+    // while the grants of the schedule still exist
+    let changes = [Event::schedule(EventType::ScheduleDeleted, &schedule)];
+    announce(&txn, &schedule, &changes).await?;
+    let active_model: schedule::ActiveModel = schedule.into();
+    active_model.delete(&txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+// loads a schedule the user can manage, locked until the transaction ends so concurrent
+// changes are made one after another and the pill count delta can't be stale
+async fn lock(
+    txn: &DatabaseTransaction,
+    id: Uuid,
+    user_id: Uuid,
+    if_match: Option<IfMatch>,
+) -> Result<schedule::Model, ApiError> {
+    let schedule = schedule::Entity::find()
+        .filter(schedule::Column::Id.eq(id))
+        .lock_exclusive()
+        .one(txn)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let schedule = check_permission(txn, schedule, user_id, Permission::Manage).await?;
+
+    let etag = etag(&schedule);
+    match if_match {
+        // a request without If-Match parses as an empty list of tags, which isn't a precondition
+        Some(IfMatch::Items(tags))
+            if !tags.is_empty() && !tags.iter().any(|tag| tag.strong_eq(&etag)) =>
+        {
+            Err(ApiError::PreconditionFailed)
+        }
+        _ => Ok(schedule),
+    }
+}
+
+async fn check_permission<C: ConnectionTrait>(
+    db: &C,
+    schedule: schedule::Model,
+    user_id: Uuid,
+    required: Permission,
+) -> Result<schedule::Model, ApiError> {
+    match caregiver_grant::Model::permission_for(user_id, &schedule, db).await? {
+        Some(permission) if permission >= required => Ok(schedule),
+        Some(_) => Err(ApiError::Forbidden),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+async fn check_product<C: ConnectionTrait>(db: &C, product: &ProductRef) -> Result<(), ApiError> {
+    check_products(db, &[product]).await?.remove(0)
+}
+
+// checks several references with a single query, with a result for each in order
+pub async fn check_products<C: ConnectionTrait>(
+    db: &C,
+    products: &[&ProductRef],
+) -> Result<Vec<Result<(), ApiError>>, ApiError> {
+    if products.is_empty() {
+        return Ok(Vec::new());
+    }
+    let condition = products.iter().fold(Condition::any(), |any, product| {
+        any.add(
+            Condition::all()
+                .add(product::Column::ApplNo.eq(product.appl_no.as_str()))
+                .add(product::Column::ProductNo.eq(product.product_no.as_str())),
+        )
+    });
+    let found = product::Entity::find().filter(condition).all(db).await?;
+    Ok(products
+        .iter()
+        .map(|product| {
+            match found
+                .iter()
+                .any(|f| f.appl_no == product.appl_no && f.product_no == product.product_no)
+            {
+                true => Ok(()),
+                false => Err(ApiError::invalid(
+                    "product",
+                    "unknown_product",
+                    format!(
+                        "No FDA product {} of application {}",
+                        product.product_no, product.appl_no
+                    ),
+                )),
+            }
+        })
+        .collect())
+}
+
+async fn log_accounting_entry(
+    txn: &DatabaseTransaction,
+    kind: EntryKind,
+    old: i32,
+    new: i32,
+    schedule_id: Uuid,
+    actor_id: Uuid,
+) -> Result<accounting_entry::Model, ApiError> {
+    let mut entry = accounting_entry::ActiveModel::new();
+    entry.amount = Set(new - old);
+    entry.kind = Set(kind);
+    entry.schedule_id = Set(schedule_id);
+    entry.actor_id = Set(Some(actor_id));
+
+    Ok(entry.insert(txn).await?)
+}
+
+async fn announce(
+    txn: &DatabaseTransaction,
+    schedule: &schedule::Model,
+    changes: &[Event],
+) -> Result<(), ApiError> {
+    let audience = caregiver_grant::Model::audience(schedule, txn).await?;
+    for event in changes {
+        events::publish(txn, &audience, event).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{DatabaseConnection, DbBackend, Statement};
+
+    use super::*;
+    use crate::test_utils;
+
+    fn new_schedule(owner_id: Uuid, pill_count: i32) -> NewSchedule {
+        NewSchedule {
+            owner_id,
+            drug_name: "Ibuprofen".to_string(),
+            cron: "0 0 8 * * *".to_string(),
+            definition: None,
+            product: None,
+            pill_count,
+            pill_amount: 2,
+        }
+    }
+
+    fn recount(pill_count: i32) -> ScheduleChanges {
+        ScheduleChanges {
+            cron: None,
+            product: None,
+            pill_count: Some(pill_count),
+            pill_amount: None,
+        }
+    }
+
+    async fn ledger<C: ConnectionTrait>(db: &C, schedule_id: Uuid) -> Vec<accounting_entry::Model> {
+        accounting_entry::Entity::find()
+            .filter(accounting_entry::Column::ScheduleId.eq(schedule_id))
+            .all(db)
+            .await
+            .unwrap()
+    }
+
+    async fn assert_balanced(db: &DatabaseConnection, schedule_id: Uuid) {
+        let schedule = schedule::Entity::find_by_id(schedule_id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+        let sum: i32 = ledger(db, schedule_id).await.iter().map(|e| e.amount).sum();
+        assert_eq!(schedule.pill_count, sum);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn pill_count_is_the_sum_of_the_ledger() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, test_utils::PASSWORD).await;
+
+        let schedule = create(&db, user.id, new_schedule(user.id, 30))
+            .await
+            .unwrap();
+        assert_balanced(&db, schedule.id).await;
+        update(&db, user.id, schedule.id, None, recount(20))
+            .await
+            .unwrap();
+        assert_balanced(&db, schedule.id).await;
+        log_dose(&db, user.id, schedule.id, Dose::Taken(None))
+            .await
+            .unwrap();
+        log_dose(&db, user.id, schedule.id, Dose::Missed)
+            .await
+            .unwrap();
+        assert_balanced(&db, schedule.id).await;
+
+        // several changes in one transaction, as a batch applies them
+        let txn = db.begin().await.unwrap();
+        let other = create(&txn, user.id, new_schedule(user.id, 10))
+            .await
+            .unwrap();
+        update(&txn, user.id, other.id, None, recount(12))
+            .await
+            .unwrap();
+        update(&txn, user.id, schedule.id, None, recount(5))
+            .await
+            .unwrap();
+        log_dose(&txn, user.id, other.id, Dose::Taken(Some(3)))
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+        assert_balanced(&db, schedule.id).await;
+        assert_balanced(&db, other.id).await;
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failing_step_rolls_back_the_schedule_and_the_ledger() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, test_utils::PASSWORD).await;
+        let schedule = create(&db, user.id, new_schedule(user.id, 30))
+            .await
+            .unwrap();
+
+        // the schedule update fails after the entry was written. the trigger only lives in
+        // the transaction and is gone with its rollback
+        let txn = db.begin().await.unwrap();
+        for sql in [
+            "CREATE FUNCTION fail_schedule_update() RETURNS trigger AS $$ \
+             BEGIN RAISE EXCEPTION 'failing on purpose'; END $$ LANGUAGE plpgsql"
+                .to_string(),
+            format!(
+                "CREATE TRIGGER fail_schedule_update BEFORE UPDATE ON schedule FOR EACH ROW \
+                 WHEN (NEW.id = '{}') EXECUTE FUNCTION fail_schedule_update()",
+                schedule.id
+            ),
+        ] {
+            txn.execute(Statement::from_string(DbBackend::Postgres, sql))
+                .await
+                .unwrap();
+        }
+        assert!(update(&txn, user.id, schedule.id, None, recount(20))
+            .await
+            .is_err());
+        assert_eq!(ledger(&txn, schedule.id).await.len(), 1);
+        let unchanged = schedule::Entity::find_by_id(schedule.id)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(unchanged.pill_count, 30);
+        txn.rollback().await.unwrap();
+        assert_balanced(&db, schedule.id).await;
+
+        // a later change failing undoes the earlier ones of the same transaction
+        let txn = db.begin().await.unwrap();
+        let created = create(&txn, user.id, new_schedule(user.id, 10))
+            .await
+            .unwrap();
+        update(&txn, user.id, schedule.id, None, recount(25))
+            .await
+            .unwrap();
+        let stale = IfMatch::Items(vec![EntityTag::new_strong("0".to_string())]);
+        let err = update(&txn, user.id, schedule.id, Some(stale), recount(1)).await;
+        assert!(matches!(err, Err(ApiError::PreconditionFailed)));
+        txn.rollback().await.unwrap();
+
+        let gone = schedule::Entity::find_by_id(created.id)
+            .one(&db)
+            .await
+            .unwrap();
+        assert!(gone.is_none());
+        assert!(ledger(&db, created.id).await.is_empty());
+        assert_eq!(ledger(&db, schedule.id).await.len(), 1);
+        assert_balanced(&db, schedule.id).await;
+    }
+}
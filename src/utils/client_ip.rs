@@ -0,0 +1,148 @@
+use std::net::IpAddr;
+
+use actix_web::http::header::HeaderName;
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+use lazy_static::lazy_static;
+use log::info;
+
+// comma separated addresses or ranges of the proxies in front of the server, like
+// "10.0.0.0/8, 192.0.2.7". X-Forwarded-For is only believed from these, without any
+// the address the connection came from is the client's
+const TRUSTED_PROXIES: &str = "TRUSTED_PROXIES";
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+lazy_static! {
+    pub static ref PROXIES: TrustedProxies = TrustedProxies::from_env().unwrap();
+}
+
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn from_env() -> anyhow::Result<TrustedProxies> {
+        let proxies = TrustedProxies::parse(&std::env::var(TRUSTED_PROXIES).unwrap_or_default())?;
+        if !proxies.networks.is_empty() {
+            info!("Trusting X-Forwarded-For from {:?}", proxies.networks);
+        }
+        Ok(proxies)
+    }
+
+    pub fn parse(list: &str) -> anyhow::Result<TrustedProxies> {
+        let networks = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| {
+                        anyhow::anyhow!("{} holds an invalid proxy {}", TRUSTED_PROXIES, entry)
+                    })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(TrustedProxies { networks })
+    }
+
+    fn trusts(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    // walks the forwarded addresses back from the peer, the first one not added by a trusted
+    // proxy is the client. anything further left could have been made up by the client
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: &[&str]) -> IpAddr {
+        let mut client = peer.to_canonical();
+        let hops = forwarded_for
+            .iter()
+            .flat_map(|header| header.split(','))
+            .rev()
+            .map(|hop| hop.trim().parse::<IpAddr>());
+        for hop in hops {
+            if !self.trusts(&client) {
+                break;
+            }
+            match hop {
+                Ok(hop) => client = hop.to_canonical(),
+                // a proxy we trust wrote something we can't read, it is the last address known
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+// the address sessions record and logins are throttled by
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    Some(PROXIES.client_ip(peer, &forwarded_for).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_without_trusted_proxies() {
+        let proxies = TrustedProxies::parse("").unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("203.0.113.9"), &["198.51.100.1"]),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("203.0.113.9"), &["198.51.100.1"]),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn client_is_the_first_address_not_added_by_a_trusted_proxy() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 192.0.2.7").unwrap();
+        // the client claims 1.1.1.1, the trusted proxies appended what they saw
+        let forwarded_for = ["1.1.1.1, 198.51.100.1", "192.0.2.7"];
+        assert_eq!(
+            proxies.client_ip(ip("10.1.2.3"), &forwarded_for),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn ipv4_peers_on_an_ipv6_socket_are_matched_as_ipv4() {
+        let proxies = TrustedProxies::parse("10.0.0.1").unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("::ffff:10.0.0.1"), &["198.51.100.1"]),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn unreadable_hops_stop_the_walk() {
+        let proxies = TrustedProxies::parse("10.0.0.1").unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), &["198.51.100.1, garbage"]),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn invalid_proxies_are_rejected() {
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+        assert!(TrustedProxies::parse("proxy.local").is_err());
+    }
+}
@@ -0,0 +1,500 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use chrono::NaiveDate;
+use entity::schedule;
+use serde::{Deserialize, Serialize};
+use utoipa::openapi::{ObjectBuilder, RefOr, Schema, Type};
+use utoipa::{PartialSchema, ToSchema};
+
+use crate::utils::validate_cron_expression;
+
+// time of day in 24 hour "HH:MM" notation
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    pub hour: u32,
+    pub minute: u32,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("\"{}\" is not a valid time, expected HH:MM", value);
+        let (hour, minute) = value.split_once(':').ok_or_else(invalid)?;
+        let hour: u32 = hour.parse().map_err(|_| invalid())?;
+        let minute: u32 = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay { hour, minute })
+    }
+}
+
+// serialized as a string, so the schema can't be derived from the fields
+impl PartialSchema for TimeOfDay {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .pattern(Some("^([01][0-9]|2[0-3]):[0-5][0-9]$"))
+            .examples(["08:00"])
+            .into()
+    }
+}
+
+impl ToSchema for TimeOfDay {}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum Weekday {
+    #[serde(alias = "mon")]
+    Mon,
+    #[serde(alias = "tue")]
+    Tue,
+    #[serde(alias = "wed")]
+    Wed,
+    #[serde(alias = "thu")]
+    Thu,
+    #[serde(alias = "fri")]
+    Fri,
+    #[serde(alias = "sat")]
+    Sat,
+    #[serde(alias = "sun")]
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    fn from_cron(value: &str) -> Option<Weekday> {
+        // cron counts sunday as day 1
+        match value.to_lowercase().as_str() {
+            "1" | "sun" => Some(Weekday::Sun),
+            "2" | "mon" => Some(Weekday::Mon),
+            "3" | "tue" => Some(Weekday::Tue),
+            "4" | "wed" => Some(Weekday::Wed),
+            "5" | "thu" => Some(Weekday::Thu),
+            "6" | "fri" => Some(Weekday::Fri),
+            "7" | "sat" => Some(Weekday::Sat),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Weekday {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// human friendly alternative to writing a raw cron expression
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleDefinition {
+    // every 8 hours starting 06:00
+    Interval {
+        hours: u32,
+        start: TimeOfDay,
+    },
+    // twice daily at 08:00 and 20:00
+    Daily {
+        times: Vec<TimeOfDay>,
+    },
+    // Mon/Wed/Fri at 09:00
+    Weekly {
+        days: Vec<Weekday>,
+        time: TimeOfDay,
+    },
+    // every other day at 09:00. cron counts days within the month and would restart on the
+    // 1st, so the cron fires daily and a dose is due every `days` days counted from start_date
+    EveryNDays {
+        days: u32,
+        time: TimeOfDay,
+        start_date: NaiveDate,
+    },
+}
+
+impl ScheduleDefinition {
+    /// Compiles the definition into the 6 field (with seconds) cron format used by schedules
+    pub fn to_cron(&self) -> Result<String, String> {
+        let cron = match self {
+            ScheduleDefinition::Interval { hours, start } => {
+                if *hours == 0 || 24 % hours != 0 {
+                    return Err("interval hours must evenly divide 24".to_string());
+                }
+                // start from the earliest hour of the day that lands on the interval
+                format!("0 {} {}/{} * * *", start.minute, start.hour % hours, hours)
+            }
+            ScheduleDefinition::Daily { times } => {
+                let first = times.first().ok_or("at least one time is required")?;
+                if times.iter().any(|t| t.minute != first.minute) {
+                    return Err("all daily times must share the same minute".to_string());
+                }
+                let mut hours: Vec<u32> = times.iter().map(|t| t.hour).collect();
+                hours.sort_unstable();
+                hours.dedup();
+                format!("0 {} {} * * *", first.minute, join(&hours))
+            }
+            ScheduleDefinition::Weekly { days, time } => {
+                if days.is_empty() {
+                    return Err("at least one day is required".to_string());
+                }
+                let days: Vec<Weekday> = Weekday::ALL
+                    .into_iter()
+                    .filter(|d| days.contains(d))
+                    .collect();
+                format!("0 {} {} * * {}", time.minute, time.hour, join(&days))
+            }
+            ScheduleDefinition::EveryNDays { days, time, .. } => {
+                if *days == 0 || *days > 365 {
+                    return Err("days must be between 1 and 365".to_string());
+                }
+                format!("0 {} {} * * *", time.minute, time.hour)
+            }
+        };
+
+        match validate_cron_expression(cron.clone()) {
+            true => Ok(cron),
+            false => Err(format!("definition compiled to invalid cron \"{}\"", cron)),
+        }
+    }
+}
+
+impl ScheduleDefinition {
+    /// The definition a schedule was made from, if it was made from one
+    pub fn of(schedule: &schedule::Model) -> Option<ScheduleDefinition> {
+        // only ever written from a ScheduleDefinition
+        schedule
+            .definition
+            .clone()
+            .and_then(|json| serde_json::from_value(json).ok())
+    }
+
+    /// Recovers a definition from a cron expression in one of the shapes `to_cron` produces
+    pub fn from_cron(cron: &str) -> Option<ScheduleDefinition> {
+        let fields: Vec<&str> = cron.split_whitespace().collect();
+        if fields.len() != 6 || fields[0] != "0" || fields[4] != "*" {
+            return None;
+        }
+        let minute: u32 = fields[1].parse().ok()?;
+        let (hour, dom, dow) = (fields[2], fields[3], fields[5]);
+        let at = |hour: u32| -> Option<TimeOfDay> {
+            TimeOfDay::try_from(format!("{}:{}", hour, minute)).ok()
+        };
+
+        let definition = match (dom, dow) {
+            ("*", "*") => match hour.split_once('/') {
+                Some((start, hours)) => Some(ScheduleDefinition::Interval {
+                    hours: hours.parse().ok()?,
+                    start: at(start.parse().ok()?)?,
+                }),
+                None => Some(ScheduleDefinition::Daily {
+                    times: numbers(hour)?
+                        .into_iter()
+                        .map(at)
+                        .collect::<Option<Vec<TimeOfDay>>>()?,
+                }),
+            },
+            ("*", _) => Some(ScheduleDefinition::Weekly {
+                days: dow
+                    .split(',')
+                    .map(Weekday::from_cron)
+                    .collect::<Option<Vec<Weekday>>>()?,
+                time: at(hour.parse().ok()?)?,
+            }),
+            _ => None,
+        };
+        // reject shapes that parse but could not have been compiled, like a 0 hour interval
+        definition.filter(|d| d.to_cron().is_ok())
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn list(values: &[String]) -> String {
+    match values {
+        [] => String::new(),
+        [one] => one.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+fn numbers(field: &str) -> Option<Vec<u32>> {
+    field.split(',').map(|v| v.parse().ok()).collect()
+}
+
+/// Describes a schedule, by its definition when the cron alone doesn't tell when doses are due
+pub fn describe_schedule(schedule: &schedule::Model) -> String {
+    match ScheduleDefinition::of(schedule) {
+        Some(ScheduleDefinition::EveryNDays {
+            days,
+            time,
+            start_date,
+        }) => {
+            let every = match days {
+                1 => "every day".to_string(),
+                2 => "every other day".to_string(),
+                days => format!("every {} days", days),
+            };
+            format!("{} at {} starting {}", every, time, start_date)
+        }
+        _ => describe(&schedule.cron),
+    }
+}
+
+/// Returns a human readable description of a cron expression, e.g.
+/// "0 0 8,20 * * *" becomes "every day at 08:00 and 20:00"
+pub fn describe(cron: &str) -> String {
+    let fields: Vec<&str> = cron.split_whitespace().collect();
+    if fields.len() < 6 || fields.len() > 7 || fields[0] != "0" {
+        return format!("custom schedule ({})", cron);
+    }
+    let (minute, hour, dom, month, dow) = (fields[1], fields[2], fields[3], fields[4], fields[5]);
+
+    let time = match (numbers(minute), numbers(hour)) {
+        (Some(m), Some(h)) if m.len() == 1 => {
+            let times: Vec<String> = h
+                .iter()
+                .map(|h| {
+                    TimeOfDay {
+                        hour: *h,
+                        minute: m[0],
+                    }
+                    .to_string()
+                })
+                .collect();
+            Some(format!("at {}", list(&times)))
+        }
+        (Some(m), None) if m.len() == 1 => match hour.split_once('/') {
+            Some((start, step)) => {
+                let start = if start == "*" { "0" } else { start };
+                match (start.parse(), step.parse::<u32>()) {
+                    (Ok(start), Ok(step)) => Some(format!(
+                        "every {} hours starting {}",
+                        step,
+                        TimeOfDay {
+                            hour: start,
+                            minute: m[0]
+                        }
+                    )),
+                    _ => None,
+                }
+            }
+            None if hour == "*" => Some(format!("every hour at minute {}", m[0])),
+            None => None,
+        },
+        _ => None,
+    };
+
+    let days = match (dom, dow) {
+        ("*", "*") | ("?", "*") | ("*", "?") => Some("every day".to_string()),
+        (_, "*") | (_, "?") => {
+            // a step restarts every month, so it's listed as the days it lands on
+            let days = match dom.split_once('/') {
+                Some((start, step)) => {
+                    let start = if start == "*" { "1" } else { start };
+                    match (start.parse::<u32>(), step.parse::<usize>()) {
+                        (Ok(start), Ok(step)) if start >= 1 && step > 0 => {
+                            Some((start..=31).step_by(step).collect())
+                        }
+                        _ => None,
+                    }
+                }
+                None => numbers(dom),
+            };
+            days.map(|d| {
+                let days: Vec<String> = d.iter().map(|d| d.to_string()).collect();
+                format!("on day {} of the month", list(&days))
+            })
+        }
+        ("*", _) | ("?", _) => dow
+            .split(',')
+            .map(Weekday::from_cron)
+            .collect::<Option<Vec<Weekday>>>()
+            .map(|d| {
+                let days: Vec<String> = d.iter().map(|d| d.to_string()).collect();
+                format!("on {}", list(&days))
+            }),
+        _ => None,
+    };
+
+    match (days, time) {
+        // "every day every 8 hours" reads better as just "every 8 hours"
+        (Some(days), Some(time)) if days == "every day" && time.starts_with("every") => {
+            if month == "*" {
+                time
+            } else {
+                format!("{} in month {}", time, month)
+            }
+        }
+        (Some(days), Some(time)) if month == "*" => format!("{} {}", days, time),
+        (Some(days), Some(time)) => format!("{} {} in month {}", days, time, month),
+        _ => format!("custom schedule ({})", cron),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use sea_orm::prelude::Uuid;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> TimeOfDay {
+        TimeOfDay { hour, minute }
+    }
+
+    #[test]
+    fn compiles_definitions() {
+        let interval = ScheduleDefinition::Interval {
+            hours: 8,
+            start: at(6, 30),
+        };
+        assert_eq!(interval.to_cron().unwrap(), "0 30 6/8 * * *");
+
+        let daily = ScheduleDefinition::Daily {
+            times: vec![at(20, 0), at(8, 0), at(8, 0)],
+        };
+        assert_eq!(daily.to_cron().unwrap(), "0 0 8,20 * * *");
+
+        let weekly = ScheduleDefinition::Weekly {
+            days: vec![Weekday::Fri, Weekday::Mon, Weekday::Wed],
+            time: at(9, 0),
+        };
+        assert_eq!(weekly.to_cron().unwrap(), "0 0 9 * * Mon,Wed,Fri");
+    }
+
+    #[test]
+    fn rejects_definitions_cron_cant_express() {
+        let uneven = ScheduleDefinition::Interval {
+            hours: 7,
+            start: at(0, 0),
+        };
+        assert!(uneven.to_cron().is_err());
+        let minutes = ScheduleDefinition::Daily {
+            times: vec![at(8, 0), at(20, 30)],
+        };
+        assert!(minutes.to_cron().is_err());
+        assert!(ScheduleDefinition::Daily { times: vec![] }
+            .to_cron()
+            .is_err());
+        let no_days = ScheduleDefinition::Weekly {
+            days: vec![],
+            time: at(9, 0),
+        };
+        assert!(no_days.to_cron().is_err());
+    }
+
+    #[test]
+    fn every_n_days_is_anchored_to_its_start_date() {
+        let json = r#"{"type":"every_n_days","days":2,"time":"09:00","start_date":"2026-10-20"}"#;
+        let definition: ScheduleDefinition = serde_json::from_str(json).unwrap();
+        // the interval is kept in the definition, the cron only gives the time of day
+        assert_eq!(definition.to_cron().unwrap(), "0 0 9 * * *");
+        let zero = r#"{"type":"every_n_days","days":0,"time":"09:00","start_date":"2026-10-20"}"#;
+        let zero: ScheduleDefinition = serde_json::from_str(zero).unwrap();
+        assert!(zero.to_cron().is_err());
+        let no_start = r#"{"type":"every_n_days","days":2,"time":"09:00"}"#;
+        assert!(serde_json::from_str::<ScheduleDefinition>(no_start).is_err());
+        // a raw day of month step isn't taken for "every n days"
+        assert_eq!(ScheduleDefinition::from_cron("0 0 9 1/3 * *"), None);
+
+        let schedule = schedule::Model {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            drug_name: "Ibuprofen".to_string(),
+            pill_count: 20,
+            pill_amount: 2,
+            cron: definition.to_cron().unwrap(),
+            definition: Some(serde_json::to_value(&definition).unwrap()),
+            added_at: Utc::now(),
+            updated_by: None,
+            version: 1,
+            product_appl_no: None,
+            product_no: None,
+        };
+        assert_eq!(
+            describe_schedule(&schedule),
+            "every other day at 09:00 starting 2026-10-20"
+        );
+    }
+
+    #[test]
+    fn recovers_compiled_definitions() {
+        let definitions = [
+            ScheduleDefinition::Interval {
+                hours: 6,
+                start: at(2, 15),
+            },
+            ScheduleDefinition::Daily {
+                times: vec![at(8, 0), at(20, 0)],
+            },
+            ScheduleDefinition::Weekly {
+                days: vec![Weekday::Tue, Weekday::Sun],
+                time: at(7, 45),
+            },
+        ];
+        for definition in definitions {
+            let cron = definition.to_cron().unwrap();
+            let recovered = ScheduleDefinition::from_cron(&cron).unwrap();
+            assert_eq!(recovered.to_cron().unwrap(), cron);
+        }
+        assert_eq!(ScheduleDefinition::from_cron("0 0 0/0 * * *"), None);
+        assert_eq!(ScheduleDefinition::from_cron("not a cron"), None);
+    }
+
+    #[test]
+    fn describes_crons() {
+        assert_eq!(describe("0 0 8,20 * * *"), "every day at 08:00 and 20:00");
+        assert_eq!(describe("0 30 6/8 * * *"), "every 8 hours starting 06:30");
+        assert_eq!(
+            describe("0 0 9 * * Mon,Wed,Fri"),
+            "on Mon, Wed and Fri at 09:00"
+        );
+        assert_eq!(describe("0 15 * * * *"), "every hour at minute 15");
+        assert_eq!(
+            describe("0 0 9 1,15 * *"),
+            "on day 1 and 15 of the month at 09:00"
+        );
+        assert_eq!(describe("0 0 9 * 6 *"), "every day at 09:00 in month 6");
+        assert_eq!(describe("*/5 * * * * *"), "custom schedule (*/5 * * * * *)");
+    }
+
+    #[test]
+    fn describes_day_of_month_steps_by_the_days_they_land_on() {
+        assert_eq!(
+            describe("0 0 9 1/10 * *"),
+            "on day 1, 11, 21 and 31 of the month at 09:00"
+        );
+        assert_eq!(
+            describe("0 0 9 */15 * *"),
+            "on day 1, 16 and 31 of the month at 09:00"
+        );
+    }
+}
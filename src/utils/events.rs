@@ -0,0 +1,259 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use actix_web::{rt, web, Error};
+use entity::session;
+use futures::{stream, Stream};
+use log::{error, info, warn};
+use sea_orm::prelude::Uuid;
+use sea_orm::{ConnectionTrait, DatabaseConnection, EntityTrait, Statement};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::models::dto;
+use crate::models::error::ApiError;
+
+// the postgres channel every instance announces changes on and listens to
+const CHANNEL: &str = "drug_data_events";
+// postgres refuses notifications of 8000 bytes or more
+const MAX_PAYLOAD: usize = 7999;
+// notified instead of an event too large to send, even as a resync for its audience.
+// every stream resyncs
+const RESYNC_ALL: &str = "resync";
+// events a slow stream can fall behind by before it's told to resync
+const BUFFER: usize = 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// comments sent while nothing happens, so proxies keep the stream open and dead ones are noticed.
+// the session of a stream is checked as often
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+const HEARTBEAT: &[u8] = b": keep-alive\n\n";
+// events may have been missed, clients should fetch what they show again
+const RESYNC: &[u8] = b"data: {\"type\":\"resync\"}\n\n";
+
+// what goes through postgres, the event with the users allowed to see it
+#[derive(Serialize, Deserialize)]
+struct Notification<E> {
+    audience: Vec<Uuid>,
+    event: E,
+}
+
+#[derive(Clone, Debug)]
+enum Message {
+    Event {
+        audience: Arc<Vec<Uuid>>,
+        data: Arc<str>,
+    },
+    Resync,
+}
+
+// fans the events of every instance out to the streams open on this one
+pub struct EventHub {
+    sender: broadcast::Sender<Message>,
+    heartbeat: Duration,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        EventHub {
+            sender: broadcast::channel(BUFFER).0,
+            heartbeat: HEARTBEAT_INTERVAL,
+        }
+    }
+}
+
+impl EventHub {
+    // listens on a connection of its own for as long as the server runs, reconnecting when it drops
+    pub fn listen(hub: web::Data<EventHub>, db_url: String) {
+        rt::spawn(async move {
+            loop {
+                match hub.receive(&db_url).await {
+                    Ok(()) => warn!("Lost the connection listening for events"),
+                    Err(err) => error!("Failed to listen for events: {:?}", err),
+                }
+                // whatever was sent until the listener is back is lost
+                let _ = hub.sender.send(Message::Resync);
+                rt::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+
+    async fn receive(&self, db_url: &str) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect(db_url).await?;
+        listener.listen(CHANNEL).await?;
+        info!("Listening for events");
+        while let Some(notification) = listener.try_recv().await? {
+            if notification.payload() == RESYNC_ALL {
+                let _ = self.sender.send(Message::Resync);
+                continue;
+            }
+            let notification: Notification<Value> =
+                match serde_json::from_str(notification.payload()) {
+                    Ok(notification) => notification,
+                    Err(err) => {
+                        error!("Failed to read event: {:?}", err);
+                        continue;
+                    }
+                };
+            // fails when no stream is open, which is fine
+            let _ = self.sender.send(Message::Event {
+                audience: Arc::new(notification.audience),
+                data: notification.event.to_string().into(),
+            });
+        }
+        Ok(())
+    }
+
+    // the events for a user as server-sent events, until the stream is dropped, the session
+    // ends or the given time, when the token it was opened with expires
+    pub fn stream(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        db: DatabaseConnection,
+        until: rt::time::Instant,
+    ) -> impl Stream<Item = Result<Bytes, Error>> {
+        let receiver = self.sender.subscribe();
+        let heartbeat = rt::time::interval(self.heartbeat);
+        stream::unfold(
+            (receiver, heartbeat, db),
+            move |(mut receiver, mut heartbeat, db)| async move {
+                let chunk = loop {
+                    tokio::select! {
+                        _ = rt::time::sleep_until(until) => return None,
+                        _ = heartbeat.tick() => match session_exists(&db, session_id).await {
+                            true => break Bytes::from_static(HEARTBEAT),
+                            false => return None,
+                        },
+                        message = receiver.recv() => match message {
+                            Ok(Message::Event { audience, data }) if audience.contains(&user_id) => {
+                                break Bytes::from(format!("data: {}\n\n", data));
+                            }
+                            Ok(Message::Event { .. }) => continue,
+                            Ok(Message::Resync) | Err(RecvError::Lagged(_)) => {
+                                break Bytes::from_static(RESYNC);
+                            }
+                            Err(RecvError::Closed) => return None,
+                        },
+                    }
+                };
+                Some((Ok(chunk), (receiver, heartbeat, db)))
+            },
+        )
+    }
+}
+
+// sessions are deleted on logout and when revoked. a failed lookup keeps the stream open
+async fn session_exists(db: &DatabaseConnection, session_id: Uuid) -> bool {
+    match session::Entity::find_by_id(session_id).one(db).await {
+        Ok(session) => session.is_some(),
+        Err(err) => {
+            warn!(
+                "Failed to look up the session of an event stream: {:?}",
+                err
+            );
+            true
+        }
+    }
+}
+
+// announces an event to every instance. postgres only sends it when the transaction it was
+// published in commits, so changes that are rolled back are never announced
+pub async fn publish<C: ConnectionTrait>(
+    db: &C,
+    audience: &[Uuid],
+    event: &dto::Event,
+) -> Result<(), ApiError> {
+    let payload = payload(audience, event)?;
+    let backend = db.get_database_backend();
+    db.execute(Statement::from_sql_and_values(
+        backend,
+        "SELECT pg_notify($1, $2)",
+        vec![CHANNEL.into(), payload.into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+// the notification for an event, or a resync when it's too large for postgres
+fn payload<E: Serialize>(audience: &[Uuid], event: &E) -> Result<String, ApiError> {
+    let payload = notification(audience, event)?;
+    if payload.len() <= MAX_PAYLOAD {
+        return Ok(payload);
+    }
+    warn!(
+        "An event of {} bytes is too large to notify, sending a resync",
+        payload.len()
+    );
+    let resync = notification(audience, &json!({ "type": "resync" }))?;
+    Ok(match resync.len() <= MAX_PAYLOAD {
+        true => resync,
+        false => RESYNC_ALL.to_string(),
+    })
+}
+
+fn notification<E: Serialize>(audience: &[Uuid], event: &E) -> Result<String, ApiError> {
+    serde_json::to_string(&Notification {
+        audience: audience.to_vec(),
+        event,
+    })
+    .map_err(ApiError::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn large_events_are_notified_as_a_resync() {
+        let audience = vec![Uuid::new_v4()];
+        let event = json!({ "type": "schedule.updated" });
+        assert!(payload(&audience, &event)
+            .unwrap()
+            .contains("schedule.updated"));
+
+        let event = json!({ "type": "schedule.updated", "drug_name": "x".repeat(MAX_PAYLOAD) });
+        let resync: Notification<Value> =
+            serde_json::from_str(&payload(&audience, &event).unwrap()).unwrap();
+        assert_eq!(resync.audience, audience);
+        assert_eq!(resync.event, json!({ "type": "resync" }));
+
+        let audience: Vec<Uuid> = (0..MAX_PAYLOAD / 36).map(|_| Uuid::new_v4()).collect();
+        assert_eq!(payload(&audience, &event).unwrap(), RESYNC_ALL);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn streams_end_with_their_session() {
+        let db = test_utils::database().await;
+        let user = test_utils::create_user(&db, test_utils::PASSWORD).await;
+        let session = user
+            .new_login_session(session::Device::default(), &db)
+            .await
+            .unwrap();
+        let hub = EventHub {
+            heartbeat: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let until = rt::time::Instant::now() + Duration::from_secs(60);
+        let mut stream = Box::pin(hub.stream(user.id, session.session_id, db.clone(), until));
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(chunk, Bytes::from_static(HEARTBEAT));
+
+        session::Entity::delete_by_id(session.session_id)
+            .exec(&db)
+            .await
+            .unwrap();
+        let ended = rt::time::timeout(Duration::from_secs(5), async {
+            while stream.next().await.is_some() {}
+        })
+        .await;
+        assert!(ended.is_ok());
+    }
+}
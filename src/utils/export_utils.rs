@@ -0,0 +1,143 @@
+use std::io::{Cursor, Write};
+
+use chrono::{DateTime, Utc};
+use entity::accounting_entry::EntryKind;
+use entity::{accounting_entry, schedule, session, user};
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde::Serialize;
+use serde_json::Value;
+use zip::write::FileOptions;
+
+// exports with more rows than this are built by a background job
+pub const BACKGROUND_EXPORT_ROWS: usize = 5_000;
+
+// 2 split the logged doses out of accounting_entries into doses
+const EXPORT_FORMAT_VERSION: u32 = 2;
+
+pub struct ExportData {
+    pub user: user::Model,
+    pub sessions: Vec<session::Model>,
+    pub schedules: Vec<schedule::Model>,
+    // the initial counts and corrections
+    pub accounting_entries: Vec<accounting_entry::Model>,
+    // the doses taken or missed
+    pub doses: Vec<accounting_entry::Model>,
+}
+
+impl ExportData {
+    pub async fn collect(db: &DatabaseConnection, user_id: Uuid) -> Result<ExportData, DbErr> {
+        let user = user::Entity::find_by_id(user_id)
+            .one(db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound("user".to_string()))?;
+        let sessions = session::Entity::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .all(db)
+            .await?;
+        let schedules = schedule::Entity::find()
+            .filter(schedule::Column::UserId.eq(user_id))
+            .order_by_asc(schedule::Column::AddedAt)
+            .all(db)
+            .await?;
+        let (doses, accounting_entries) = accounting_entry::Entity::find()
+            .filter(accounting_entry::Column::ScheduleId.is_in(schedules.iter().map(|s| s.id)))
+            .order_by_asc(accounting_entry::Column::Timestamp)
+            .all(db)
+            .await?
+            .into_iter()
+            .partition(|entry| matches!(entry.kind, EntryKind::Dose | EntryKind::Missed));
+
+        Ok(ExportData {
+            user,
+            sessions,
+            schedules,
+            accounting_entries,
+            doses,
+        })
+    }
+
+    // builds a zip holding every table as csv and json, plus a manifest describing them
+    pub fn to_zip(&self) -> anyhow::Result<Vec<u8>> {
+        let tables = vec![
+            ("user", rows(std::slice::from_ref(&self.user))?),
+            ("sessions", rows(&self.sessions)?),
+            ("schedules", rows(&self.schedules)?),
+            ("accounting_entries", rows(&self.accounting_entries)?),
+            ("doses", rows(&self.doses)?),
+        ];
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+        let mut files = vec![];
+        for (name, rows) in &tables {
+            let json_name = format!("{}.json", name);
+            zip.start_file(&json_name, options)?;
+            zip.write_all(&serde_json::to_vec_pretty(rows)?)?;
+
+            let csv_name = format!("{}.csv", name);
+            zip.start_file(&csv_name, options)?;
+            zip.write_all(&to_csv(rows)?)?;
+
+            files.push(ManifestFile {
+                table: name.to_string(),
+                records: rows.len(),
+                json: json_name,
+                csv: csv_name,
+            });
+        }
+
+        let manifest = Manifest {
+            version: EXPORT_FORMAT_VERSION,
+            user_id: self.user.id,
+            generated_at: Utc::now(),
+            files,
+        };
+        zip.start_file("manifest.json", options)?;
+        zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    version: u32,
+    user_id: Uuid,
+    generated_at: DateTime<Utc>,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    table: String,
+    records: usize,
+    json: String,
+    csv: String,
+}
+
+fn rows<T: Serialize>(models: &[T]) -> serde_json::Result<Vec<Value>> {
+    models.iter().map(serde_json::to_value).collect()
+}
+
+// flattens json objects into csv, nested values are written as json text
+fn to_csv(rows: &[Value]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let headers: Vec<String> = match rows.first() {
+        Some(Value::Object(first)) => first.keys().cloned().collect(),
+        _ => vec![],
+    };
+    writer.write_record(&headers)?;
+    for row in rows {
+        let record: Vec<String> = headers
+            .iter()
+            .map(|header| match row.get(header) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+            })
+            .collect();
+        writer.write_record(&record)?;
+    }
+    Ok(writer.into_inner()?)
+}
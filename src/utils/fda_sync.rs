@@ -0,0 +1,88 @@
+use std::sync::Mutex;
+
+use actix_web::{rt, web};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    // no import has run since the server started, the products come from the migration
+    Idle,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub products: Option<usize>,
+    pub error: Option<String>,
+}
+
+// re-imports of the Drugs@FDA products, kept in memory so the status is lost when the server restarts
+pub struct FdaSync {
+    status: Mutex<SyncStatus>,
+}
+
+impl Default for FdaSync {
+    fn default() -> Self {
+        FdaSync {
+            status: Mutex::new(SyncStatus {
+                state: SyncState::Idle,
+                started_at: None,
+                finished_at: None,
+                products: None,
+                error: None,
+            }),
+        }
+    }
+}
+
+impl FdaSync {
+    pub fn status(&self) -> SyncStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // starts an import in the background, returns false if one is already running
+    pub fn start(sync: web::Data<FdaSync>, db: DatabaseConnection) -> bool {
+        {
+            let mut status = sync.status.lock().unwrap();
+            if status.state == SyncState::Running {
+                return false;
+            }
+            *status = SyncStatus {
+                state: SyncState::Running,
+                started_at: Some(Utc::now()),
+                finished_at: None,
+                products: None,
+                error: None,
+            };
+        }
+
+        rt::spawn(async move {
+            info!("Starting FDA product import");
+            let result = migration::fda::import(&db).await;
+            let mut status = sync.status.lock().unwrap();
+            status.finished_at = Some(Utc::now());
+            match result {
+                Ok(products) => {
+                    status.state = SyncState::Succeeded;
+                    status.products = Some(products);
+                }
+                Err(err) => {
+                    error!("FDA product import failed: {:?}", err);
+                    status.state = SyncState::Failed;
+                    status.error = Some(err.to_string());
+                }
+            }
+        });
+        true
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use actix_web::web;
+use entity::user;
+use lazy_static::lazy_static;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::models::error::ApiError;
+
+// how many argon2 hashes may run at once, each one uses 64MiB of memory
+const CONCURRENCY: &str = "PASSWORD_HASH_CONCURRENCY";
+// requests waiting longer than this for a permit are turned away
+const PERMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref PERMITS: Semaphore = Semaphore::new(concurrency());
+}
+
+fn concurrency() -> usize {
+    std::env::var(CONCURRENCY)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(4)
+}
+
+// held while hashing or verifying a password
+async fn permit() -> Result<SemaphorePermit<'static>, ApiError> {
+    match tokio::time::timeout(PERMIT_TIMEOUT, PERMITS.acquire()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        _ => Err(ApiError::Busy),
+    }
+}
+
+// hashes on the blocking pool so it doesn't stall the worker, for saving as the user's password
+pub async fn hash_password(password: String) -> Result<String, ApiError> {
+    let _permit = permit().await?;
+    web::block(move || user::hash_password(&password))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)
+}
+
+// runs the verification on the blocking pool so it doesn't stall the worker
+pub async fn verify_password(user: &user::Model, password: String) -> Result<bool, ApiError> {
+    let _permit = permit().await?;
+    let user = user.clone();
+    web::block(move || user.verify_password(password).unwrap_or(false))
+        .await
+        .map_err(ApiError::internal)
+}
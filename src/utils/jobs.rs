@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use actix_web::rt;
+use actix_web::web;
+use entity::{export_job, idempotency_key, session};
+use log::{error, info};
+use sea_orm::DatabaseConnection;
+
+use crate::utils::login_throttle::LoginThrottle;
+
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const IDEMPOTENCY_KEY_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const THROTTLE_PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const EXPORT_PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// deletes expired sessions every hour for as long as the server runs
+pub fn start_session_purge(db: DatabaseConnection) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(SESSION_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match session::Model::purge_expired(&db).await {
+                Ok(count) => info!("Purged {} expired sessions", count),
+                Err(err) => error!("Failed to purge expired sessions: {:?}", err),
+            }
+        }
+    });
+}
+
+// deletes idempotency keys past their ttl every hour, so saved responses don't pile up
+pub fn start_idempotency_key_purge(db: DatabaseConnection) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(IDEMPOTENCY_KEY_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match idempotency_key::Model::purge_expired(&db).await {
+                Ok(count) => info!("Purged {} expired idempotency keys", count),
+                Err(err) => error!("Failed to purge expired idempotency keys: {:?}", err),
+            }
+        }
+    });
+}
+
+// forgets old login failures so the throttle doesn't grow without bound
+pub fn start_throttle_purge(throttle: web::Data<LoginThrottle>) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(THROTTLE_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let count = throttle.purge();
+            if count > 0 {
+                info!("Forgot {} expired login failures", count);
+            }
+        }
+    });
+}
+
+// deletes background exports past their retention, failed ones included, so the archives
+// don't pile up in the database
+pub fn start_export_purge(db: DatabaseConnection) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(EXPORT_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match export_job::Model::purge_expired(&db).await {
+                Ok(count) => info!("Purged {} expired export jobs", count),
+                Err(err) => error!("Failed to purge expired export jobs: {:?}", err),
+            }
+        }
+    });
+}
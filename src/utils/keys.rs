@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use log::info;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// directory of `{kid}.pub.pem` verification keys and `{kid}.pem` private keys
+const KEYS_DIR: &str = "JWT_KEYS_DIR";
+// kid of the key new tokens are signed with
+const SIGNING_KID: &str = "JWT_SIGNING_KID";
+// kid used for the shared secret when no key directory is configured
+const SECRET_KID: &str = "secret";
+
+// DER prefix of an Ed25519 SubjectPublicKeyInfo, the raw 32 byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+lazy_static! {
+    pub static ref KEYS: KeyStore = KeyStore::from_env().unwrap();
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+struct VerifyingKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+    // shared secrets are never published
+    jwk: Option<Jwk>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+// every key tokens can be verified with, and the one new tokens are signed with.
+// retired keys stay in the set until the tokens they signed have expired
+pub struct KeyStore {
+    signing: SigningKey,
+    verifying: HashMap<String, VerifyingKey>,
+}
+
+impl KeyStore {
+    pub fn from_env() -> anyhow::Result<KeyStore> {
+        match std::env::var(KEYS_DIR) {
+            Ok(dir) => {
+                let kid = std::env::var(SIGNING_KID)
+                    .map_err(|_| anyhow::anyhow!("{} is not set", SIGNING_KID))?;
+                KeyStore::from_dir(Path::new(&dir), &kid)
+            }
+            // HS256 with the shared secret, for local development
+            Err(_) => {
+                let secret = std::env::var("SECRET_KEY")?;
+                let mut verifying = HashMap::new();
+                verifying.insert(
+                    SECRET_KID.to_string(),
+                    VerifyingKey {
+                        algorithm: Algorithm::HS256,
+                        key: DecodingKey::from_secret(secret.as_bytes()),
+                        jwk: None,
+                    },
+                );
+                Ok(KeyStore {
+                    signing: SigningKey {
+                        kid: SECRET_KID.to_string(),
+                        algorithm: Algorithm::HS256,
+                        key: EncodingKey::from_secret(secret.as_bytes()),
+                    },
+                    verifying,
+                })
+            }
+        }
+    }
+
+    pub fn from_dir(dir: &Path, signing_kid: &str) -> anyhow::Result<KeyStore> {
+        let mut verifying = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if let Some(kid) = name.strip_suffix(".pub.pem") {
+                let pem = fs::read(&path)?;
+                let key = load_public_key(kid, &pem)?;
+                info!("Loaded {:?} verification key {}", key.algorithm, kid);
+                verifying.insert(kid.to_string(), key);
+            }
+        }
+
+        let algorithm = match verifying.get(signing_kid) {
+            Some(key) => key.algorithm,
+            None => anyhow::bail!("no public key found for signing key {}", signing_kid),
+        };
+        let private = fs::read(dir.join(format!("{}.pem", signing_kid)))?;
+        let key = match algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&private)?,
+            _ => EncodingKey::from_rsa_pem(&private)?,
+        };
+        info!("Signing tokens with key {}", signing_kid);
+
+        Ok(KeyStore {
+            signing: SigningKey {
+                kid: signing_kid.to_string(),
+                algorithm,
+                key,
+            },
+            verifying,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> jsonwebtoken::errors::Result<String> {
+        let mut header = Header::new(self.signing.algorithm);
+        header.kid = Some(self.signing.kid.clone());
+        jsonwebtoken::encode(&header, claims, &self.signing.key)
+    }
+
+    // picks the verification key from the token's kid, so tokens signed before a rotation stay valid
+    pub fn decode<T: for<'de> Deserialize<'de>>(
+        &self,
+        token: &str,
+    ) -> jsonwebtoken::errors::Result<jsonwebtoken::TokenData<T>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        // tokens issued before kids were added were signed with the shared secret
+        let kid = header.kid.unwrap_or_else(|| SECRET_KID.to_string());
+        let key = self
+            .verifying
+            .get(&kid)
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+        jsonwebtoken::decode::<T>(token, &key.key, &Validation::new(key.algorithm))
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verifying
+                .values()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+// the algorithm is decided by the key type, RSA keys sign with RS256 and Ed25519 keys with EdDSA
+fn load_public_key(kid: &str, pem: &[u8]) -> anyhow::Result<VerifyingKey> {
+    let text = std::str::from_utf8(pem)?;
+    let rsa =
+        RsaPublicKey::from_public_key_pem(text).or_else(|_| RsaPublicKey::from_pkcs1_pem(text));
+    if let Ok(rsa) = rsa {
+        return Ok(VerifyingKey {
+            algorithm: Algorithm::RS256,
+            key: DecodingKey::from_rsa_pem(pem)?,
+            jwk: Some(Jwk {
+                kty: "RSA".to_string(),
+                kid: kid.to_string(),
+                key_use: "sig".to_string(),
+                alg: "RS256".to_string(),
+                crv: None,
+                x: None,
+                n: Some(URL_SAFE_NO_PAD.encode(rsa.n().to_bytes_be())),
+                e: Some(URL_SAFE_NO_PAD.encode(rsa.e().to_bytes_be())),
+            }),
+        });
+    }
+
+    let der = pem::parse(pem)?.contents;
+    if der.len() == ED25519_SPKI_PREFIX.len() + 32 && der.starts_with(&ED25519_SPKI_PREFIX) {
+        return Ok(VerifyingKey {
+            algorithm: Algorithm::EdDSA,
+            key: DecodingKey::from_ed_pem(pem)?,
+            jwk: Some(Jwk {
+                kty: "OKP".to_string(),
+                kid: kid.to_string(),
+                key_use: "sig".to_string(),
+                alg: "EdDSA".to_string(),
+                crv: Some("Ed25519".to_string()),
+                x: Some(URL_SAFE_NO_PAD.encode(&der[ED25519_SPKI_PREFIX.len()..])),
+                n: None,
+                e: None,
+            }),
+        });
+    }
+
+    anyhow::bail!("key {} is neither an RSA nor an Ed25519 public key", kid)
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use entity::user;
+use log::warn;
+use sea_orm::prelude::Uuid;
+
+// failures allowed before each further attempt has to wait
+const USERNAME_FREE_ATTEMPTS: u32 = 5;
+// higher, so users behind a shared address don't lock each other out
+const IP_FREE_ATTEMPTS: u32 = 20;
+const MFA_FREE_ATTEMPTS: u32 = 5;
+// the wait doubles with every failure past the free attempts, up to the maximum
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+// failures are forgotten this long after the last one
+const FAILURE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ThrottleKey {
+    Username(String),
+    Ip(String),
+    Mfa(Uuid),
+}
+
+impl ThrottleKey {
+    // normalized like stored usernames, so the limit can't be dodged by changing the spelling
+    pub fn username(username: &str) -> ThrottleKey {
+        ThrottleKey::Username(user::normalize_username(username))
+    }
+
+    fn free_attempts(&self) -> u32 {
+        match self {
+            ThrottleKey::Username(_) => USERNAME_FREE_ATTEMPTS,
+            ThrottleKey::Ip(_) => IP_FREE_ATTEMPTS,
+            ThrottleKey::Mfa(_) => MFA_FREE_ATTEMPTS,
+        }
+    }
+}
+
+// the keys a password guess counts against. logins and the password confirmations of logged in
+// users share them, so a stolen session doesn't get attempts of its own
+pub fn password_keys(username: &str, ip: Option<String>) -> Vec<ThrottleKey> {
+    let mut keys = vec![ThrottleKey::username(username)];
+    if let Some(ip) = ip {
+        keys.push(ThrottleKey::Ip(ip));
+    }
+    keys
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+// failed login attempts, kept in memory so they are reset when the server restarts
+#[derive(Default)]
+pub struct LoginThrottle {
+    failures: Mutex<HashMap<ThrottleKey, Failures>>,
+}
+
+impl LoginThrottle {
+    // how long until any of the keys may try again, None if they all can now
+    pub fn retry_after(&self, keys: &[ThrottleKey]) -> Option<Duration> {
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap();
+        keys.iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    pub fn record_failure(&self, keys: &[ThrottleKey]) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+            if now - entry.last > FAILURE_WINDOW {
+                entry.count = 0;
+            }
+            entry.count += 1;
+            entry.last = now;
+            if entry.count > key.free_attempts() {
+                let doublings = (entry.count - key.free_attempts() - 1).min(16);
+                let lockout = (BASE_LOCKOUT * 2u32.pow(doublings)).min(MAX_LOCKOUT);
+                entry.locked_until = Some(now + lockout);
+                warn!(
+                    "{:?} locked out for {}s after {} failed attempts",
+                    key,
+                    lockout.as_secs(),
+                    entry.count
+                );
+            }
+        }
+    }
+
+    pub fn clear(&self, key: &ThrottleKey) {
+        self.failures.lock().unwrap().remove(key);
+    }
+
+    // drops failures that have aged out of the window, returns how many were removed
+    pub fn purge(&self) -> usize {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        let before = failures.len();
+        failures.retain(|_, entry| {
+            now - entry.last <= FAILURE_WINDOW
+                || entry.locked_until.is_some_and(|until| until > now)
+        });
+        before - failures.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the lockout after each failure, within the second that passes while the test runs
+    fn lockouts(throttle: &LoginThrottle, key: &ThrottleKey, failures: u32) -> Vec<u64> {
+        (0..failures)
+            .map(|_| {
+                throttle.record_failure(std::slice::from_ref(key));
+                throttle
+                    .retry_after(std::slice::from_ref(key))
+                    .map_or(0, |wait| wait.as_secs() + 1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn usernames_wait_twice_as_long_after_each_failure_past_five() {
+        let throttle = LoginThrottle::default();
+        let key = ThrottleKey::username("Alice@Example.com");
+        assert_eq!(
+            lockouts(&throttle, &key, 10),
+            [0, 0, 0, 0, 0, 1, 2, 4, 8, 16]
+        );
+        // other spellings of the name are the same key
+        assert!(throttle
+            .retry_after(&[ThrottleKey::username("alice@example.com")])
+            .is_some());
+        assert!(throttle
+            .retry_after(&[ThrottleKey::username("bob@example.com")])
+            .is_none());
+    }
+
+    #[test]
+    fn addresses_get_twenty_free_attempts() {
+        let throttle = LoginThrottle::default();
+        let key = ThrottleKey::Ip("192.0.2.7".to_string());
+        let lockouts = lockouts(&throttle, &key, 23);
+        assert!(lockouts[..20].iter().all(|&lockout| lockout == 0));
+        assert_eq!(lockouts[20..], [1, 2, 4]);
+    }
+
+    #[test]
+    fn lockouts_stop_growing_at_the_maximum() {
+        let throttle = LoginThrottle::default();
+        let key = ThrottleKey::username("alice");
+        let lockouts = lockouts(&throttle, &key, 40);
+        assert_eq!(*lockouts.last().unwrap(), MAX_LOCKOUT.as_secs());
+    }
+
+    #[test]
+    fn the_longest_lockout_of_any_key_applies_and_logging_in_clears_the_username() {
+        let throttle = LoginThrottle::default();
+        let keys = password_keys("alice", Some("192.0.2.7".to_string()));
+        for _ in 0..7 {
+            throttle.record_failure(&keys);
+        }
+        // the username is locked, the address has free attempts left
+        let wait = throttle.retry_after(&keys).unwrap();
+        assert!(wait > Duration::from_secs(1) && wait <= Duration::from_secs(2));
+        assert!(throttle.retry_after(&keys[1..]).is_none());
+
+        throttle.clear(&keys[0]);
+        assert!(throttle.retry_after(&keys).is_none());
+    }
+}
@@ -0,0 +1,50 @@
+use actix_web::{rt, web};
+use entity::{email_verification, user};
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::error;
+use sea_orm::DatabaseConnection;
+
+// smtp:// sends in plain text, which is what a local mail sink like mailhog expects,
+// use smtps:// or smtp://host?tls=required for a real server
+const SMTP_URL: &str = "SMTP_URL";
+const MAIL_FROM: &str = "MAIL_FROM";
+// the token is appended to this to build the link in verification mails
+const EMAIL_VERIFICATION_URL: &str = "EMAIL_VERIFICATION_URL";
+
+pub async fn send_mail(to: &str, subject: &str, body: String) -> anyhow::Result<()> {
+    let url = std::env::var(SMTP_URL)?;
+    let from: Mailbox = std::env::var(MAIL_FROM)
+        .unwrap_or_else(|_| "DrugData <noreply@localhost>".to_string())
+        .parse()?;
+    let message = Message::builder()
+        .from(from)
+        .to(to.parse()?)
+        .subject(subject)
+        .body(body)?;
+
+    let mailer = AsyncSmtpTransport::<Tokio1Executor>::from_url(&url)?.build();
+    mailer.send(message).await?;
+    Ok(())
+}
+
+// mails a link proving the user owns their email address username, in the background
+pub fn send_email_verification(user: user::Model, db: web::Data<DatabaseConnection>) {
+    rt::spawn(async move {
+        let token = match email_verification::Model::create(user.id, db.as_ref()).await {
+            Ok(token) => token,
+            Err(err) => return error!("Failed to create email verification: {:?}", err),
+        };
+        let link = match std::env::var(EMAIL_VERIFICATION_URL) {
+            Ok(url) => format!("{}{}", url, token),
+            Err(_) => token,
+        };
+        let body = format!(
+            "Use this link to verify your email address, it expires in a day:\n\n{}",
+            link
+        );
+        if let Err(err) = send_mail(&user.username, "Verify your email address", body).await {
+            error!("Failed to send email verification: {:?}", err);
+        }
+    });
+}
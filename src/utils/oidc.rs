@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::cookie::{Cookie, SameSite};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::info;
+use rand::Rng;
+use sea_orm::prelude::Uuid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// the provider's issuer url, discovery is read from {issuer}/.well-known/openid-configuration
+const ISSUER: &str = "OIDC_ISSUER";
+const CLIENT_ID: &str = "OIDC_CLIENT_ID";
+// left unset for public clients, which rely on PKCE alone
+const CLIENT_SECRET: &str = "OIDC_CLIENT_SECRET";
+// must match a redirect uri registered with the provider, points at /auth/oidc/callback
+const REDIRECT_URI: &str = "OIDC_REDIRECT_URI";
+
+// how long a user has to finish logging in at the provider
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+// allowed clock difference with the provider when checking token times
+const CLOCK_LEEWAY: u64 = 60;
+// holds the state of the login the browser started, so a callback can't be finished in
+// another browser than the one that started it
+pub const STATE_COOKIE: &str = "oidc_state";
+const STATE_COOKIE_PATH: &str = "/auth/oidc";
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+}
+
+impl OidcConfig {
+    // None when single sign-on isn't configured
+    pub fn from_env() -> Option<OidcConfig> {
+        Some(OidcConfig {
+            issuer: std::env::var(ISSUER).ok()?,
+            client_id: std::env::var(CLIENT_ID).ok()?,
+            client_secret: std::env::var(CLIENT_SECRET).ok(),
+            redirect_uri: std::env::var(REDIRECT_URI).ok()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub azp: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    // when the user last logged in at the provider, sent when max_age was asked for
+    pub auth_time: Option<i64>,
+}
+
+// what a login at the provider is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    Login,
+    // a logged in user linking an identity to their account
+    Link(Uuid),
+    // a logged in user confirming a destructive action, the user has to log in at the
+    // provider again rather than reuse a session there
+    Reauth { user_id: Uuid, session_id: Uuid },
+}
+
+struct PendingLogin {
+    verifier: String,
+    nonce: String,
+    purpose: Purpose,
+    started: Instant,
+    // unix time, compared with the auth_time the provider reports
+    started_at: i64,
+}
+
+// a finished login at the provider
+pub struct Completion {
+    pub claims: IdTokenClaims,
+    pub purpose: Purpose,
+}
+
+// OpenID Connect relying party using the authorization code flow with PKCE.
+// pending logins are kept in memory, so logins in progress are lost when the server restarts
+pub struct OidcClient {
+    pub config: Option<OidcConfig>,
+    http: reqwest::Client,
+    discovery: Mutex<Option<Discovery>>,
+    jwks: Mutex<Option<JwkSet>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+fn random_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+impl OidcClient {
+    pub fn from_env() -> OidcClient {
+        let config = OidcConfig::from_env();
+        if let Some(config) = &config {
+            info!("Single sign-on enabled with issuer {}", config.issuer);
+        }
+        OidcClient::new(config)
+    }
+
+    pub fn new(config: Option<OidcConfig>) -> OidcClient {
+        OidcClient {
+            config,
+            http: reqwest::Client::new(),
+            discovery: Mutex::new(None),
+            jwks: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn config(&self) -> anyhow::Result<&OidcConfig> {
+        self.config
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("single sign-on is not configured"))
+    }
+
+    async fn discovery(&self) -> anyhow::Result<Discovery> {
+        if let Some(discovery) = self.discovery.lock().unwrap().clone() {
+            return Ok(discovery);
+        }
+        let config = self.config()?;
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // a document for another issuer would make every token fail validation later
+        if discovery.issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+            anyhow::bail!(
+                "discovery document is for issuer {}, expected {}",
+                discovery.issuer,
+                config.issuer
+            );
+        }
+        *self.discovery.lock().unwrap() = Some(discovery.clone());
+        Ok(discovery)
+    }
+
+    // the provider's signing keys, fetched again when a token names a key we haven't seen
+    async fn decoding_key(&self, kid: &Option<String>) -> anyhow::Result<DecodingKey> {
+        for refresh in [false, true] {
+            let cached = self.jwks.lock().unwrap().clone();
+            let jwks = match (cached, refresh) {
+                (Some(jwks), false) => jwks,
+                _ => {
+                    let discovery = self.discovery().await?;
+                    let jwks: JwkSet = self
+                        .http
+                        .get(&discovery.jwks_uri)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await?;
+                    *self.jwks.lock().unwrap() = Some(jwks.clone());
+                    jwks
+                }
+            };
+            let jwk = match kid {
+                Some(kid) => jwks.find(kid),
+                // a provider with a single key may leave the kid out
+                None if jwks.keys.len() == 1 => jwks.keys.first(),
+                None => None,
+            };
+            if let Some(jwk) = jwk {
+                return Ok(DecodingKey::from_jwk(jwk)?);
+            }
+        }
+        anyhow::bail!("no signing key found for kid {:?}", kid)
+    }
+
+    // starts a login, returns the provider url to send the user to and the cookie to set in
+    // the browser that goes there
+    pub async fn authorization_url(
+        &self,
+        purpose: Purpose,
+    ) -> anyhow::Result<(String, Cookie<'static>)> {
+        let config = self.config()?;
+        let discovery = self.discovery().await?;
+
+        let state = random_token();
+        let nonce = random_token();
+        let verifier = random_token();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.retain(|_, login| login.started.elapsed() < PENDING_LOGIN_TTL);
+            pending.insert(
+                state.clone(),
+                PendingLogin {
+                    verifier,
+                    nonce: nonce.clone(),
+                    purpose,
+                    started: Instant::now(),
+                    started_at: Utc::now().timestamp(),
+                },
+            );
+        }
+
+        let mut url = reqwest::Url::parse(&discovery.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_uri)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        if let Purpose::Reauth { .. } = purpose {
+            url.query_pairs_mut()
+                .append_pair("prompt", "login")
+                .append_pair("max_age", "0");
+        }
+
+        let cookie = Cookie::build(STATE_COOKIE, state)
+            .path(STATE_COOKIE_PATH)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(config.redirect_uri.starts_with("https://"))
+            .max_age(actix_web::cookie::time::Duration::seconds(
+                PENDING_LOGIN_TTL.as_secs() as i64,
+            ))
+            .finish();
+        Ok((url.to_string(), cookie))
+    }
+
+    // clears the state cookie once the callback used it
+    pub fn removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = Cookie::build(STATE_COOKIE, "")
+            .path(STATE_COOKIE_PATH)
+            .finish();
+        cookie.make_removal();
+        cookie
+    }
+
+    // trades the code from the callback for an id token and validates it
+    pub async fn complete(&self, code: &str, state: &str) -> anyhow::Result<Completion> {
+        let config = self.config()?;
+        // the state can only be used once
+        let login = match self.pending.lock().unwrap().remove(state) {
+            Some(login) if login.started.elapsed() < PENDING_LOGIN_TTL => login,
+            _ => anyhow::bail!("unknown or expired login state"),
+        };
+        let discovery = self.discovery().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let tokens: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = self.validate_id_token(&tokens.id_token, &discovery).await?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            anyhow::bail!("id token nonce does not match the login");
+        }
+        // a provider that ignored max_age let the user through on an older login
+        if let Purpose::Reauth { .. } = login.purpose {
+            match claims.auth_time {
+                Some(auth_time) if auth_time + CLOCK_LEEWAY as i64 >= login.started_at => (),
+                _ => anyhow::bail!("id token has no auth_time after the login started"),
+            }
+        }
+        Ok(Completion {
+            claims,
+            purpose: login.purpose,
+        })
+    }
+
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        discovery: &Discovery,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let config = self.config()?;
+        let header = jsonwebtoken::decode_header(id_token)?;
+        // shared secret algorithms would let anyone holding the client secret forge tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            anyhow::bail!("id token uses unsupported algorithm {:?}", header.alg);
+        }
+        let key = self.decoding_key(&header.kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = CLOCK_LEEWAY;
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss", "sub", "aud"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)?.claims;
+
+        // when azp is present it has to name this client
+        if let Some(azp) = &claims.azp {
+            if azp != &config.client_id {
+                anyhow::bail!("id token was issued to {}", azp);
+            }
+        }
+        Ok(claims)
+    }
+}
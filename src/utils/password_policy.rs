@@ -0,0 +1,394 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use sha1::{Digest, Sha1};
+
+use crate::models::error::ApiError;
+use crate::models::response::FieldError;
+
+// NIST SP 800-63B 5.1.1.2, length and a blocklist instead of composition rules
+const MIN_LENGTH: &str = "PASSWORD_MIN_LENGTH";
+const MAX_LENGTH: &str = "PASSWORD_MAX_LENGTH";
+// either a directory of `{prefix}.txt` range files holding `SUFFIX:COUNT` lines,
+// or a single file of `HASH:COUNT` lines, as produced by the pwned passwords downloader
+const BLOCKLIST: &str = "PASSWORD_BLOCKLIST";
+
+const DEFAULT_MIN_LENGTH: usize = 8;
+const DEFAULT_MAX_LENGTH: usize = 64;
+// length of the sha1 prefix a range file covers
+const RANGE_PREFIX_LENGTH: usize = 5;
+// words specific to this service that make a password easy to guess
+const SERVICE_WORDS: [&str; 2] = ["drugdata", "drug_data"];
+// the most common passwords, checked even when no breached password list is configured
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+lazy_static! {
+    pub static ref PASSWORD_POLICY: PasswordPolicy = PasswordPolicy::from_env().unwrap();
+    static ref COMMON: HashSet<&'static str> = COMMON_PASSWORDS.lines().collect();
+}
+
+enum Blocklist {
+    None,
+    Ranges(PathBuf),
+    Hashes(HashSet<String>),
+}
+
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    blocklist: Blocklist,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PasswordViolation {
+    TooShort(usize),
+    TooLong(usize),
+    Common,
+    Breached,
+    ContainsUsername,
+    ContextSpecific,
+    Repetitive,
+    Sequential,
+}
+
+impl PasswordViolation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasswordViolation::TooShort(_) => "too_short",
+            PasswordViolation::TooLong(_) => "too_long",
+            PasswordViolation::Common => "common",
+            PasswordViolation::Breached => "breached",
+            PasswordViolation::ContainsUsername => "contains_username",
+            PasswordViolation::ContextSpecific => "context_specific",
+            PasswordViolation::Repetitive => "repetitive",
+            PasswordViolation::Sequential => "sequential",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            PasswordViolation::TooShort(min) => {
+                format!("Password must be at least {} characters", min)
+            }
+            PasswordViolation::TooLong(max) => {
+                format!("Password must be at most {} characters", max)
+            }
+            PasswordViolation::Common => {
+                "Password is too commonly used, choose a different one".to_string()
+            }
+            PasswordViolation::Breached => {
+                "Password has appeared in a data breach, choose a different one".to_string()
+            }
+            PasswordViolation::ContainsUsername => {
+                "Password must not contain the username".to_string()
+            }
+            PasswordViolation::ContextSpecific => {
+                "Password must not contain the name of the service".to_string()
+            }
+            PasswordViolation::Repetitive => {
+                "Password must not be a single repeated character".to_string()
+            }
+            PasswordViolation::Sequential => {
+                "Password must not be a run of sequential characters like 12345678 or abcdefgh"
+                    .to_string()
+            }
+        }
+    }
+
+    pub fn field_error(&self, field: &str) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            code: self.code().to_string(),
+            message: self.message(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> anyhow::Result<PasswordPolicy> {
+        let length = |name: &str, default: usize| -> anyhow::Result<usize> {
+            match std::env::var(name) {
+                Ok(value) => Ok(value.parse()?),
+                Err(_) => Ok(default),
+            }
+        };
+        let blocklist = match std::env::var(BLOCKLIST) {
+            Ok(path) => Blocklist::load(Path::new(&path))?,
+            Err(_) => {
+                warn!(
+                    "{} is not set, passwords are only checked against a short list of common ones",
+                    BLOCKLIST
+                );
+                Blocklist::None
+            }
+        };
+        Ok(PasswordPolicy {
+            min_length: length(MIN_LENGTH, DEFAULT_MIN_LENGTH)?,
+            max_length: length(MAX_LENGTH, DEFAULT_MAX_LENGTH)?,
+            blocklist,
+        })
+    }
+
+    // returns every rule the password breaks, lengths count unicode characters rather than bytes
+    pub fn validate(&self, password: &str, username: &str) -> Vec<PasswordViolation> {
+        let mut violations = vec![];
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong(self.max_length));
+        }
+
+        let lower = password.to_lowercase();
+        let username = username.trim().to_lowercase();
+        // for email usernames the part before the @ is what people reuse
+        let local_part = username.split('@').next().unwrap_or("");
+        if [username.as_str(), local_part]
+            .iter()
+            .any(|name| name.chars().count() >= 3 && lower.contains(name))
+        {
+            violations.push(PasswordViolation::ContainsUsername);
+        }
+        if SERVICE_WORDS.iter().any(|word| lower.contains(word)) {
+            violations.push(PasswordViolation::ContextSpecific);
+        }
+        let mut chars = password.chars();
+        if let Some(first) = chars.next() {
+            if chars.all(|c| c == first) {
+                violations.push(PasswordViolation::Repetitive);
+            }
+        }
+        if is_sequential(&lower) {
+            violations.push(PasswordViolation::Sequential);
+        }
+
+        if COMMON.contains(lower.as_str()) {
+            violations.push(PasswordViolation::Common);
+        } else if self.blocklist.contains(password) {
+            violations.push(PasswordViolation::Breached);
+        }
+        violations
+    }
+}
+
+// every character one after or one before the last, like 12345678, abcdefgh or 87654321
+fn is_sequential(password: &str) -> bool {
+    let codes: Vec<i64> = password.chars().map(|c| c as i64).collect();
+    let steps: Vec<i64> = codes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    match steps.first() {
+        Some(step) if step.abs() == 1 => steps.iter().all(|s| s == step),
+        _ => false,
+    }
+}
+
+impl Blocklist {
+    fn load(path: &Path) -> anyhow::Result<Blocklist> {
+        if path.is_dir() {
+            info!("Checking passwords against range files in {:?}", path);
+            return Ok(Blocklist::Ranges(path.to_path_buf()));
+        }
+        let hashes: HashSet<String> = fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .map(|hash| hash.trim().to_uppercase())
+            .filter(|hash| !hash.is_empty())
+            .collect();
+        info!("Loaded {} breached password hashes", hashes.len());
+        Ok(Blocklist::Hashes(hashes))
+    }
+
+    fn contains(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        match self {
+            Blocklist::None => false,
+            Blocklist::Hashes(hashes) => hashes.contains(&hash),
+            // only the range sharing the hash's prefix is read, like the k-anonymity api
+            Blocklist::Ranges(dir) => {
+                let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+                let range = dir.join(format!("{}.txt", prefix));
+                match fs::read_to_string(&range) {
+                    Ok(range) => range.lines().any(|line| {
+                        line.split(':')
+                            .next()
+                            .is_some_and(|s| s.trim().eq_ignore_ascii_case(suffix))
+                    }),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+                    Err(err) => {
+                        error!("Failed to read password range {:?}: {:?}", range, err);
+                        false
+                    }
+                }
+            }
+        }
+    }
+}
+
+// a validation error listing every violation, if the password isn't acceptable
+pub fn check_password(field: &str, password: &str, username: &str) -> Result<(), ApiError> {
+    let violations = PASSWORD_POLICY.validate(password, username);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::Validation(
+        violations.iter().map(|v| v.field_error(field)).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::prelude::Uuid;
+
+    use super::*;
+
+    const USERNAME: &str = "Alice.Smith@example.com";
+
+    fn policy(blocklist: Blocklist) -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: DEFAULT_MIN_LENGTH,
+            max_length: DEFAULT_MAX_LENGTH,
+            blocklist,
+        }
+    }
+
+    fn sha1(password: &str) -> String {
+        hex::encode_upper(Sha1::digest(password.as_bytes()))
+    }
+
+    // a password of the given number of characters that breaks no other rule
+    fn of_length(length: usize, alphabet: &str) -> String {
+        alphabet.chars().cycle().take(length).collect()
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        let policy = policy(Blocklist::None);
+        let accented = "éàüöñçøåß";
+        // 7 characters are too short however many bytes they take
+        let short = of_length(7, accented);
+        assert!(short.len() > DEFAULT_MIN_LENGTH);
+        assert_eq!(
+            policy.validate(&short, USERNAME),
+            [PasswordViolation::TooShort(8)]
+        );
+        // and 64 are fine although they take more than 64 bytes
+        let long = of_length(64, accented);
+        assert!(long.len() > DEFAULT_MAX_LENGTH);
+        assert!(policy.validate(&long, USERNAME).is_empty());
+    }
+
+    #[test]
+    fn passwords_are_between_8_and_64_characters() {
+        let policy = policy(Blocklist::None);
+        let alphabet = "tulip garden ocean 42";
+        for (length, violations) in [
+            (7, vec![PasswordViolation::TooShort(8)]),
+            (8, vec![]),
+            (64, vec![]),
+            (65, vec![PasswordViolation::TooLong(64)]),
+        ] {
+            assert_eq!(
+                policy.validate(&of_length(length, alphabet), USERNAME),
+                violations,
+                "{} characters",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn passwords_cant_contain_the_username_or_the_service() {
+        let policy = policy(Blocklist::None);
+        for password in ["my alice.smith@example.com pw", "xx ALICE.SMITH yy"] {
+            assert_eq!(
+                policy.validate(password, USERNAME),
+                [PasswordViolation::ContainsUsername],
+                "{}",
+                password
+            );
+        }
+        // short usernames would rule out too much
+        assert!(policy.validate("my bo password", "bo").is_empty());
+        assert_eq!(
+            policy.validate("My DrugData password", USERNAME),
+            [PasswordViolation::ContextSpecific]
+        );
+    }
+
+    #[test]
+    fn passwords_cant_be_repetitive_or_sequential() {
+        let policy = policy(Blocklist::None);
+        assert_eq!(
+            policy.validate("zzzzzzzzzz", USERNAME),
+            [PasswordViolation::Repetitive]
+        );
+        for password in ["3456789:;<", "bcdefghijk", "98765432", "LKJIHGFEDC"] {
+            assert_eq!(
+                policy.validate(password, USERNAME),
+                [PasswordViolation::Sequential],
+                "{}",
+                password
+            );
+        }
+        // a run inside a longer password is fine
+        assert!(policy.validate("12345678 tulips", USERNAME).is_empty());
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_without_a_blocklist() {
+        let policy = policy(Blocklist::None);
+        assert_eq!(
+            policy.validate("Password123", USERNAME),
+            [PasswordViolation::Common]
+        );
+        assert_eq!(
+            policy.validate("12345678", USERNAME),
+            [PasswordViolation::Sequential, PasswordViolation::Common]
+        );
+    }
+
+    #[test]
+    fn breached_passwords_are_found_in_a_hash_file() {
+        let path = std::env::temp_dir().join(format!("{}.txt", Uuid::new_v4()));
+        fs::write(
+            &path,
+            format!("{}:42\n{}:7\n", sha1("tulip garden ocean"), sha1("other")),
+        )
+        .unwrap();
+        let policy = policy(Blocklist::load(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            policy.validate("tulip garden ocean", USERNAME),
+            [PasswordViolation::Breached]
+        );
+        assert!(policy.validate("tulip garden river", USERNAME).is_empty());
+    }
+
+    #[test]
+    fn breached_passwords_are_found_in_a_range_directory() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir(&dir).unwrap();
+        let hash = sha1("tulip garden ocean");
+        let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+        fs::write(
+            dir.join(format!("{}.txt", prefix)),
+            format!(
+                "0000000000000000000000000000000000A:1\n{}:42\n",
+                suffix.to_lowercase()
+            ),
+        )
+        .unwrap();
+        let policy = policy(Blocklist::load(&dir).unwrap());
+
+        let breached = policy.validate("tulip garden ocean", USERNAME);
+        // a password whose range file doesn't exist
+        let other = policy.validate("tulip garden river", USERNAME);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(breached, [PasswordViolation::Breached]);
+        assert!(other.is_empty());
+    }
+}
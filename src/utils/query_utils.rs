@@ -0,0 +1,334 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sea_orm::prelude::Uuid;
+use sea_orm::sea_query::IntoCondition;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, IdenStatic, Iterable, ModelTrait,
+    PaginatorTrait, PrimaryKeyToColumn, QueryFilter, QueryOrder, QuerySelect, Select, Value,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::models::error::ApiError;
+use crate::models::response::{ApiVersion, Pagination};
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+// paging and sorting of a list endpoint, filters are taken as a separate query per endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParams {
+    // zero based page to fetch by offset, counts the total too
+    pub page_num: Option<u64>,
+    // 1 to 200, defaults to 50
+    pub page_size: Option<u64>,
+    // next_cursor from the previous page, cheaper than paging by offset
+    pub cursor: Option<String>,
+    // comma separated fields, prefixed with - for descending, e.g. -added_at,drug_name
+    pub sort: Option<String>,
+}
+
+// a column clients may sort by, under the name they use for it
+pub type Sortable<C> = (&'static str, C);
+
+#[derive(Clone, Copy)]
+struct SortKey<C> {
+    column: C,
+    desc: bool,
+}
+
+// the sort key values of the last row of a page, opaque to clients
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    // cursors are only valid for the sort they were made for
+    sort: String,
+    after: Vec<CursorValue>,
+}
+
+// values keep their type, postgres won't compare an int4 column with an int8 parameter
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum CursorValue {
+    Null,
+    Bool(bool),
+    Int(i32),
+    BigInt(i64),
+    String(String),
+    Uuid(Uuid),
+    Timestamp(DateTime<Utc>),
+}
+
+impl CursorValue {
+    fn from_value(value: Value) -> Option<CursorValue> {
+        Some(match value {
+            Value::Bool(Some(value)) => CursorValue::Bool(value),
+            Value::Int(Some(value)) => CursorValue::Int(value),
+            Value::BigInt(Some(value)) => CursorValue::BigInt(value),
+            Value::String(Some(value)) => CursorValue::String(*value),
+            Value::Uuid(Some(value)) => CursorValue::Uuid(*value),
+            Value::ChronoDateTimeUtc(Some(value)) => CursorValue::Timestamp(*value),
+            Value::Bool(None)
+            | Value::Int(None)
+            | Value::BigInt(None)
+            | Value::String(None)
+            | Value::Uuid(None)
+            | Value::ChronoDateTimeUtc(None) => CursorValue::Null,
+            _ => return None,
+        })
+    }
+
+    fn into_value(self) -> Option<Value> {
+        match self {
+            CursorValue::Null => None,
+            CursorValue::Bool(value) => Some(value.into()),
+            CursorValue::Int(value) => Some(value.into()),
+            CursorValue::BigInt(value) => Some(value.into()),
+            CursorValue::String(value) => Some(value.into()),
+            CursorValue::Uuid(value) => Some(value.into()),
+            CursorValue::Timestamp(value) => Some(value.into()),
+        }
+    }
+}
+
+// fetches one page of a query, sorted by the requested fields and then the primary key so
+// the order is total. pages by cursor unless a page_num is given. the legacy routes returned
+// whole lists before paging was added and have no cursors, so they get the largest page by
+// offset unless they ask for less
+pub async fn list<E>(
+    select: Select<E>,
+    params: &ListParams,
+    sortable: &[Sortable<E::Column>],
+    default_sort: &str,
+    version: ApiVersion,
+    db: &DatabaseConnection,
+) -> Result<(Vec<E::Model>, Pagination), ApiError>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+{
+    let (page_num, default_page_size) = match version {
+        ApiVersion::Legacy => (Some(params.page_num.unwrap_or(0)), MAX_PAGE_SIZE),
+        ApiVersion::V1 => (params.page_num, DEFAULT_PAGE_SIZE),
+    };
+    let page_size = params
+        .page_size
+        .unwrap_or(default_page_size)
+        .clamp(1, MAX_PAGE_SIZE);
+    let sort = params.sort.as_deref().unwrap_or(default_sort);
+    let keys = sort_keys::<E>(sort, sortable)?;
+    let select = keys.iter().fold(select, |select, key| match key.desc {
+        true => select.order_by_desc(key.column),
+        false => select.order_by_asc(key.column),
+    });
+
+    if let Some(page_num) = page_num {
+        if params.cursor.is_some() && version == ApiVersion::V1 {
+            return Err(ApiError::invalid(
+                "cursor",
+                "conflicts_with_page_num",
+                "Page either by cursor or by page_num, not both",
+            ));
+        }
+        check_offset(page_num, page_size)?;
+        let paginator = select.paginate(db, page_size as usize);
+        let total = paginator.num_items().await? as u64;
+        let models = paginator.fetch_page(page_num as usize).await?;
+        return Ok((models, Pagination::offset(page_num, page_size, total)));
+    }
+
+    let select = match &params.cursor {
+        Some(cursor) => {
+            let after = decode_cursor(cursor, sort, keys.len())?;
+            match after_condition(&keys, after) {
+                Some(condition) => select.filter(condition),
+                None => return Ok((Vec::new(), Pagination::cursor(page_size, None))),
+            }
+        }
+        None => select,
+    };
+    // one extra row tells whether there is a next page
+    let mut models = select.limit(page_size + 1).all(db).await?;
+    let next_cursor = match models.len() as u64 > page_size {
+        true => {
+            models.truncate(page_size as usize);
+            models
+                .last()
+                .map(|last| encode_cursor(last, sort, &keys))
+                .transpose()?
+        }
+        false => None,
+    };
+    Ok((models, Pagination::cursor(page_size, next_cursor)))
+}
+
+// a contains filter on user input, which would otherwise match % and _ as wildcards. a
+// backslash is the default escape character of LIKE in postgres
+pub fn like_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// postgres takes offsets as a bigint, larger pages can't be fetched and would overflow on the way
+pub fn check_offset(page_num: u64, page_size: u64) -> Result<u64, ApiError> {
+    page_num
+        .checked_mul(page_size)
+        .filter(|offset| *offset <= i64::MAX as u64)
+        .ok_or_else(|| {
+            ApiError::invalid(
+                "page_num",
+                "page_too_large",
+                "page_num is too large for the page_size",
+            )
+        })
+}
+
+fn sort_keys<E: EntityTrait>(
+    sort: &str,
+    sortable: &[Sortable<E::Column>],
+) -> Result<Vec<SortKey<E::Column>>, ApiError> {
+    let mut keys: Vec<SortKey<E::Column>> = Vec::new();
+    for field in sort.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let (name, desc) = match field.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (field, false),
+        };
+        let column = sortable
+            .iter()
+            .find(|(sortable, _)| *sortable == name)
+            .map(|(_, column)| *column)
+            .ok_or_else(|| {
+                let names: Vec<&str> = sortable.iter().map(|(name, _)| *name).collect();
+                ApiError::invalid(
+                    "sort",
+                    "invalid_sort",
+                    format!(
+                        "Can't sort by {}, sort by one of {}",
+                        name,
+                        names.join(", ")
+                    ),
+                )
+            })?;
+        if !has_column::<E>(&keys, column) {
+            keys.push(SortKey { column, desc });
+        }
+    }
+    for column in E::PrimaryKey::iter().map(|key| key.into_column()) {
+        if !has_column::<E>(&keys, column) {
+            keys.push(SortKey {
+                column,
+                desc: false,
+            });
+        }
+    }
+    Ok(keys)
+}
+
+fn has_column<E: EntityTrait>(keys: &[SortKey<E::Column>], column: E::Column) -> bool {
+    keys.iter()
+        .any(|key| key.column.as_str() == column.as_str())
+}
+
+fn encode_cursor<M: ModelTrait>(
+    model: &M,
+    sort: &str,
+    keys: &[SortKey<<M::Entity as EntityTrait>::Column>],
+) -> Result<String, ApiError> {
+    let after = keys
+        .iter()
+        .map(|key| {
+            CursorValue::from_value(model.get(key.column)).ok_or_else(|| {
+                ApiError::internal(anyhow::anyhow!(
+                    "{} can't be used in a cursor",
+                    key.column.as_str()
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let json = serde_json::to_vec(&Cursor {
+        sort: sort.to_string(),
+        after,
+    })
+    .map_err(ApiError::internal)?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+fn decode_cursor(cursor: &str, sort: &str, keys: usize) -> Result<Vec<Option<Value>>, ApiError> {
+    let invalid = || ApiError::invalid("cursor", "invalid_cursor", "The cursor is invalid");
+    let json = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let cursor: Cursor = serde_json::from_slice(&json).map_err(|_| invalid())?;
+    if cursor.sort != sort || cursor.after.len() != keys {
+        return Err(ApiError::invalid(
+            "cursor",
+            "invalid_cursor",
+            "The cursor was made for a different sort",
+        ));
+    }
+    Ok(cursor
+        .after
+        .into_iter()
+        .map(CursorValue::into_value)
+        .collect())
+}
+
+// rows sorting strictly after the cursor's, compared key by key. postgres sorts nulls as
+// larger than any value, so they come last ascending and first descending
+fn after_condition<C: ColumnTrait>(
+    keys: &[SortKey<C>],
+    after: Vec<Option<Value>>,
+) -> Option<Condition> {
+    let mut condition = Condition::any();
+    let mut branches = 0;
+    for (i, key) in keys.iter().enumerate() {
+        let beyond = match (key.desc, after[i].clone()) {
+            // nothing sorts after a null
+            (false, None) => None,
+            (false, Some(value)) => Some(
+                Condition::any()
+                    .add(key.column.gt(value))
+                    .add(key.column.is_null()),
+            ),
+            (true, None) => Some(key.column.is_not_null().into_condition()),
+            (true, Some(value)) => Some(key.column.lt(value).into_condition()),
+        };
+        if let Some(beyond) = beyond {
+            let equal = keys[..i]
+                .iter()
+                .zip(&after)
+                .fold(Condition::all(), |all, (key, value)| match value {
+                    Some(value) => all.add(key.column.eq(value.clone())),
+                    None => all.add(key.column.is_null()),
+                });
+            condition = condition.add(equal.add(beyond));
+            branches += 1;
+        }
+    }
+    (branches > 0).then_some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_must_fit_a_bigint() {
+        assert_eq!(check_offset(3, 50).unwrap(), 150);
+        assert!(check_offset(u64::MAX, 2).is_err());
+        assert!(check_offset(i64::MAX as u64, 2).is_err());
+        assert!(check_offset(i64::MAX as u64, 1).is_ok());
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(like_escape("ibu"), "ibu");
+        assert_eq!(like_escape("100%"), "100\\%");
+        assert_eq!(like_escape("a_b\\c"), "a\\_b\\\\c");
+    }
+}
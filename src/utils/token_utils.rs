@@ -1,24 +1,165 @@
-use actix_web::web;
-use entity::session::Model;
-use jsonwebtoken::{DecodingKey, TokenData, Validation};
-
-use sea_orm::DatabaseConnection;
-use sea_orm::DbErr;
-
-pub fn decode_token(token: String) -> Result<TokenData<Model>, jsonwebtoken::errors::Error> {
-    let string = std::env::var("SECRET_KEY").unwrap();
-    let key = string.as_bytes();
-    println!("{:?}", key.len());
-    jsonwebtoken::decode::<Model>(
-        &token,
-        &DecodingKey::from_secret(key),
-        &Validation::default(),
-    )
-}
-
-pub async fn verify_token(
-    token_data: &TokenData<Model>,
-    db: &web::Data<DatabaseConnection>,
-) -> Result<Model, DbErr> {
-    entity::user::Model::validate_login_session(&token_data.claims, &db.get_ref()).await
+use chrono::Utc;
+use entity::session::{Claims, ACCESS_TOKEN_TYPE};
+use jsonwebtoken::errors::{Error, ErrorKind};
+use jsonwebtoken::TokenData;
+
+use crate::utils::keys::KEYS;
+use sea_orm::prelude::Uuid;
+use serde::{Deserialize, Serialize};
+
+static DOWNLOAD_LINK_TTL: i64 = 60 * 15; // in seconds
+static MFA_CHALLENGE_TTL: i64 = 60 * 5; // in seconds
+static REAUTH_TTL: i64 = 60 * 5; // in seconds
+
+// every kind of token is signed with the same keys, the typ claim keeps one kind from being
+// accepted as another
+const DOWNLOAD_TOKEN_TYPE: &str = "download";
+const MFA_CHALLENGE_TYPE: &str = "mfa_challenge";
+const REAUTH_TYPE: &str = "reauth";
+
+// claims of a signed link to download a finished export
+#[derive(Serialize, Deserialize)]
+pub struct DownloadClaims {
+    pub typ: String,
+    pub job_id: Uuid,
+    pub exp: i64,
+}
+
+// claims of the token a client trades for a session once it has a second factor.
+#[derive(Serialize, Deserialize)]
+pub struct MfaChallengeClaims {
+    pub typ: String,
+    pub mfa_user_id: Uuid,
+    pub exp: i64,
+}
+
+// claims of the proof that a user without a password just logged in at the identity provider,
+// which confirms a destructive action from the session that asked for it
+#[derive(Serialize, Deserialize)]
+pub struct ReauthClaims {
+    pub typ: String,
+    pub reauth_user_id: Uuid,
+    pub reauth_session_id: Uuid,
+    pub exp: i64,
+}
+
+pub fn encode_token(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+    KEYS.encode(claims)
+}
+
+pub fn decode_token(token: String) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
+    let data = KEYS.decode::<Claims>(&token)?;
+    check_type(&data.claims.typ, ACCESS_TOKEN_TYPE)?;
+    Ok(data)
+}
+
+pub fn encode_download_token(job_id: Uuid) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = DownloadClaims {
+        typ: DOWNLOAD_TOKEN_TYPE.to_string(),
+        job_id,
+        exp: Utc::now().timestamp() + DOWNLOAD_LINK_TTL,
+    };
+    KEYS.encode(&claims)
+}
+
+pub fn decode_download_token(token: &str) -> Result<DownloadClaims, jsonwebtoken::errors::Error> {
+    let claims = KEYS.decode::<DownloadClaims>(token)?.claims;
+    check_type(&claims.typ, DOWNLOAD_TOKEN_TYPE)?;
+    Ok(claims)
+}
+
+pub fn encode_mfa_challenge(user_id: Uuid) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = MfaChallengeClaims {
+        typ: MFA_CHALLENGE_TYPE.to_string(),
+        mfa_user_id: user_id,
+        exp: Utc::now().timestamp() + MFA_CHALLENGE_TTL,
+    };
+    KEYS.encode(&claims)
+}
+
+pub fn decode_mfa_challenge(
+    token: &str,
+) -> Result<MfaChallengeClaims, jsonwebtoken::errors::Error> {
+    let claims = KEYS.decode::<MfaChallengeClaims>(token)?.claims;
+    check_type(&claims.typ, MFA_CHALLENGE_TYPE)?;
+    Ok(claims)
+}
+
+pub fn encode_reauth_token(
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = ReauthClaims {
+        typ: REAUTH_TYPE.to_string(),
+        reauth_user_id: user_id,
+        reauth_session_id: session_id,
+        exp: Utc::now().timestamp() + REAUTH_TTL,
+    };
+    KEYS.encode(&claims)
+}
+
+pub fn decode_reauth_token(token: &str) -> Result<ReauthClaims, jsonwebtoken::errors::Error> {
+    let claims = KEYS.decode::<ReauthClaims>(token)?.claims;
+    check_type(&claims.typ, REAUTH_TYPE)?;
+    Ok(claims)
+}
+
+fn check_type(typ: &str, expected: &str) -> Result<(), Error> {
+    match typ == expected {
+        true => Ok(()),
+        false => Err(ErrorKind::InvalidToken.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // a token with the fields of every kind, so only its typ tells them apart
+    fn token(typ: &str) -> String {
+        crate::test_utils::keys();
+        let id = Uuid::new_v4();
+        KEYS.encode(&json!({
+            "typ": typ,
+            "session_id": id,
+            "user_id": id,
+            "job_id": id,
+            "mfa_user_id": id,
+            "reauth_user_id": id,
+            "reauth_session_id": id,
+            "iat": Utc::now().timestamp(),
+            "exp": Utc::now().timestamp() + 60,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn tokens_are_only_accepted_as_their_own_kind() {
+        assert!(decode_token(token("access")).is_ok());
+        assert!(decode_token(token("download")).is_err());
+        assert!(decode_token(token("mfa_challenge")).is_err());
+
+        assert!(decode_download_token(&token("download")).is_ok());
+        assert!(decode_download_token(&token("access")).is_err());
+
+        assert!(decode_mfa_challenge(&token("mfa_challenge")).is_ok());
+        assert!(decode_mfa_challenge(&token("download")).is_err());
+
+        assert!(decode_reauth_token(&token("reauth")).is_ok());
+        assert!(decode_reauth_token(&token("access")).is_err());
+    }
+
+    #[test]
+    fn encoded_tokens_decode_as_their_kind() {
+        crate::test_utils::keys();
+        let id = Uuid::new_v4();
+        let download = encode_download_token(id).unwrap();
+        assert_eq!(decode_download_token(&download).unwrap().job_id, id);
+        assert!(decode_mfa_challenge(&download).is_err());
+
+        let challenge = encode_mfa_challenge(id).unwrap();
+        assert_eq!(decode_mfa_challenge(&challenge).unwrap().mfa_user_id, id);
+        assert!(decode_download_token(&challenge).is_err());
+    }
 }
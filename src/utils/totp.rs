@@ -0,0 +1,101 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::Rng;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// RFC 6238 defaults, which every authenticator app supports
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+// codes from one step either side are accepted to allow for clock drift
+const ALLOWED_SKEW: i64 = 1;
+const SECRET_LENGTH: usize = 20;
+const NONCE_LENGTH: usize = 12;
+const ISSUER: &str = "DrugData";
+
+// base64 of the 32 byte key secrets are encrypted with
+const ENCRYPTION_KEY: &str = "TOTP_ENCRYPTION_KEY";
+
+lazy_static! {
+    pub static ref SECRET_CIPHER: Aes256Gcm = cipher_from_env().unwrap();
+}
+
+fn cipher_from_env() -> anyhow::Result<Aes256Gcm> {
+    let key = match std::env::var(ENCRYPTION_KEY) {
+        Ok(key) => STANDARD.decode(key)?,
+        // derived from the jwt secret for local development
+        Err(_) => Sha256::digest(std::env::var("SECRET_KEY")?.as_bytes()).to_vec(),
+    };
+    Aes256Gcm::new_from_slice(&key)
+        .map_err(|_| anyhow::anyhow!("{} must be 32 bytes", ENCRYPTION_KEY))
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    rand::thread_rng().gen::<[u8; SECRET_LENGTH]>().to_vec()
+}
+
+pub fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+// the uri authenticator apps read from a qr code
+pub fn provisioning_uri(secret: &[u8], username: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = ISSUER,
+        account = urlencoding::encode(username),
+        secret = encode_secret(secret),
+        digits = DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+// stored as base64 of the nonce followed by the ciphertext
+pub fn encrypt_secret(secret: &[u8]) -> anyhow::Result<String> {
+    let nonce = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
+    let ciphertext = SECRET_CIPHER
+        .encrypt(Nonce::from_slice(&nonce), secret)
+        .map_err(|_| anyhow::anyhow!("failed to encrypt totp secret"))?;
+    Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+pub fn decrypt_secret(stored: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = STANDARD.decode(stored)?;
+    if bytes.len() < NONCE_LENGTH {
+        anyhow::bail!("stored totp secret is too short");
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    SECRET_CIPHER
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt totp secret"))
+}
+
+// RFC 4226 HOTP of the counter, truncated to DIGITS digits
+fn code_at(secret: &[u8], step: i64) -> String {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+// returns the time step the code belongs to, so callers can refuse to accept it twice
+pub fn verify(secret: &[u8], code: &str, unix_time: i64) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = unix_time / STEP_SECONDS;
+    (current - ALLOWED_SKEW..=current + ALLOWED_SKEW).find(|step| code_at(secret, *step) == code)
+}